@@ -0,0 +1,62 @@
+//! Benchmarks turning 4 Mpixels into bytes: the `flat_map` over `Pixel::to_bytes` that call sites
+//! used to write, against `pixels_to_bytes`, `pixels_into_bytes`, and `pixels_to_rgb_bytes`.
+//!
+//! Run with `cargo bench --bench bytes`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{pixels_into_bytes, pixels_to_bytes, pixels_to_rgb_bytes, Pixel};
+
+const RUNS: u32 = 30;
+
+type Convert = fn(Vec<Pixel>) -> Vec<u8>;
+
+/// Runs `convert` on a copy of `img` `RUNS` times, returning the fastest run. The copy is made
+/// outside of the timing, so that the by-value conversions can be measured the same way.
+fn run(img: &[Pixel], convert: Convert) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let img = black_box(img.to_vec());
+            let now = Instant::now();
+            let bytes = black_box(convert(img));
+            let elapsed = now.elapsed();
+            drop(bytes);
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let (width, height) = (2048, 2048);
+    let img = testgen::generate(Pattern::Noise, width, height, 1);
+    let mpixels = img.len() as f64 / 1_000_000.;
+
+    println!("{}x{}, fastest of {} runs", width, height, RUNS);
+    // Each pair converts the same way, first with a flat_map and then with the helper.
+    let benches: [(&str, Convert); 6] = [
+        ("iter().flat_map", |img| {
+            img.iter().flat_map(|p| p.to_bytes()).collect()
+        }),
+        ("pixels_to_bytes", |img| pixels_to_bytes(&img)),
+        ("into_iter().flat_map", |img| {
+            img.into_iter().flat_map(|p| p.to_bytes()).collect()
+        }),
+        ("pixels_into_bytes", pixels_into_bytes),
+        ("iter().flat_map (RGB)", |img| {
+            img.iter().flat_map(|p| p.to_rgb_bytes()).collect()
+        }),
+        ("pixels_to_rgb_bytes", |img| pixels_to_rgb_bytes(&img)),
+    ];
+    for (name, convert) in benches {
+        let dur = run(&img, convert);
+        println!(
+            "  {:<22} {:>9.3} ms  {:>8.1} Mpixels/sec",
+            name,
+            dur.as_secs_f64() * 1000.,
+            mpixels / dur.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Benchmarks `Decoder::decode` on `testgen` images that each lean on different ops: noise (mostly
+//! `QOI_OP_RGB`), gradients (`QOI_OP_DIFF` and `QOI_OP_LUMA`), and the mixed pattern (all ops).
+//! `StreamDecoder::feed_slice` decodes the same images for comparison.
+//!
+//! Images are decoded from a `&[u8]`, so reading is as cheap as it gets and the time is spent in
+//! the op dispatch itself.
+//!
+//! Run with `cargo bench --bench decode`. Add `--features unsafe-perf` to compare the unchecked
+//! writes into the image and lookups into the buffer of previously seen pixels.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qoiparser::stream::{StreamDecoder, StreamDecoderOutput};
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{Decoder, Pixel};
+
+const RUNS: u32 = 30;
+
+/// Decodes `data` with `Decoder`.
+fn decoder(data: &[u8]) -> Vec<Pixel> {
+    Decoder::new().decode(&mut &data[..]).unwrap().1
+}
+
+/// Decodes `data` with a single `StreamDecoder::feed_slice` call, collecting the pixels the same
+/// way `Decoder` does.
+fn stream(data: &[u8]) -> Vec<Pixel> {
+    let mut img = Vec::new();
+    StreamDecoder::new()
+        .feed_slice(data, |out| {
+            if let StreamDecoderOutput::Pixels(it) = out {
+                img.extend(it);
+            }
+        })
+        .unwrap();
+    img
+}
+
+/// Runs `decode` over `data` `RUNS` times, returning the fastest run.
+fn run(data: &[u8], decode: impl Fn(&[u8]) -> Vec<Pixel>) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            black_box(decode(black_box(data)));
+            now.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let (width, height) = (2048, 2048);
+    let mpixels = (width * height) as f64 / 1_000_000.;
+
+    println!("{}x{}, fastest of {} runs", width, height, RUNS);
+    for pattern in [Pattern::Noise, Pattern::Gradient, Pattern::Mixed] {
+        let img = testgen::generate(pattern, width, height, 1);
+        let data = testgen::encode(&img, width, height);
+
+        for (name, decode) in [
+            ("Decoder", decoder as fn(&[u8]) -> Vec<Pixel>),
+            ("Stream", stream),
+        ] {
+            let dur = run(&data, decode);
+            println!(
+                "  {:<10} {:<8} {:>9.3} ms  {:>8.1} Mpixels/sec",
+                format!("{:?}", pattern),
+                name,
+                dur.as_secs_f64() * 1000.,
+                mpixels / dur.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Benchmarks `Encoder::encode_to_vec` on `testgen` images that each lean on different ops: a
+//! solid color (all `QOI_OP_RUN`), gradients (`QOI_OP_DIFF` and `QOI_OP_LUMA`), noise (mostly
+//! `QOI_OP_RGB`), and the mixed pattern (all ops). `Encoder::encode_bytes` encodes the noise image
+//! from RGBA bytes for comparison.
+//!
+//! Run with `cargo bench --bench encode`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{pixels_to_bytes, Channels, Colorspace, Encoder, Header};
+
+const RUNS: u32 = 30;
+
+/// Runs `encode` `RUNS` times, returning the fastest run.
+fn run(encode: impl Fn() -> Vec<u8>) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            black_box(encode());
+            now.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn print(name: &str, dur: Duration, mpixels: f64) {
+    println!(
+        "  {:<16} {:>9.3} ms  {:>8.1} Mpixels/sec",
+        name,
+        dur.as_secs_f64() * 1000.,
+        mpixels / dur.as_secs_f64()
+    );
+}
+
+fn main() {
+    let (width, height) = (2048, 2048);
+    let mpixels = (width * height) as f64 / 1_000_000.;
+    let header = Header::new(width, height, Channels::RGBA, Colorspace::sRGB);
+
+    println!("{}x{}, fastest of {} runs", width, height, RUNS);
+    for pattern in [Pattern::Solid, Pattern::Gradient, Pattern::Noise, Pattern::Mixed] {
+        let img = testgen::generate(pattern, width, height, 1);
+        let dur = run(|| Encoder::new().encode_to_vec(&header, black_box(&img)).unwrap());
+        print(&format!("{:?}", pattern), dur, mpixels);
+    }
+
+    let bytes = pixels_to_bytes(&testgen::generate(Pattern::Noise, width, height, 1));
+    let dur = run(|| {
+        let mut out = Vec::new();
+        Encoder::new()
+            .encode_bytes(&header, black_box(&bytes), &mut out)
+            .unwrap();
+        out
+    });
+    print("Noise (bytes)", dur, mpixels);
+}
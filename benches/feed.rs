@@ -0,0 +1,112 @@
+//! Benchmarks feeding a whole image to `StreamDecoder`, one `feed` call per byte against a single
+//! `feed_slice` or `feed_all` call.
+//!
+//! Both decode `tests/dice.qoi` and a larger synthetic image, and count the decoded pixels so that
+//! nothing can be optimized away. The per-byte loop is written the way a user would write it.
+//!
+//! Run with `cargo bench --bench feed`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qoiparser::stream::{PixelSink, PixelsIter, StreamDecoder, StreamDecoderOutput};
+use qoiparser::testgen::{self, Pattern};
+
+const RUNS: u32 = 20;
+
+/// Hands every pixel in `it` to `black_box`, returning how many there were.
+fn consume(it: PixelsIter) -> u64 {
+    let mut count = 0;
+    for pix in it {
+        black_box(pix);
+        count += 1;
+    }
+    count
+}
+
+/// Decodes `data` with one `feed` call per byte, returning the number of pixels decoded.
+fn feed_bytes(data: &[u8]) -> u64 {
+    let mut sdec = StreamDecoder::new();
+    let mut pixels = 0;
+    for &byte in data {
+        match sdec.feed(byte).unwrap() {
+            StreamDecoderOutput::Pixels(it) => pixels += consume(it),
+            StreamDecoderOutput::Finished => break,
+            _ => {}
+        }
+    }
+    pixels
+}
+
+/// Decodes `data` with a single `feed_slice` call, returning the number of pixels decoded.
+fn feed_slice(data: &[u8]) -> u64 {
+    let mut sdec = StreamDecoder::new();
+    let mut pixels = 0;
+    sdec.feed_slice(data, |out| {
+        if let StreamDecoderOutput::Pixels(it) = out {
+            pixels += consume(it);
+        }
+    })
+    .unwrap();
+    pixels
+}
+
+/// Counts the pixels handed to it by `feed_all`.
+struct Count(u64);
+
+impl PixelSink for Count {
+    fn pixels(&mut self, pixels: PixelsIter) {
+        self.0 += consume(pixels);
+    }
+}
+
+/// Decodes `data` with a single `feed_all` call, returning the number of pixels decoded.
+fn feed_all(data: &[u8]) -> u64 {
+    let mut count = Count(0);
+    StreamDecoder::new().feed_all(data, &mut count).unwrap();
+    count.0
+}
+
+/// Runs `decode` over `data` `RUNS` times, returning the fastest run.
+fn run(data: &[u8], expected: u64, decode: impl Fn(&[u8]) -> u64) -> Duration {
+    // Warm up before timing anything.
+    assert_eq!(decode(black_box(data)), expected);
+
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            black_box(decode(black_box(data)));
+            now.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, data: &[u8], dur: Duration) {
+    let ms = dur.as_micros() as f64 / 1000.;
+    let mbps = data.len() as f64 / dur.as_secs_f64() / 1_000_000.;
+    println!("  {:<10} {:>9.3} ms  {:>8.2} MB/sec", name, ms, mbps);
+}
+
+fn bench(name: &str, data: &[u8], width: u32, height: u32) {
+    let expected = width as u64 * height as u64;
+
+    println!("{} ({}x{}, {} bytes), fastest of {} runs", name, width, height, data.len(), RUNS);
+    let slice = run(data, expected, feed_slice);
+    let bytes = run(data, expected, feed_bytes);
+    let all = run(data, expected, feed_all);
+    report("feed", data, bytes);
+    report("feed_slice", data, slice);
+    report("feed_all", data, all);
+    println!("  speedup    {:>9.2}x (feed_all over feed)", bytes.as_secs_f64() / all.as_secs_f64());
+}
+
+fn main() {
+    let dice = std::fs::read("tests/dice.qoi").unwrap();
+    bench("dice.qoi", &dice, 800, 600);
+
+    let (width, height) = (2048, 2048);
+    let img = testgen::generate(Pattern::Mixed, width, height, 1);
+    let mixed = testgen::encode(&img, width, height);
+    bench("mixed", &mixed, width, height);
+}
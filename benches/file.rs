@@ -0,0 +1,96 @@
+//! Benchmarks decoding the same file several ways: `decode` and `decode_bufread` reading it through a
+//! `BufReader<File>`, and `decode_slice` on its contents, read into memory before timing. The cost
+//! of only parsing is shown by `decode_discard`, which keeps no pixels, and `decode_pipelined`
+//! reads the file on a second thread.
+//!
+//! The files are freshly written, so they are read from the page cache and `decode_pipelined` has
+//! no IO stalls to hide here. Its gain depends on the disk, so time it against your own storage
+//! with the `--pipelined` option of the binary.
+//!
+//! Run with `cargo bench --bench file`.
+
+use std::fs::File;
+use std::hint::black_box;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::stream::decode_pipelined;
+use qoiparser::Decoder;
+
+const RUNS: u32 = 30;
+
+/// Runs `decode` `RUNS` times, returning the fastest run.
+fn run(decode: impl Fn()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            decode();
+            now.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn bench(path: &Path) {
+    let data = std::fs::read(path).unwrap();
+    let open = || BufReader::new(File::open(path).unwrap());
+
+    let benches = [
+        (
+            "decode",
+            run(|| {
+                black_box(Decoder::new().decode(&mut open()).unwrap());
+            }),
+        ),
+        (
+            "decode_bufread",
+            run(|| {
+                black_box(Decoder::new().decode_bufread(&mut open()).unwrap());
+            }),
+        ),
+        (
+            "decode_discard",
+            run(|| {
+                black_box(Decoder::new().decode_discard(&mut open()).unwrap());
+            }),
+        ),
+        (
+            "decode_pipelined",
+            run(|| {
+                black_box(decode_pipelined(path, 256 * 1024).unwrap());
+            }),
+        ),
+        (
+            "decode_slice",
+            run(|| {
+                black_box(Decoder::new().decode_slice(black_box(&data)).unwrap());
+            }),
+        ),
+    ];
+
+    println!("{} ({} bytes)", path.display(), data.len());
+    for (name, dur) in benches {
+        println!(
+            "  {:<16} {:>9.3} ms  {:>8.2} MB/sec",
+            name,
+            dur.as_secs_f64() * 1000.,
+            data.len() as f64 / dur.as_secs_f64() / 1_000_000.
+        );
+    }
+}
+
+fn main() {
+    println!("Fastest of {} runs", RUNS);
+    bench(Path::new("tests/dice.qoi"));
+
+    let (width, height) = (2048, 2048);
+    for pattern in [Pattern::Noise, Pattern::Mixed] {
+        let img = testgen::generate(pattern, width, height, 1);
+        let path = std::env::temp_dir().join(format!("qoi-bench-{:?}.qoi", pattern));
+        std::fs::write(&path, testgen::encode(&img, width, height)).unwrap();
+        bench(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
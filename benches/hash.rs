@@ -0,0 +1,64 @@
+//! Benchmarks `Decoder::hash_pixel`, which runs once for every pixel that isn't part of a run.
+//!
+//! This compares the `Wrapping<u8>` implementation against the same hash written with the
+//! `wrapping_*` methods on `u8`, to check that the abstraction doesn't cost anything.
+//!
+//! Run with `cargo bench --bench hash`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qoiparser::{Decoder, Pixel};
+
+const ITERATIONS: u32 = 10_000_000;
+
+/// The spec hash, using `u8` methods directly instead of `Wrapping`.
+#[inline]
+fn hash_pixel_direct(p: Pixel) -> u8 {
+    p.r.wrapping_mul(3)
+        .wrapping_add(p.g.wrapping_mul(5))
+        .wrapping_add(p.b.wrapping_mul(7))
+        .wrapping_add(p.a.wrapping_mul(11))
+}
+
+/// Hashes `ITERATIONS` different pixels with `hash`, returning the time taken.
+///
+/// The pixels are derived from the loop counter and every hash is folded into the result, so the
+/// compiler can neither constant-fold the hash nor skip any of the calls. `hash` is generic rather
+/// than a function pointer so that it can be inlined, as it is in the decoders.
+fn run(hash: impl Fn(Pixel) -> u8) -> Duration {
+    let now = Instant::now();
+
+    let mut acc = 0u8;
+    for i in 0..black_box(ITERATIONS) {
+        let [r, g, b, a] = i.to_le_bytes();
+        let pix = Pixel::new(r, g ^ a, b, a.wrapping_add(r));
+        acc ^= hash(black_box(pix));
+    }
+    black_box(acc);
+
+    now.elapsed()
+}
+
+fn report(name: &str, dur: Duration) {
+    let ms = dur.as_micros() as f64 / 1000.;
+    let ns = dur.as_nanos() as f64 / ITERATIONS as f64;
+    println!("{:<10} {:>9.3} ms  {:>6.3} ns/hash", name, ms, ns);
+}
+
+fn main() {
+    // Both versions must agree before their timings mean anything.
+    for i in 0..=u16::MAX {
+        let [x, y] = i.to_le_bytes();
+        let pix = Pixel::new(x, y, x ^ y, x.wrapping_add(y));
+        assert_eq!(Decoder::hash_pixel(pix), hash_pixel_direct(pix));
+    }
+
+    // Warm up before timing anything.
+    run(Decoder::hash_pixel);
+    run(hash_pixel_direct);
+
+    println!("{} hashes each", ITERATIONS);
+    report("Wrapping", run(Decoder::hash_pixel));
+    report("direct", run(hash_pixel_direct));
+}
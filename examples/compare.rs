@@ -0,0 +1,129 @@
+//! Decodes the same images with this crate and with the `qoi` and `rapid-qoi` crates, checking
+//! that all three produce identical pixels and printing how fast each one is.
+//!
+//! Run with `cargo run --release --example compare --features compare-crates`.
+//!
+//! The images are `tests/dice.qoi` plus a few `testgen` images, which are written to the temp
+//! folder and read back like any other file. Any disagreement between the decoders stops the run
+//! with the first pixel that differs.
+
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{pixels_into_bytes, Decoder};
+
+const RUNS: u32 = 10;
+
+/// Writes the `testgen` images to the temp folder, returning their paths.
+fn write_testgen_images() -> Vec<PathBuf> {
+    let (width, height) = (1024, 1024);
+
+    Pattern::ALL
+        .iter()
+        .map(|&pattern| {
+            let img = testgen::generate(pattern, width, height, 7);
+            let path = std::env::temp_dir().join(format!("qoi-compare-{:?}.qoi", pattern));
+            std::fs::write(&path, testgen::encode(&img, width, height)).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn decode_qoiparser(data: &[u8]) -> Vec<u8> {
+    let (_, img) = Decoder::new().decode_slice(data).unwrap();
+    pixels_into_bytes(img)
+}
+
+fn decode_qoi(data: &[u8]) -> Vec<u8> {
+    qoi::Decoder::new(data)
+        .unwrap()
+        .with_channels(qoi::Channels::Rgba)
+        .decode_to_vec()
+        .unwrap()
+}
+
+fn decode_rapid_qoi(data: &[u8]) -> Vec<u8> {
+    let mut qoi = rapid_qoi::Qoi::decode_header(data).unwrap();
+    // Decode RGB images to RGBA too, like the other decoders. The ops are the same either way.
+    qoi.colors = rapid_qoi::Colors::SrgbLinA;
+    let mut out = vec![0; qoi.decoded_size()];
+    qoi.decode_skip_header(&data[14..], &mut out).unwrap();
+    out
+}
+
+/// Runs `decode` `RUNS` times, returning the fastest run.
+fn time(data: &[u8], decode: fn(&[u8]) -> Vec<u8>) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            black_box(decode(black_box(data)));
+            now.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+/// Panics with the first pixel that differs between `ours` and `theirs`, as decoded by the crate
+/// named `other`.
+fn check_equal(name: &str, other: &str, width: u32, ours: &[u8], theirs: &[u8]) {
+    if ours == theirs {
+        return;
+    }
+
+    assert_eq!(
+        ours.len(),
+        theirs.len(),
+        "{}: qoiparser and {} disagree on the size",
+        name,
+        other
+    );
+    let i = ours
+        .chunks_exact(4)
+        .zip(theirs.chunks_exact(4))
+        .position(|(a, b)| a != b)
+        .unwrap();
+    panic!(
+        "{}: first differing pixel is {} at ({}, {}): qoiparser {:?}, {} {:?}",
+        name,
+        i,
+        i as u32 % width,
+        i as u32 / width,
+        &ours[i * 4..i * 4 + 4],
+        other,
+        &theirs[i * 4..i * 4 + 4],
+    );
+}
+
+fn main() {
+    let mut paths = vec![PathBuf::from("tests/dice.qoi")];
+    paths.extend(write_testgen_images());
+
+    println!("Mpixels/s, fastest of {} runs", RUNS);
+    println!(
+        "{:<24} {:>12} {:>12} {:>12}",
+        "image", "qoiparser", "qoi", "rapid-qoi"
+    );
+
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let data = std::fs::read(path).unwrap();
+        let header = qoi::decode_header(&data).unwrap();
+        let mpixels = header.width as f64 * header.height as f64 / 1_000_000.;
+
+        let ours = decode_qoiparser(&data);
+        check_equal(&name, "qoi", header.width, &ours, &decode_qoi(&data));
+        check_equal(
+            &name,
+            "rapid-qoi",
+            header.width,
+            &ours,
+            &decode_rapid_qoi(&data),
+        );
+
+        let [ours, qoi, rapid] = [decode_qoiparser, decode_qoi, decode_rapid_qoi]
+            .map(|decode| mpixels / time(&data, decode).as_secs_f64());
+        println!("{:<24} {:>12.1} {:>12.1} {:>12.1}", name, ours, qoi, rapid);
+    }
+}
@@ -0,0 +1,32 @@
+//! Writes a few `testgen` images into each of the folders given on the command line, as seeds for
+//! the fuzzers. Each pattern leans on different ops, so the fuzzers start out covering all of them
+//! instead of only what the regression corpus happens to hold.
+//!
+//! Used by `fuzz/seed_corpus.sh`: `cargo run --example fuzz_seeds -- fuzz/corpus/fuzz_decode`.
+
+use std::path::PathBuf;
+
+use qoiparser::testgen::{self, Pattern};
+
+/// Small sizes, so that the fuzzers can mutate them quickly. The odd ones leave partial runs at
+/// the end of rows and of the image.
+const SIZES: [(u32, u32); 3] = [(1, 1), (8, 8), (31, 7)];
+
+fn main() {
+    let dirs: Vec<PathBuf> = std::env::args_os().skip(1).map(PathBuf::from).collect();
+    if dirs.is_empty() {
+        eprintln!("usage: fuzz_seeds <dir>...");
+        std::process::exit(2);
+    }
+
+    for dir in &dirs {
+        std::fs::create_dir_all(dir).unwrap();
+        for pattern in Pattern::ALL {
+            for (width, height) in SIZES {
+                let img = testgen::generate(pattern, width, height, 1);
+                let name = format!("testgen-{:?}-{}x{}.qoi", pattern, width, height);
+                std::fs::write(dir.join(name), testgen::encode(&img, width, height)).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoiparser::{DecodeLimits, Decoder};
+
+fuzz_target!(|data: &[u8]| {
+    // Keep the limits small so that a random header can't make us allocate gigabytes.
+    let limits = DecodeLimits { max_pixels: 1 << 20 };
+
+    if let Ok((header, img)) = Decoder::with_limits(limits).decode_slice(data) {
+        assert_eq!(img.len() as u64, header.width as u64 * header.height as u64);
+    }
+});
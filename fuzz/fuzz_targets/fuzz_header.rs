@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoiparser::Header;
+
+fuzz_target!(|data: &[u8]| {
+    // Only the first 14 bytes make up the header. Anything past that is ignored.
+    if let Some(header) = data.get(..14) {
+        let header: &[u8; 14] = header.try_into().unwrap();
+        let _ = Header::from_bytes(header);
+    }
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoiparser::interop::ArbitraryImage;
+use qoiparser::stream::{StreamDecoder, StreamDecoderOutput};
+use qoiparser::{Decoder, Encoder};
+
+fuzz_target!(|img: ArbitraryImage| {
+    let encoded = Encoder::new().encode_to_vec(&img.header, &img.pixels).unwrap();
+
+    let (header, decoded) = Decoder::new().strict(true).decode_slice(&encoded).unwrap();
+    assert_eq!(header, img.header);
+    assert!(decoded == img.pixels);
+
+    let mut sdec = StreamDecoder::new();
+    let mut streamed = Vec::new();
+    for &byte in &encoded {
+        match sdec.feed(byte).unwrap() {
+            StreamDecoderOutput::Pixels(it) => streamed.extend(it),
+            StreamDecoderOutput::Finished => break,
+            _ => {}
+        }
+    }
+    assert!(sdec.is_finished());
+    assert!(streamed == img.pixels);
+});
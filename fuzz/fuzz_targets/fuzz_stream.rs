@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoiparser::stream::{StreamDecoder, StreamDecoderOutput};
+
+fuzz_target!(|data: &[u8]| {
+    let mut sdec = StreamDecoder::new();
+
+    for &byte in data {
+        let before = sdec.cur_pix();
+
+        match sdec.feed(byte) {
+            // Any error ends the decode. The decoder must be reset before it is used again.
+            Err(_) => break,
+            Ok(StreamDecoderOutput::Finished) => break,
+            Ok(StreamDecoderOutput::Pixels(it)) => {
+                let count = it.count() as u64;
+                assert!(count > 0);
+                assert_eq!(sdec.cur_pix(), before + count);
+            }
+            Ok(_) => assert_eq!(sdec.cur_pix(), before),
+        }
+
+        if let Some(num_pix) = sdec.num_pix() {
+            assert!(sdec.cur_pix() <= num_pix);
+        }
+    }
+});
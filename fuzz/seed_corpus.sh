@@ -0,0 +1,16 @@
+#!/bin/sh
+# Seeds the fuzzing corpora with the QOI images found in the regression corpus, along with a few
+# generated images (see examples/fuzz_seeds.rs).
+#
+# Run from the root of the repository: ./fuzz/seed_corpus.sh
+set -e
+
+targets="fuzz_header fuzz_decode fuzz_stream fuzz_roundtrip"
+
+for target in $targets; do
+    mkdir -p "fuzz/corpus/$target"
+    cp tests/corpus/*/*.qoi "fuzz/corpus/$target/"
+done
+
+# shellcheck disable=SC2086 # the targets are split into one folder each on purpose
+cargo run --quiet --example fuzz_seeds -- $(printf 'fuzz/corpus/%s ' $targets)
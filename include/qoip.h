@@ -0,0 +1,71 @@
+/*
+ * C interface to the qoiparser streaming decoder. Build the library with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ *
+ * See src/ffi.rs for the full documentation of each function.
+ */
+#ifndef QOIP_H
+#define QOIP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define QOIP_OK 0
+#define QOIP_DONE 1
+#define QOIP_ERR_NULL (-1)
+#define QOIP_ERR_DECODE (-2)
+#define QOIP_ERR_INCOMPLETE (-3)
+#define QOIP_ERR_PANIC (-4)
+
+typedef struct QoipPixel {
+    uint8_t r;
+    uint8_t g;
+    uint8_t b;
+    uint8_t a;
+} QoipPixel;
+
+typedef struct QoipHeader {
+    uint32_t width;
+    uint32_t height;
+    uint8_t channels;
+    uint8_t colorspace;
+} QoipHeader;
+
+typedef struct QoipStream QoipStream;
+
+/* Called with every run of identical pixels: the pixel, how many times it repeats, and the index
+ * of the first of them in the image. */
+typedef void (*qoip_pixel_cb)(void *user, const QoipPixel *run_pixel, uint32_t run_len,
+                              uint64_t start_index);
+
+/* Returns NULL if the decoder couldn't be created. */
+QoipStream *qoip_stream_new(void);
+
+/* Returns QOIP_OK once the chunk is consumed, or QOIP_DONE once the end marker after the last
+ * pixel has been read and checked. A wrong end marker is QOIP_ERR_DECODE. */
+int qoip_stream_feed(QoipStream *handle, const uint8_t *data, size_t len, qoip_pixel_cb cb,
+                     void *user);
+
+/* Bytes of the last fed chunk that were used, end marker included. After QOIP_DONE, the rest of
+ * the chunk belongs to the next image. */
+size_t qoip_stream_consumed(const QoipStream *handle);
+
+/* Returns QOIP_ERR_INCOMPLETE until the header has been fed. */
+int qoip_stream_header(const QoipStream *handle, QoipHeader *out);
+
+/* Ends the current image and resets the decoder for the next one. Returns QOIP_ERR_INCOMPLETE if
+ * the image, end marker included, wasn't fully fed. */
+int qoip_stream_finish(QoipStream *handle);
+
+void qoip_stream_free(QoipStream *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* QOIP_H */
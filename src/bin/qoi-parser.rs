@@ -1,69 +1,253 @@
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, BufWriter, Stdout, Write};
+use std::path::PathBuf;
 use std::time::Instant;
 
 use clap::Parser;
 
-use qoiparser::{Args, Decoder};
-use qoiparser::stream::{StreamDecoderOutput, StreamDecoder};
-use qoiparser::Pixel;
+use qoiparser::{decode_to_file, ChannelOrder, Channels, Decoder};
+use qoiparser::stream::{decode_pipelined, ChunkedDriver, PixelSink, PixelsIter};
+use qoiparser::{Colorspace, Encoder, Header, Pixel};
+
+/// The values of `--channels`.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ChannelsArg {
+    Rgb,
+    Rgba,
+}
+
+impl From<ChannelsArg> for Channels {
+    fn from(arg: ChannelsArg) -> Self {
+        match arg {
+            ChannelsArg::Rgb => Channels::RGB,
+            ChannelsArg::Rgba => Channels::RGBA,
+        }
+    }
+}
+
+/// The values of `--order`, named like [ChannelOrder::name].
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OrderArg {
+    Rgba,
+    Bgra,
+    Rgb,
+}
+
+impl From<OrderArg> for ChannelOrder {
+    fn from(arg: OrderArg) -> Self {
+        match arg {
+            OrderArg::Rgba => ChannelOrder::Rgba,
+            OrderArg::Bgra => ChannelOrder::Bgra,
+            OrderArg::Rgb => ChannelOrder::Rgb,
+        }
+    }
+}
+
+/// Parses the value of `--pixels`: a number of pixels, or `all` for every pixel.
+fn parse_pixel_count(s: &str) -> Result<u64, String> {
+    match s {
+        "all" => Ok(u64::MAX),
+        n => n
+            .parse()
+            .map_err(|_| format!("expected a number of pixels or `all`, got `{}`", n)),
+    }
+}
+
+#[derive(Debug, Parser)]
+struct Args {
+    #[arg(short, long)]
+    file: PathBuf,
+    #[arg(short, long)]
+    stream: bool,
+    /// The number of bytes read from the file at a time: the size of the BufReader, or with
+    /// --stream, the chunk size of the stream decoder. The default of 64 KiB suits SSDs. On
+    /// spinning disks, try 256 KiB (262144) or more.
+    #[arg(long, default_value_t = 64 * 1024, value_parser = clap::value_parser!(u64).range(1..))]
+    buf_size: u64,
+    /// Read the file on a separate thread while decoding, with up to 4 buffers of --buf-size
+    /// bytes in flight. This helps when reading is slow, as on spinning disks and network
+    /// filesystems.
+    #[arg(long, conflicts_with = "stream")]
+    pipelined: bool,
+    /// Only check that the file is a valid QOI image, end marker included, without keeping the
+    /// pixels. Exits with an error if it isn't.
+    #[arg(long, conflicts_with_all = ["stream", "pipelined"])]
+    verify: bool,
+    /// Decode straight to a raw pixel file at this path instead of into memory, for images too
+    /// large to hold in memory.
+    #[arg(long, value_name = "OUT", conflicts_with_all = ["stream", "pipelined", "verify"])]
+    raw: Option<PathBuf>,
+    /// The channel order of the pixels written with --raw.
+    #[arg(long, value_enum, default_value_t = OrderArg::Rgba, requires = "raw")]
+    order: OrderArg,
+    /// With --raw, also write the dimensions, channels, colorspace, and channel order to a JSON
+    /// file named after the raw file with `.json` appended.
+    #[cfg(feature = "serde")]
+    #[arg(long, requires = "raw")]
+    sidecar: bool,
+    /// Convert the image to a PNG at this path. Rows are written out as soon as they are
+    /// decoded, so the image is never held in memory as a whole.
+    #[cfg(feature = "png")]
+    #[arg(long, value_name = "OUT", conflicts_with_all = ["pipelined", "verify", "raw", "pixels"])]
+    png: Option<PathBuf>,
+    /// Encode the file as a QOI image at this path instead of decoding it. The file holds raw
+    /// pixels, as written by --raw, with exactly --width times --height pixels of --channels.
+    #[arg(
+        long,
+        value_name = "OUT",
+        requires_all = ["width", "height"],
+        conflicts_with_all = ["stream", "pipelined", "verify", "raw", "pixels"]
+    )]
+    encode: Option<PathBuf>,
+    /// The width of the image read with --encode.
+    #[arg(long, requires = "encode")]
+    width: Option<u32>,
+    /// The height of the image read with --encode.
+    #[arg(long, requires = "encode")]
+    height: Option<u32>,
+    /// The channels of the image read with --encode: 4 bytes per pixel for rgba, 3 for rgb.
+    #[arg(long, value_enum, default_value_t = ChannelsArg::Rgba, requires = "encode")]
+    channels: ChannelsArg,
+    /// Print the first N decoded pixels in hex, or every pixel with `all`. With --stream, pixels
+    /// are printed as they are decoded.
+    #[arg(long, value_name = "N", value_parser = parse_pixel_count, conflicts_with_all = ["verify", "raw"])]
+    pixels: Option<u64>,
+    /// Show the image in a window until Esc is pressed or the window is closed.
+    #[cfg(feature = "viewer")]
+    #[arg(long)]
+    window: bool,
+    /// With --window, draw transparent areas over a checkerboard.
+    #[cfg(feature = "viewer")]
+    #[arg(long, requires = "window")]
+    checkerboard: bool,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let file = File::open(args.file)?;
+    let file = File::open(&args.file)?;
 
     let size = file.metadata()?.len();
     let size = (size as f32) / f32::powi(1000., 2); // MB
 
-    let mut file = BufReader::new(file);
+    let buf_size = args.buf_size as usize;
+    let mut file = BufReader::with_capacity(buf_size, file);
+
+    #[cfg(feature = "viewer")]
+    if args.window {
+        let (header, img) = Decoder::new().decode_bufread(&mut file)?;
+        return show_window(header.width, header.height, &img, args.checkerboard);
+    }
+
+    if args.verify {
+        let now = Instant::now();
+        let (header, pixels) = Decoder::new().decode_discard(&mut file)?;
+        let dur = (now.elapsed().as_micros() as f32) / 1000.;
+
+        println!("Valid: {}x{}, {} pixels", header.width, header.height, pixels);
+        println!("Time: {} ms", dur);
+        println!("Throughput: {} MB/sec", size / (dur / 1000.));
+        return Ok(());
+    }
+
+    if let Some(out) = &args.encode {
+        let (width, height) = (args.width.zip(args.height))
+            .expect("clap requires --width and --height with --encode");
+        let header = Header::new(width, height, args.channels.into(), Colorspace::sRGB);
+
+        let now = Instant::now();
+        let mut qoi = File::create(out)?;
+        let written = Encoder::new().encode_reader(&header, &mut file, &mut qoi)?;
+        let dur = (now.elapsed().as_micros() as f32) / 1000.;
+
+        println!("Wrote {}x{} QOI image to {} ({} bytes)", width, height, out.display(), written);
+        println!("Time: {} ms", dur);
+        println!("Throughput: {} MB/sec", size / (dur / 1000.));
+        return Ok(());
+    }
+
+    if let Some(out) = &args.raw {
+        let order = ChannelOrder::from(args.order);
+        let now = Instant::now();
+        let header = decode_to_file(&mut file, out, order)?;
+        let dur = (now.elapsed().as_micros() as f32) / 1000.;
+
+        println!(
+            "Wrote {}x{} {} pixels to {}",
+            header.width,
+            header.height,
+            order.name(),
+            out.display()
+        );
+        #[cfg(feature = "serde")]
+        if args.sidecar {
+            let sidecar = qoiparser::write_raw_sidecar(&header, order, out)?;
+            println!("Wrote {}", sidecar.display());
+        }
+        println!("Time: {} ms", dur);
+        println!("Throughput: {} MB/sec", size / (dur / 1000.));
+        return Ok(());
+    }
+
+    #[cfg(feature = "png")]
+    if let Some(out) = &args.png {
+        let now = Instant::now();
+        let png = BufWriter::new(File::create(out)?);
+        let header = qoiparser::interop::decode_to_png(&mut file, png)?;
+        let dur = (now.elapsed().as_micros() as f32) / 1000.;
+
+        println!("Wrote {}x{} PNG to {}", header.width, header.height, out.display());
+        println!("Time: {} ms", dur);
+        println!("Throughput: {} MB/sec", size / (dur / 1000.));
+        return Ok(());
+    }
+
+    if args.pipelined {
+        println!("Using pipelined decoder");
+
+        let now = Instant::now();
+        let (_, img) = decode_pipelined(&args.file, buf_size.saturating_mul(4))?;
+        let dur = (now.elapsed().as_micros() as f32) / 1000.;
+
+        println!("File Size: {} MB", size);
+        println!("Buffer Size: {} bytes", buf_size);
+        println!("Time: {} ms", dur);
+        println!("Throughput: {} MB/sec", size / (dur / 1000.));
+        println!("Num pixels: {}", img.len());
+        if let Some(limit) = args.pixels {
+            let mut out = BufWriter::new(std::io::stdout());
+            print_pixels(&mut out, 0, limit, &img)?;
+            out.flush()?;
+        }
+        return Ok(());
+    }
 
     if args.stream {
         println!("Using stream decoder");
-        let mut sdec = StreamDecoder::new();
-
-        let mut img_size: u64 = 0;
         let mut img: Vec<Pixel> = Vec::new();
 
-        let mut buf = [0u8; 1];
-
+        // The driver reads in chunks of its own, so the BufReader is bypassed.
         let now = Instant::now();
-        while file.read_exact(&mut buf).is_ok() {
-            match sdec.feed(buf[0]).unwrap() {
-                // The StreamDecoder informs us if it needs more bytes after recieving one
-                // byte. This allows us to work on just getting those bytes and checking
-                // the state again later.
-                StreamDecoderOutput::NeedMore(_) => {}
-
-                // After recieving the image size, we can reserve space for the image
-                // buffer.
-                StreamDecoderOutput::ImageWidthParsed(w) => {
-                    img_size = w as u64;
-                }
-                StreamDecoderOutput::ImageHeightParsed(h) => {
-                    img_size *= h as u64;
-                    img.reserve_exact(img_size as usize);
-                }
-
-                // When pixels are ready to be produced, the StreamDecoder returns an
-                // iterator that produces those pixels. This is a lightweight iterator,
-                // with just a Pixel and u8 count attached (5 bytes in total).
-                StreamDecoderOutput::Pixels(it) => {
-                    for pix in it {
-                        img.push(pix);
-                    }
-                }
-
-                // The StreamDecoder informs us when it has returned all pixels in the
-                // image.
-                StreamDecoderOutput::Finished => break,
-                _ => {}
+        let mut driver = ChunkedDriver::new(&mut file).chunk_size(buf_size);
+        match args.pixels {
+            Some(limit) => {
+                let mut sink = PrintSink {
+                    img: &mut img,
+                    limit,
+                    out: BufWriter::new(std::io::stdout()),
+                };
+                driver.run(&mut sink)?;
+                sink.out.flush()?;
+            }
+            None => {
+                driver.run(&mut img)?;
             }
         }
         let dur = Instant::now() - now;
         let dur = (dur.as_micros() as f32) / 1000.;
 
         println!("File Size: {} MB", size);
+        println!("Buffer Size: {} bytes", buf_size);
         println!("Time: {} ms", dur);
         println!("Throughput: {} MB/sec", size / (dur / 1000.));
         println!("Num pixels: {}", img.len());
@@ -74,17 +258,100 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut dec = Decoder::new();
 
         let now = Instant::now();
-        let (_, img) = dec.decode(&mut file)?;
+        let (_, img) = dec.decode_bufread(&mut file)?;
 
         let dur = Instant::now() - now;
         let dur = (dur.as_micros() as f32) / 1000.;
 
         println!("File Size: {} MB", size);
+        println!("Buffer Size: {} bytes", buf_size);
         println!("Time: {} ms", dur);
         println!("Throughput: {} MB/sec", size / (dur / 1000.));
         println!("Num pixels: {}", img.len());
+        if let Some(limit) = args.pixels {
+            let mut out = BufWriter::new(std::io::stdout());
+            print_pixels(&mut out, 0, limit, &img)?;
+            out.flush()?;
+        }
+    }
+
+
+    Ok(())
+}
+
+/// Prints the pixels at positions below `limit` as `[pos] r:aa g:bb b:cc a:dd` lines. `start` is
+/// the position of the first pixel in `pixels`.
+fn print_pixels(
+    out: &mut impl Write,
+    start: u64,
+    limit: u64,
+    pixels: &[Pixel],
+) -> std::io::Result<()> {
+    for (pos, p) in (start..limit).zip(pixels) {
+        writeln!(out, "[{}] r:{:02x} g:{:02x} b:{:02x} a:{:02x}", pos, p.r, p.g, p.b, p.a)?;
+    }
+    Ok(())
+}
+
+/// Collects the pixels like a `Vec`, printing the first `limit` of them as they are decoded.
+struct PrintSink<'a> {
+    img: &'a mut Vec<Pixel>,
+    limit: u64,
+    out: BufWriter<Stdout>,
+}
+
+impl PixelSink for PrintSink<'_> {
+    fn header(&mut self, header: &Header) {
+        self.img.header(header);
+    }
+
+    fn pixels(&mut self, pixels: PixelsIter) {
+        let start = self.img.len();
+        self.img.extend(pixels);
+        if (start as u64) < self.limit {
+            // The sink can't fail, and stdout going away (e.g. when piped into head) shouldn't
+            // stop the decode, so write errors are dropped.
+            let _ = print_pixels(&mut self.out, start as u64, self.limit, &self.img[start..]);
+        }
     }
+}
 
+/// Shows the image in a window until Esc is pressed or the window is closed. Images larger than
+/// `MAX_WINDOW` are scaled down to fit, keeping their aspect ratio.
+#[cfg(feature = "viewer")]
+fn show_window(
+    width: u32,
+    height: u32,
+    img: &[Pixel],
+    checkerboard: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use minifb::{Key, ScaleMode, Window, WindowOptions};
+    use qoiparser::view;
+
+    // minifb can't tell us how big the screen is, so stay within a size most screens can show.
+    const MAX_WINDOW: (u32, u32) = (1600, 900);
+
+    let buf = match checkerboard {
+        true => view::to_0rgb(&view::checkerboard(img, width, 8)),
+        false => view::to_0rgb(img),
+    };
+
+    let (win_width, win_height) = view::fit_within(width, height, MAX_WINDOW.0, MAX_WINDOW.1);
+    let mut window = Window::new(
+        "qoi-parser",
+        win_width as usize,
+        win_height as usize,
+        WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::AspectRatioStretch,
+            ..WindowOptions::default()
+        },
+    )?;
+    window.set_target_fps(30);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        window.update_with_buffer(&buf, width as usize, height as usize)?;
+    }
 
     Ok(())
 }
@@ -95,70 +362,19 @@ mod tests {
         use image::codecs::png::PngEncoder;
         use image::ImageEncoder;
         use std::fs::File;
-        use std::io::{BufReader, Read};
 
-        use qoiparser::stream::{StreamDecoder, StreamDecoderOutput};
+        use qoiparser::stream::ChunkedDriver;
         use qoiparser::Pixel;
 
+        let file = File::open("tests/dice.qoi").unwrap();
 
-        let file = BufReader::new(File::open("tests/dice.qoi").unwrap());
-        let mut iter = file.bytes();
-
-        let mut sdec = StreamDecoder::new();
-
-        let mut width: u32 = 0;
-        let mut height: u32 = 0;
-
-        let mut img_size: u64 = 0;
         let mut img: Vec<Pixel> = Vec::new();
-
-        while let Some(b) = iter.next() {
-            match b {
-                Ok(byte) => {
-                    match sdec.feed(byte).unwrap() {
-                        // The StreamDecoder informs us if it needs more bytes after recieving one
-                        // byte. This allows us to work on just getting those bytes and checking
-                        // the state again later.
-                        StreamDecoderOutput::NeedMore(_) => {}
-
-                        // After recieving the image size, we can reserve space for the image
-                        // buffer.
-                        StreamDecoderOutput::ImageWidthParsed(w) => {
-                            width = w;
-                            img_size = w as u64;
-                        }
-                        StreamDecoderOutput::ImageHeightParsed(h) => {
-                            height = h;
-                            img_size *= h as u64;
-                            img.reserve_exact(img_size as usize);
-                        }
-
-                        // When pixels are ready to be produced, the StreamDecoder returns an
-                        // iterator that produces those pixels. This is a lightweight iterator,
-                        // with just a Pixel and u8 count attached (5 bytes in total).
-                        StreamDecoderOutput::Pixels(it) => {
-                            for pix in it {
-                                img.push(pix);
-                            }
-                        }
-
-                        // The StreamDecoder informs us when it has returned all pixels in the
-                        // image.
-                        StreamDecoderOutput::Finished => break,
-                        _ => {}
-                    }
-                }
-                // If we failed to pull a byte out of the file, then throw an error.
-                Err(e) => {
-                    println!("{}", e);
-                    assert!(false)
-                }
-            }
-        }
+        let header = ChunkedDriver::new(file).run(&mut img).unwrap();
+        let (width, height) = (header.width, header.height);
 
         let png_enc = PngEncoder::new(File::create("tests/output_stream.png").unwrap());
 
-        let buf: Vec<u8> = img.into_iter().flat_map(|a| a.to_bytes()).collect();
+        let buf = qoiparser::pixels_into_bytes(img);
 
         png_enc
             .write_image(&buf, width, height, image::ColorType::Rgba8)
@@ -184,7 +400,7 @@ mod tests {
 
         let png_enc = PngEncoder::new(File::create("tests/output.png").unwrap());
 
-        let buf: Vec<u8> = img.into_iter().flat_map(|a| a.to_bytes()).collect();
+        let buf = qoiparser::pixels_into_bytes(img);
 
         png_enc
             .write_image(&buf, header.width, header.height, image::ColorType::Rgba8)
@@ -1,15 +1,28 @@
+#![warn(clippy::indexing_slicing)]
+
 use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "fmt")]
 use std::fmt::Display;
-use std::io::Read;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::path::Path;
+#[cfg(feature = "serde")]
+use std::path::PathBuf;
 use std::num::Wrapping;
+use std::cmp::Ordering;
+use std::ops::{Add, AddAssign, ControlFlow};
+use std::time::{Duration, Instant};
 
-use crate::utils::Error;
+use crate::stream::{StreamDecoder, StreamDecoderOutput};
+use crate::utils::{err_msg, CountingReader, Error};
 
 /// The number of channels in the image. This is specified in the header.
 ///
 /// This does not necessarily mean anything for the content of the image.
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Channels {
     RGB = 3,
     RGBA = 4,
@@ -22,14 +35,12 @@ impl TryFrom<u8> for Channels {
         match value {
             3 => Ok(Channels::RGB),
             4 => Ok(Channels::RGBA),
-            _ => Err(Error::HeaderParseError(format!(
-                "Unknown value for channels: {}",
-                value
-            ))),
+            _ => Err(Error::InvalidChannels(value)),
         }
     }
 }
 
+#[cfg(feature = "fmt")]
 impl Display for Channels {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let val = match self {
@@ -44,7 +55,8 @@ impl Display for Channels {
 ///
 /// This does not necessarily mean anything for the content of the image.
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Colorspace {
     #[allow(non_camel_case_types)]
     sRGB = 0,
@@ -58,14 +70,12 @@ impl TryFrom<u8> for Colorspace {
         match value {
             0 => Ok(Colorspace::sRGB),
             1 => Ok(Colorspace::Linear),
-            _ => Err(Error::HeaderParseError(format!(
-                "Unknown value for colorspace: {}",
-                value
-            ))),
+            _ => Err(Error::InvalidColorspace(value)),
         }
     }
 }
 
+#[cfg(feature = "fmt")]
 impl Display for Colorspace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let val = match self {
@@ -79,7 +89,7 @@ impl Display for Colorspace {
 /// The header that appears as the first 14 bytes of a QOI image.
 ///
 /// This should always be read first before reading any of the rest of the file.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     pub magic: [u8; 4], // reads to 'qoif'
     pub width: u32,
@@ -89,14 +99,31 @@ pub struct Header {
 }
 
 impl Header {
-    fn from_bytes(data: &[u8; 14]) -> Result<Self, anyhow::Error> {
+    /// Creates a header for an image with the given dimensions. The magic bytes are filled in.
+    pub fn new(width: u32, height: u32, channels: Channels, colorspace: Colorspace) -> Self {
+        Header {
+            magic: QOI_MAGIC,
+            width,
+            height,
+            channels,
+            colorspace,
+        }
+    }
+
+    /// Parses the 14 header bytes found at the start of every QOI image.
+    ///
+    /// This validates the magic bytes, channels, and colorspace, and that the width and height are
+    /// both nonzero as the spec requires. A zero dimension is reported as
+    /// [Error::InvalidDimensions]. How large the image may be is checked against a decoder's
+    /// [DecodeLimits](crate::dec::DecodeLimits) when decoding.
+    pub fn from_bytes(data: &[u8; 14]) -> Result<Self, anyhow::Error> {
         let mut data = std::io::Cursor::new(data);
 
         let mut magic = [0; 4];
         data.read_exact(&mut magic)?;
 
-        if magic != [b'q', b'o', b'i', b'f'] {
-            return Err(Error::HeaderParseError(format!(
+        if magic != QOI_MAGIC {
+            return Err(Error::HeaderParseError(err_msg!(
                 "Magic bytes did not translate to qoif: {:?}",
                 magic
             )))?;
@@ -104,6 +131,9 @@ impl Header {
 
         let width = data.read_u32::<BigEndian>()?;
         let height = data.read_u32::<BigEndian>()?;
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidDimensions { width, height })?;
+        }
 
         let channels = data.read_u8()?;
         let colorspace = data.read_u8()?;
@@ -116,8 +146,38 @@ impl Header {
             colorspace: colorspace.try_into()?,
         })
     }
+
+    /// Serializes the header into the 14 bytes that start a QOI image.
+    pub fn to_bytes(&self) -> [u8; 14] {
+        let mut data = [0; 14];
+        data[0..4].copy_from_slice(&self.magic);
+        data[4..8].copy_from_slice(&self.width.to_be_bytes());
+        data[8..12].copy_from_slice(&self.height.to_be_bytes());
+        data[12] = self.channels as u8;
+        data[13] = self.colorspace as u8;
+        data
+    }
+
+    /// The aspect ratio of the image as `(width, height)` in lowest terms, such as `(16, 9)` for a
+    /// 1920x1080 image. An image with a zero dimension gives `(1, 0)` or `(0, 1)`, and a 0x0 image
+    /// gives `(0, 0)`.
+    pub fn aspect_ratio(&self) -> (u32, u32) {
+        match gcd(self.width, self.height) {
+            0 => (0, 0),
+            d => (self.width / d, self.height / d),
+        }
+    }
+}
+
+/// The greatest common divisor of `a` and `b`, using the Euclidean algorithm.
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
 }
 
+#[cfg(feature = "fmt")]
 impl Display for Header {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
@@ -132,11 +192,26 @@ impl Display for Header {
     }
 }
 
+/// The 4 magic bytes that start every QOI image, `qoif`.
+pub const QOI_MAGIC: [u8; 4] = *b"qoif";
+
+/// Whether `data` starts with the QOI magic bytes. This is only a quick check of the file type, as
+/// for picking a decoder: the rest of the header may still be invalid.
+pub fn is_qoi(data: &[u8]) -> bool {
+    data.starts_with(&QOI_MAGIC)
+}
+
+/// The 8 bytes that end every QOI image, after the last op: seven `0x00` bytes followed by a
+/// `0x01`. See [write_end_marker](crate::enc::write_end_marker).
+pub const QOI_END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
 /// Submodule containing constants representing the ops available in the QOI format. This isn't an
 /// enum due to a limitation in the lanaguage that makes going from Enum -> u8 in a match statement
 /// (i.e., in a pattern clause) not possible. The work arounds are annoying so, this is the most
 /// clean way of implementing it.
 pub(crate) mod ops {
+    pub use super::QOI_END_MARKER;
+
     pub const QOI_OP_RGB: u8 = 0b1111_1110;
     pub const QOI_OP_RGBA: u8 = 0b1111_1111;
     pub const QOI_OP_INDEX: u8 = 0b0000_0000;
@@ -145,10 +220,83 @@ pub(crate) mod ops {
     pub const QOI_OP_RUN: u8 = 0b1100_0000;
 }
 
-/// A pixel with RGBA values.
+/// The kinds of ops, as looked up by [op_info].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpKind {
+    Rgb,
+    Rgba,
+    Index,
+    Diff,
+    Luma,
+    Run,
+}
+
+/// The kind of op for every possible tag byte, along with the number of bytes that follow it.
+#[allow(clippy::indexing_slicing)]
+static OP_TABLE: [(OpKind, u8); 256] = {
+    let mut table = [(OpKind::Index, 0); 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = match i as u8 {
+            // 8-bit tags have precedence over the 2-bit QOI_OP_RUN tag they overlap with.
+            ops::QOI_OP_RGB => (OpKind::Rgb, 3),
+            ops::QOI_OP_RGBA => (OpKind::Rgba, 4),
+            tag => match tag & 0xc0 {
+                ops::QOI_OP_INDEX => (OpKind::Index, 0),
+                ops::QOI_OP_DIFF => (OpKind::Diff, 0),
+                ops::QOI_OP_LUMA => (OpKind::Luma, 1),
+                _ => (OpKind::Run, 0),
+            },
+        };
+        i += 1;
+    }
+    table
+};
+
+/// Looks up the kind of op `tag` starts, and the number of bytes that follow it.
+#[inline]
+#[allow(clippy::indexing_slicing)]
+fn op_info(tag: u8) -> (OpKind, u8) {
+    // Every u8 is in bounds.
+    OP_TABLE[tag as usize]
+}
+
+/// The slot for `i` in a buffer of previously seen pixels, where `i` is a hash or the argument of
+/// a `QOI_OP_INDEX`. Only the low 6 bits of `i` are used, so this is always in bounds. The buffer
+/// holds either `Pixel`s or pixels packed with [to_bits](crate::dec::Pixel::to_bits).
+///
+/// With the `unsafe-perf` feature, the (already impossible) bounds check is skipped.
+#[inline]
+#[allow(clippy::indexing_slicing)]
+pub(crate) fn seen_slot<T>(buffer: &mut [T; 64], i: u8) -> &mut T {
+    let i = (i & 0x3f) as usize;
+
+    #[cfg(feature = "unsafe-perf")]
+    {
+        debug_assert!(i < buffer.len());
+        // SAFETY: `i` is masked to 0..64, and the buffer has 64 entries.
+        unsafe { buffer.get_unchecked_mut(i) }
+    }
+    #[cfg(not(feature = "unsafe-perf"))]
+    &mut buffer[i]
+}
+
+/// Allocates room for `len` values up front, returning an error instead of panicking or aborting
+/// if that much memory can't be had.
+fn alloc<T>(len: usize) -> Result<Vec<T>, Error> {
+    let mut v = Vec::new();
+    v.try_reserve_exact(len).map_err(|_| {
+        Error::DecodingError(err_msg!("Failed to allocate {} values for the image", len))
+    })?;
+    Ok(v)
+}
+
+/// A pixel with RGBA values. For pixels without alpha, see [PixelRgb].
 ///
-/// TODO: This only allows for RGBA pixels. RGB should be exposed somehow.
-#[derive(Copy, Clone, Debug)]
+/// The layout is fixed to the four bytes in RGBA order, which
+/// [pixels_into_bytes](crate::dec::pixels_into_bytes) relies on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -165,8 +313,104 @@ impl Pixel {
     pub fn to_bytes(self) -> [u8; 4] {
         [self.r, self.g, self.b, self.a]
     }
+
+    /// The red, green, and blue bytes of this pixel, dropping alpha. This is how pixels are laid
+    /// out in [Channels::RGB] image data.
+    pub fn to_rgb_bytes(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    /// The red, green, blue, and alpha bytes of this pixel. This is the same as
+    /// [to_bytes](crate::dec::Pixel::to_bytes), named to pair with
+    /// [to_rgb_bytes](crate::dec::Pixel::to_rgb_bytes).
+    pub fn to_rgba_bytes(self) -> [u8; 4] {
+        self.to_bytes()
+    }
+
+    /// Multiplies the red, green, and blue channels by `factor`, rounding and clamping the results
+    /// to 0-255. Alpha is unchanged. A factor of 0.5 darkens by half, 2.0 brightens.
+    pub fn scale(self, factor: f32) -> Self {
+        // Float to int casts saturate, which takes care of the clamping (NaN becomes 0).
+        let scale = |c: u8| (c as f32 * factor).round() as u8;
+        Pixel::new(scale(self.r), scale(self.g), scale(self.b), self.a)
+    }
+
+    /// Modulates this pixel by `other`, channel by channel (alpha included), treating 255 as 1.0.
+    /// This is the usual way of tinting sprites: multiplying by white leaves a pixel unchanged and
+    /// multiplying by black makes it black.
+    pub fn multiply(self, other: Pixel) -> Self {
+        let mul = |a: u8, b: u8| ((a as u16 * b as u16 + 127) / 255) as u8;
+        Pixel::new(
+            mul(self.r, other.r),
+            mul(self.g, other.g),
+            mul(self.b, other.b),
+            mul(self.a, other.a),
+        )
+    }
+
+    /// Returns this pixel with its red channel replaced.
+    pub fn with_red(self, r: u8) -> Self {
+        Pixel { r, ..self }
+    }
+
+    /// Returns this pixel with its green channel replaced.
+    pub fn with_green(self, g: u8) -> Self {
+        Pixel { g, ..self }
+    }
+
+    /// Returns this pixel with its blue channel replaced.
+    pub fn with_blue(self, b: u8) -> Self {
+        Pixel { b, ..self }
+    }
+
+    /// Returns this pixel with its alpha channel replaced.
+    pub fn with_alpha(self, a: u8) -> Self {
+        Pixel { a, ..self }
+    }
+
+    /// Packs this pixel into a `u32`, with the bytes in RGBA order in memory (native endian).
+    pub fn to_bits(self) -> u32 {
+        u32::from_ne_bytes(self.to_bytes())
+    }
+
+    /// Unpacks a pixel packed by [to_bits](crate::dec::Pixel::to_bits).
+    pub fn from_bits(bits: u32) -> Self {
+        let [r, g, b, a] = bits.to_ne_bytes();
+        Pixel::new(r, g, b, a)
+    }
+
+    /// The BT.709 luminance of this pixel, from 0.0 to 255.0. Alpha is ignored, and the channels
+    /// are used as they are, without removing the sRGB gamma.
+    pub fn luminance(self) -> f32 {
+        self.luminance_key() as f32 / 10_000.0
+    }
+
+    /// The luminance scaled by 10000, which keeps it exact for [Ord].
+    fn luminance_key(self) -> u32 {
+        2126 * self.r as u32 + 7152 * self.g as u32 + 722 * self.b as u32
+    }
+}
+
+/// Pixels are ordered by their [luminance](crate::dec::Pixel::luminance), darkest first, so that
+/// sorting a slice of pixels sorts it by brightness.
+///
+/// Pixels with the same luminance are ordered by red, then green, blue, and alpha. Only equal
+/// pixels compare as `Ordering::Equal`, which keeps this consistent with `Eq`.
+impl Ord for Pixel {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.luminance_key()
+            .cmp(&other.luminance_key())
+            .then_with(|| self.to_bytes().cmp(&other.to_bytes()))
+    }
 }
 
+impl PartialOrd for Pixel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "fmt")]
 impl Display for Pixel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!(
@@ -176,6 +420,29 @@ impl Display for Pixel {
     }
 }
 
+/// Channel-wise addition of two pixels, alpha included.
+///
+/// This saturates rather than wraps (`200 + 100 = 255`), which is what blending and effects
+/// generally expect. Note that this is NOT the arithmetic used by the QOI ops, which wrap.
+impl Add for Pixel {
+    type Output = Pixel;
+
+    fn add(self, rhs: Pixel) -> Self::Output {
+        Pixel::new(
+            self.r.saturating_add(rhs.r),
+            self.g.saturating_add(rhs.g),
+            self.b.saturating_add(rhs.b),
+            self.a.saturating_add(rhs.a),
+        )
+    }
+}
+
+impl AddAssign for Pixel {
+    fn add_assign(&mut self, rhs: Pixel) {
+        *self = *self + rhs;
+    }
+}
+
 /// This default impl is NOT for the default state of a QOI decoder. It is for a default value for
 /// pixels, which is all 0s.
 impl Default for Pixel {
@@ -184,6 +451,202 @@ impl Default for Pixel {
     }
 }
 
+/// A type that decoded pixels can be stored as, with [Decoder::decode_as]. Implemented by [Pixel]
+/// and [PixelRgb].
+pub trait QoiPixel: Copy {
+    /// Builds a pixel from the channels of a decoded pixel. Types without alpha drop it.
+    fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self;
+
+    /// The alpha of this pixel, which is 255 for types without alpha.
+    fn alpha(&self) -> u8;
+
+    /// The red, green, blue, and alpha bytes of this pixel.
+    fn to_rgba(&self) -> [u8; 4];
+}
+
+impl QoiPixel for Pixel {
+    #[inline]
+    fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Pixel::new(r, g, b, a)
+    }
+
+    fn alpha(&self) -> u8 {
+        self.a
+    }
+
+    fn to_rgba(&self) -> [u8; 4] {
+        self.to_bytes()
+    }
+}
+
+/// A pixel with only RGB values, 3 bytes in size, so that a `Vec<PixelRgb>` is laid out like
+/// [Channels::RGB] image data.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct PixelRgb(pub [u8; 3]);
+
+impl PixelRgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        PixelRgb([r, g, b])
+    }
+}
+
+impl QoiPixel for PixelRgb {
+    #[inline]
+    fn from_rgba(r: u8, g: u8, b: u8, _a: u8) -> Self {
+        PixelRgb([r, g, b])
+    }
+
+    fn alpha(&self) -> u8 {
+        255
+    }
+
+    fn to_rgba(&self) -> [u8; 4] {
+        let [r, g, b] = self.0;
+        [r, g, b, 255]
+    }
+}
+
+impl From<Pixel> for PixelRgb {
+    fn from(value: Pixel) -> Self {
+        PixelRgb(value.to_rgb_bytes())
+    }
+}
+
+/// The pixels as RGBA bytes, 4 per pixel, which is the layout most image libraries take.
+pub fn pixels_to_bytes(pixels: &[Pixel]) -> Vec<u8> {
+    // The size hint of a flat_map over arrays is exact, so this allocates once, and it measured
+    // faster than a memcpy into a new Vec (see benches/bytes.rs).
+    pixels.iter().flat_map(|p| p.to_bytes()).collect()
+}
+
+/// Like [pixels_to_bytes], but reuses the allocation of `pixels` instead of copying them.
+pub fn pixels_into_bytes(pixels: Vec<Pixel>) -> Vec<u8> {
+    let mut pixels = std::mem::ManuallyDrop::new(pixels);
+    let (ptr, len, cap) = (pixels.as_mut_ptr(), pixels.len(), pixels.capacity());
+    // SAFETY: Pixel is repr(C) with four u8 fields, so it has a size of 4, an alignment of 1, and
+    // no padding. The allocation is the same whether it is seen as `cap` pixels or `cap * 4`
+    // bytes, and every byte of it up to `len * 4` is initialized.
+    unsafe { Vec::from_raw_parts(ptr.cast::<u8>(), len * 4, cap * 4) }
+}
+
+/// The pixels as RGB bytes, 3 per pixel, dropping alpha. This is how pixels are laid out in
+/// [Channels::RGB] image data.
+pub fn pixels_to_rgb_bytes(pixels: &[Pixel]) -> Vec<u8> {
+    pixels.iter().flat_map(|p| p.to_rgb_bytes()).collect()
+}
+
+/// The layout of each pixel in raw pixel data, as written by [decode_to_file].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ChannelOrder {
+    /// 4 bytes per pixel, as [pixels_to_bytes] returns them.
+    #[default]
+    Rgba,
+    /// 4 bytes per pixel, as [Decoder::decode_to_bgra8] returns them.
+    Bgra,
+    /// 3 bytes per pixel, dropping alpha, as [pixels_to_rgb_bytes] returns them.
+    Rgb,
+}
+
+impl ChannelOrder {
+    /// The number of bytes each pixel takes.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            ChannelOrder::Rgba | ChannelOrder::Bgra => 4,
+            ChannelOrder::Rgb => 3,
+        }
+    }
+
+    /// Lays out `pixel` in this order in `out`, returning the bytes that make up the pixel.
+    #[inline]
+    fn put(self, pixel: Pixel, out: &mut [u8; 4]) -> &[u8] {
+        match self {
+            ChannelOrder::Rgba => *out = pixel.to_bytes(),
+            ChannelOrder::Bgra => *out = [pixel.b, pixel.g, pixel.r, pixel.a],
+            ChannelOrder::Rgb => {
+                *out = pixel.to_bytes();
+                let [rgb @ .., _] = out;
+                return rgb;
+            }
+        }
+        out
+    }
+
+    /// The name used for this order on the command line and in sidecar files.
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelOrder::Rgba => "rgba",
+            ChannelOrder::Bgra => "bgra",
+            ChannelOrder::Rgb => "rgb",
+        }
+    }
+}
+
+/// Limits applied while decoding untrusted images.
+///
+/// The header of a QOI image controls how much memory the chunked decoder allocates up front, so
+/// a 14 byte file can otherwise ask for gigabytes of pixels. The default mirrors the
+/// `QOI_PIXELS_MAX` limit of the reference implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The maximum number of pixels (width * height) an image may contain.
+    pub max_pixels: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_pixels: 400_000_000,
+        }
+    }
+}
+
+/// The result of a call to [decode_nonblocking](crate::dec::Decoder::decode_nonblocking).
+#[derive(Debug)]
+pub enum DecodeProgress {
+    /// The reader has no more data right now, or the deadline passed. Call again to continue.
+    More,
+    /// The image has been fully decoded.
+    Done(Header, Vec<Pixel>),
+}
+
+/// The state of a decode started by [decode_nonblocking](crate::dec::Decoder::decode_nonblocking)
+/// that is waiting on the reader.
+struct PartialDecode {
+    sdec: StreamDecoder,
+    img: Vec<Pixel>,
+}
+
+/// Counters collected while decoding an image with
+/// [decode_with_metrics](crate::dec::Decoder::decode_with_metrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DecodeMetrics {
+    /// The number of bytes read from the input. Like [decode](crate::dec::Decoder::decode), this
+    /// stops before the end marker unless the decoder is strict.
+    pub bytes_read: u64,
+    /// The number of bytes of RGBA pixel data produced.
+    pub bytes_produced: u64,
+    /// The number of pixels decoded.
+    pub pixel_count: u64,
+    /// The wall time taken by the whole decode, header included.
+    pub duration: Duration,
+    /// The most pixels produced by a single `QOI_OP_RUN`, at most 62. This is 0 if the image has
+    /// no runs.
+    pub max_run: u8,
+    /// The number of pixels produced by `QOI_OP_RUN`s. Divided by the number of runs, this is the
+    /// average run length.
+    pub total_run_pixels: u64,
+    /// Whether the header had its channels and colorspace swapped, and was only decoded because
+    /// of [fix_swapped_header](crate::dec::Decoder::fix_swapped_header).
+    pub swapped_header_fixed: bool,
+    /// Whether a `QOI_OP_RUN` ran past the last pixel and was cut short, which only a lenient
+    /// decoder allows (see [strict](crate::dec::Decoder::strict)).
+    pub run_clamped: bool,
+}
+
 /// A decoder for QOI images.
 ///
 /// This is a fairly lightweight object right now. It only contains the decoder state (last pixel
@@ -191,9 +654,23 @@ impl Default for Pixel {
 /// decoding function is [decode](crate::dec::Decoder::decode).
 ///
 /// See [StreamDecoder](crate::stream::StreamDecoder) for the streaming implementation.
+///
+/// Decoding never panics, whatever the input: anything malformed is returned as an error. The
+/// only allocation sized by the input is the image itself, which is capped by [DecodeLimits] and
+/// reported as an error if the memory can't be had. `tests/no_panic.rs` checks this against random
+/// and mutated images.
 pub struct Decoder {
-    state: Pixel,
-    buffer: [Pixel; 64],
+    // Both packed with Pixel::to_bits. See decode_ops.
+    state: u32,
+    buffer: [u32; 64],
+    limits: DecodeLimits,
+    hash: fn(Pixel) -> u8,
+    strict: bool,
+    fix_swapped_header: bool,
+    custom_hash: bool,
+    // Whether read_header swapped the channels and colorspace back, for DecodeMetrics.
+    swapped_header: bool,
+    partial: Option<PartialDecode>,
 }
 
 impl Default for Decoder {
@@ -205,22 +682,99 @@ impl Default for Decoder {
 impl Decoder {
     /// Creates a new Decoder with its default state, ready for parsing.
     pub fn new() -> Self {
+        Self::with_limits(DecodeLimits::default())
+    }
+
+    /// Creates a new Decoder that rejects any image exceeding the given limits.
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self {
+            state: Pixel::new(0, 0, 0, 255).to_bits(),
+            buffer: [0; 64],
+            limits,
+            hash: Decoder::hash_pixel,
+            strict: false,
+            fix_swapped_header: false,
+            custom_hash: false,
+            swapped_header: false,
+            partial: None,
+        }
+    }
+
+    /// Enables every check for images that decode fine but don't follow the spec. Disabled by
+    /// default, which accepts anything that can be decoded unambiguously. Strict mode turns on:
+    ///
+    /// - The end marker is read and must be correct. Leniently, reading stops after the last pixel.
+    /// - The input must end right after the end marker. Anything left over (trailing garbage, or a
+    ///   second image) is reported as [Error::TrailingData].
+    /// - A `QOI_OP_RUN` may not run past the last pixel, which is a [Error::DecodingError].
+    ///   Leniently, the run is cut short at the end of the image.
+    ///
+    /// The magic bytes, channels, and colorspace are always checked, strict or not, as is that the
+    /// width and height are nonzero (see [Header::from_bytes]).
+    ///
+    /// `bytes_remaining` is only known when decoding with
+    /// [decode_slice](crate::dec::Decoder::decode_slice). Other readers are not drained to count
+    /// it, as they may never end.
+    ///
+    /// With the `tracing` feature, a lenient decoder logs a warning when it cuts a run short, and
+    /// when it skips the end marker.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Accepts headers with the channels and colorspace bytes swapped, a bug in some encoders.
+    /// Disabled by default.
+    ///
+    /// When the channels byte is 0 or 1 and the colorspace byte is 3 or 4, neither is valid where
+    /// it is, but both are valid the other way around, so they are swapped back before the header
+    /// is parsed. With the `tracing` feature, a warning is logged when this happens. Any other
+    /// invalid value is still an error.
+    pub fn fix_swapped_header(mut self, fix: bool) -> Self {
+        self.fix_swapped_header = fix;
+        self
+    }
+
+    /// Sets the maximum number of pixels (width * height) an image may have, replacing the limit
+    /// from [DecodeLimits]. The header is checked against it before anything is allocated for the
+    /// pixels, so a small limit keeps a malicious header from costing any memory.
+    ///
+    /// The default of 400 million pixels matches the reference implementation. A server handling
+    /// untrusted uploads will likely want far less, while a tool for huge images may want more.
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.limits.max_pixels = max_pixels;
+        self
+    }
+
+    /// Creates a new Decoder that uses `hash` instead of the spec's hash to place pixels in the
+    /// buffer of previously seen pixels.
+    ///
+    /// This is meant for experimenting with variants of the format, such as measuring how
+    /// collisions in the buffer affect compression. Images decoded with anything other than
+    /// [hash_pixel](crate::dec::Decoder::hash_pixel) are NOT standard QOI images and will not
+    /// decode correctly with any other decoder.
+    pub fn with_hash(hash: fn(Pixel) -> u8) -> Self {
         Self {
-            state: Pixel::new(0, 0, 0, 255),
-            buffer: [Pixel::new(0, 0, 0, 0); 64],
+            hash,
+            custom_hash: true,
+            ..Self::new()
         }
     }
 
     /// Resets a Decoder to its default state. This is used before any decoding occurs, ensuring
     /// that we start at the correct state.
     fn reset(&mut self) {
-        self.state = Pixel::new(0, 0, 0, 255);
-        self.buffer = [Pixel::default(); 64]
+        self.state = Pixel::new(0, 0, 0, 255).to_bits();
+        self.buffer = [0; 64];
+        self.swapped_header = false;
+        self.partial = None;
     }
 
     /// Hashes a pixel given the format from the documentation.
+    ///
+    /// The position in the buffer of previously seen pixels is this value modulo 64.
     #[inline]
-    pub(crate) fn hash_pixel(p: Pixel) -> u8 {
+    pub fn hash_pixel(p: Pixel) -> u8 {
         let r = Wrapping(p.r);
         let g = Wrapping(p.g);
         let b = Wrapping(p.b);
@@ -241,184 +795,2692 @@ impl Decoder {
     /// The decoding code below was heavily based on the reference implementation found at:
     /// https://github.com/phoboslab/qoi
     ///
-    /// TODO: This only works with RGBA pixels, when it should work with RGB as well.
+    /// With the `tracing` feature, this runs inside a `decode` span recording the image dimensions
+    /// and the number of bytes read.
+    ///
+    /// To decode into another pixel type, such as [PixelRgb], use
+    /// [decode_as](crate::dec::Decoder::decode_as).
     pub fn decode(&mut self, data: &mut impl Read) -> Result<(Header, Vec<Pixel>), anyhow::Error>
     {
-        // Reset the decoder's state, just in case this object is used more than once.
-        self.reset();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "decode",
+            width = tracing::field::Empty,
+            height = tracing::field::Empty,
+            channels = tracing::field::Empty,
+            bytes_in = tracing::field::Empty,
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        let data = &mut CountingReader::new(data);
 
-        let mut buf = [0u8; 14];
-        data.read_exact(&mut buf)?;
+        let (header, num_pixels) = self.read_header(data)?;
 
-        let header = Header::from_bytes(&buf)?;
+        #[cfg(feature = "tracing")]
+        span.record("width", header.width)
+            .record("height", header.height)
+            .record("channels", header.channels as u8);
 
-        let num_pixels = (header.width * header.height) as usize;
-        let mut img = vec![Pixel::new(0, 0, 0, 0); num_pixels];
+        let img = self.decode_vec(data, num_pixels)?;
+        self.finish(data)?;
 
-        // Main buffer used for storing data.
-        let mut buf = [0u8; 1];
-        // let mut op_buf = [0u8; 1];
+        #[cfg(feature = "tracing")]
+        span.record("bytes_in", data.count());
 
-        let mut run = 0;
+        Ok((header, img))
+    }
 
-        // Read does not guarantee that .read() will return enough bytes to fill the buffer it is
-        // given. You must either check that you were given fewer bytes and recall .read(), or use
-        // the alternative .read_exact(), which does that for you. Caveat here is that it attempts
-        // to fill the buffer and you must have a buffer of the correct size.
-        //
-        // We preallocate buffers for that use here.
-        let mut rgba_buf = [0; 4];
-        let mut rgb_buf = [0; 3];
-
-        // Modify every pixel in the image
-        for pix in img.iter_mut().take(num_pixels) {
-            // Run gets set to some number if QOI_OP_RUN is found. Each loop skips reading more ops
-            // and instead just uses the previous pixel state.
-            if run > 0 {
-                run -= 1;
-            } else {
-                data.read_exact(&mut buf)?;
+    /// Decodes an image like [decode](crate::dec::Decoder::decode), but into `buf` instead of a new
+    /// `Vec`. Returns the header.
+    ///
+    /// `buf` is cleared first and only grows when its capacity is too small for the image, so
+    /// decoding a series of images of the same size (like the frames of a video) into the same
+    /// buffer allocates only once. If decoding fails, `buf` holds the pixels decoded so far.
+    pub fn decode_reuse(
+        &mut self,
+        data: &mut impl Read,
+        buf: &mut Vec<Pixel>,
+    ) -> Result<Header, anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        buf.clear();
+        buf.try_reserve_exact(num_pixels).map_err(|_| {
+            Error::DecodingError(err_msg!("Failed to allocate {} pixels", num_pixels))
+        })?;
+        self.decode_pixels(data, num_pixels, |_, pix| buf.push(pix))?;
+        self.finish(data)?;
+
+        Ok(header)
+    }
 
-                match buf[0] {
-                    // 8-bit tags have precedence (RGB & RGBA).
-                    ops::QOI_OP_RGB => {
-                        // Read the RGB values
-                        data.read_exact(&mut rgb_buf)?;
+    /// Decodes an image like [decode](crate::dec::Decoder::decode), and also returns
+    /// [DecodeMetrics] describing the decode.
+    pub fn decode_with_metrics(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(Header, Vec<Pixel>, DecodeMetrics), anyhow::Error> {
+        let now = Instant::now();
 
-                        // Set the pixel
-                        self.state = Pixel::new(rgb_buf[0], rgb_buf[1], rgb_buf[2], self.state.a);
+        let mut data = CountingReader::new(data);
+        let (header, num_pixels) = self.read_header(&mut data)?;
+
+        let mut max_run = 0;
+        let mut total_run_pixels = 0;
+        let mut run_clamped = false;
+        let mut img = alloc(num_pixels)?;
+        self.decode_pixels_traced(
+            &mut data,
+            num_pixels,
+            |_, pix| img.push(pix),
+            |pos, op, _| {
+                if let [tag] = *op {
+                    if let (OpKind::Run, _) = op_info(tag) {
+                        // A run is cut short by the end of the image.
+                        let full = (tag & 0x3f) as usize + 1;
+                        run_clamped |= full > num_pixels - pos;
+                        let run = usize::min(full, num_pixels - pos) as u8;
+                        max_run = u8::max(max_run, run);
+                        total_run_pixels += run as u64;
                     }
-                    ops::QOI_OP_RGBA => {
-                        // Read the RGBA values
-                        data.read_exact(&mut rgba_buf)?;
+                }
+                Ok(())
+            },
+        )?;
+        self.finish(&mut data)?;
+
+        let metrics = DecodeMetrics {
+            bytes_read: data.count(),
+            bytes_produced: img.len() as u64 * 4,
+            pixel_count: img.len() as u64,
+            duration: now.elapsed(),
+            max_run,
+            total_run_pixels,
+            swapped_header_fixed: self.swapped_header,
+            run_clamped,
+        };
+
+        Ok((header, img, metrics))
+    }
+
+    /// Decodes an image from a non-blocking reader, returning [DecodeProgress::More] whenever the
+    /// reader returns `WouldBlock`.
+    ///
+    /// The progress made so far is kept in the decoder, so calling this again with the same reader
+    /// picks up where the last call left off. Once the image is done, the next call starts a new
+    /// one. Any other decode call in between throws the partial image away.
+    ///
+    /// This is built on [StreamDecoder] and reads one byte at a time, so the reader should be
+    /// buffered. The end marker is always read and checked, and nothing past it is, so another
+    /// image can follow on the same reader. The decoder's limits and strict mode are applied: a
+    /// strict decoder also requires the reader to end right after the marker, and returns
+    /// [DecodeProgress::More] while waiting to find out. Decoders made with
+    /// [with_hash](crate::dec::Decoder::with_hash) are rejected, and
+    /// [fix_swapped_header](crate::dec::Decoder::fix_swapped_header) is not applied.
+    pub fn decode_nonblocking(&mut self, data: &mut impl Read) -> Result<DecodeProgress, Error> {
+        self.decode_partial(data, None)
+    }
+
+    /// Like [decode_nonblocking](crate::dec::Decoder::decode_nonblocking), also returning
+    /// [DecodeProgress::More] once `deadline` has passed. This bounds how long a single call can
+    /// take, such as when decoding within a frame budget.
+    ///
+    /// The clock is checked every few thousand bytes, so a call can run slightly past the
+    /// deadline. Each call makes some progress, even with a deadline that has already passed, so
+    /// calling this repeatedly always finishes the image.
+    pub fn decode_with_deadline(
+        &mut self,
+        data: &mut impl Read,
+        deadline: Instant,
+    ) -> Result<DecodeProgress, Error> {
+        self.decode_partial(data, Some(deadline))
+    }
+
+    /// The loop behind [decode_nonblocking](crate::dec::Decoder::decode_nonblocking) and
+    /// [decode_with_deadline](crate::dec::Decoder::decode_with_deadline).
+    fn decode_partial(
+        &mut self,
+        data: &mut impl Read,
+        deadline: Option<Instant>,
+    ) -> Result<DecodeProgress, Error> {
+        // How many bytes are fed between looks at the clock.
+        const CLOCK_INTERVAL: u32 = 4096;
+
+        if self.custom_hash {
+            self.partial = None;
+            return Err(Error::DecodingError(err_msg!(
+                "Decoding without blocking only supports the spec's hash"
+            )));
+        }
 
-                        // Set the pixel
-                        self.state = Pixel::new(rgba_buf[0], rgba_buf[1], rgba_buf[2], rgba_buf[3]);
+        // Taken out of the decoder, so that whether the image finishes or fails, the next call
+        // starts over. It is only put back if the reader would block.
+        let mut partial = self.partial.take().unwrap_or_else(|| PartialDecode {
+            sdec: StreamDecoder::new().strict(self.strict),
+            img: Vec::new(),
+        });
+
+        let mut buf = [0u8; 1];
+        let mut fed: u32 = 0;
+        let res = loop {
+            let finished = partial.sdec.is_finished();
+            if finished && !self.strict {
+                break Ok(());
+            }
+
+            if fed == CLOCK_INTERVAL {
+                fed = 0;
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    self.partial = Some(partial);
+                    return Ok(DecodeProgress::More);
+                }
+            }
+            fed += 1;
+
+            match data.read(&mut buf) {
+                // Strictly, the reader must end right after the end marker.
+                Ok(0) => break Ok(()),
+                Ok(_) if finished => {
+                    break Err(Error::TrailingData {
+                        bytes_remaining: None,
+                    })
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    self.partial = Some(partial);
+                    return Ok(DecodeProgress::More);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e.into()),
+            }
+
+            match partial.sdec.feed(buf[0]) {
+                Ok(StreamDecoderOutput::HeaderDone(header)) => {
+                    let num_pixels = header.width as u64 * header.height as u64;
+                    if num_pixels > self.limits.max_pixels {
+                        break Err(Error::DecodingError(err_msg!(
+                            "Image has {} pixels, which exceeds the limit of {}",
+                            num_pixels, self.limits.max_pixels
+                        )));
                     }
-                    // 2-bit tags
-                    _ => {
-                        // Match on only the top two bits.
-                        match buf[0] & 0xc0 {
-                            ops::QOI_OP_INDEX => {
-                                // Grab the pixel at this index
-                                self.state = self.buffer[buf[0] as usize];
-                            }
-                            ops::QOI_OP_DIFF => {
-                                // Grab the three differences (r,g,b). Each are 2-bits.
-                                let dr = (buf[0] >> 4) & 0x03;
-                                let dg = (buf[0] >> 2) & 0x03;
-                                let db = buf[0] & 0x03;
-
-                                // Set each pixel value from the differences.
-                                // Each is biased by 2 (e.g., 0b00 = -2, 0b11 = 1).
-                                self.state.r =
-                                    u8::wrapping_add(self.state.r, u8::wrapping_sub(dr, 2));
-                                self.state.g =
-                                    u8::wrapping_add(self.state.g, u8::wrapping_sub(dg, 2));
-                                self.state.b =
-                                    u8::wrapping_add(self.state.b, u8::wrapping_sub(db, 2));
-                            }
-                            ops::QOI_OP_LUMA => {
-                                // Grab the green difference (6-bits).
-                                let dg = u8::wrapping_sub(buf[0] & 0x3f, 32);
-
-                                // Read in the second byte of data.
-                                data.read_exact(&mut buf)?;
-
-                                // Grab the dr - dg and db - dg values (4-bits).
-                                let dr_dg = (buf[0] >> 4) & 0x0f;
-                                let db_dg = buf[0] & 0x0f;
-
-                                let mid = u8::wrapping_sub(dg, 8);
-                                // Set each pixel value from the differences.
-                                self.state.r =
-                                    u8::wrapping_add(self.state.r, u8::wrapping_add(mid, dr_dg));
-                                self.state.g = u8::wrapping_add(self.state.g, dg);
-                                self.state.b =
-                                    u8::wrapping_add(self.state.b, u8::wrapping_add(mid, db_dg));
-                            }
-                            ops::QOI_OP_RUN => {
-                                // Grab the number of pixels in the run.
-                                run = buf[0] & 0x3f;
-                            }
-                            _ => {
-                                Err(Error::DecodingError("Unknown tag!".to_string()))?;
-                            }
-                        }
+                    let reserved = usize::try_from(num_pixels)
+                        .ok()
+                        .and_then(|n| partial.img.try_reserve_exact(n).ok());
+                    if reserved.is_none() {
+                        break Err(Error::DecodingError(err_msg!(
+                            "Failed to allocate {} pixels",
+                            num_pixels
+                        )));
                     }
                 }
-                // Hash the pixel and set it in the global buffer
-                let hash = Decoder::hash_pixel(self.state);
-                self.buffer[hash as usize % 64] = self.state;
+                Ok(StreamDecoderOutput::Pixels(it)) => partial.img.extend(it),
+                Ok(_) => {}
+                Err(e) => break Err(e),
             }
-            *pix = self.state;
+        };
+
+        res?;
+
+        // Fails if the reader ended before the end of the end marker.
+        let header = partial.sdec.finish()?;
+        Ok(DecodeProgress::Done(header, partial.img))
+    }
+
+    /// Decodes an image like [decode](crate::dec::Decoder::decode), and also returns the total
+    /// number of bytes read from `data`.
+    ///
+    /// Unlike `decode`, this always reads and checks the 8 byte end marker, so the count covers the
+    /// entire image and `data` is left positioned right after it. This is useful when the image is
+    /// followed by other data in the same stream (outside of strict mode).
+    pub fn decode_counting(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(Header, Vec<Pixel>, u64), anyhow::Error> {
+        let mut data = CountingReader::new(data);
+        let (header, img) = self.decode(&mut data)?;
+
+        // Strict mode has already read it.
+        if !self.strict {
+            read_end_marker(&mut data)?;
         }
 
+        Ok((header, img, data.count()))
+    }
+
+    /// Decodes an image into pixels of type `P`, converting each one as it is decoded. With
+    /// [PixelRgb], this gives 3 bytes per pixel with alpha dropped, without decoding to RGBA first.
+    ///
+    /// `decode_as::<Pixel>` gives the same result as [decode](crate::dec::Decoder::decode).
+    pub fn decode_as<P: QoiPixel>(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(Header, Vec<P>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        let mut img = alloc(num_pixels)?;
+        self.decode_pixels(data, num_pixels, |_, pix| {
+            img.push(P::from_rgba(pix.r, pix.g, pix.b, pix.a));
+        })?;
+        self.finish(data)?;
+
         Ok((header, img))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::dec::Decoder;
-    use crate::dec::{Channels, Colorspace, Header};
-    use image::io::Reader as ImageReader;
-    use std::fs::File;
-    use std::path::PathBuf;
+    /// Decodes an image, calling `f` with the position and value of every pixel as it is decoded
+    /// instead of collecting them. A run calls `f` once for each of its pixels. Returns the header.
+    ///
+    /// This runs the same loop as [decode](crate::dec::Decoder::decode), so it is as fast, but
+    /// allocates nothing for the pixels. The image is fully decoded, and the end marker read in
+    /// strict mode, before this returns, so `f` may already have seen pixels when an error
+    /// turns up.
+    pub fn decode_each(
+        &mut self,
+        data: &mut impl Read,
+        f: impl FnMut(usize, Pixel),
+    ) -> Result<Header, anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
 
-    #[test]
-    fn test_decoder() {
+        self.decode_pixels(data, num_pixels, f)?;
+        self.finish(data)?;
 
-        // Using image's QOI reader as a known-good reader. We should parse to the same bytes.
-        let img_qoi_img = ImageReader::open("tests/dice.qoi")
-            .unwrap()
-            .decode()
-            .unwrap();
-        let img_qoi_img = img_qoi_img.into_bytes();
+        Ok(header)
+    }
+
+    /// Decodes an image, keeping only the alpha channel of each pixel.
+    ///
+    /// This is meant for images used as masks, and uses a quarter of the memory of
+    /// [decode](crate::dec::Decoder::decode). Images with [Channels::RGB] normally contain no alpha
+    /// information, in which case every value is 255.
+    pub fn decode_alpha(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(Header, Vec<u8>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
 
-        let mut qoi_file = File::open(PathBuf::from("tests/dice.qoi")).unwrap();
-        let (_, qoi_img) = Decoder::new().decode(&mut qoi_file).unwrap();
-        let qoi_img: Vec<u8> = qoi_img.into_iter().flat_map(|a| a.to_bytes()).collect();
+        let mut alpha = alloc(num_pixels)?;
+        self.decode_pixels(data, num_pixels, |_, pix| alpha.push(pix.a))?;
+        self.finish(data)?;
 
-        // Not doing an assert_eq on qoi_img and img_qoi_img because it blows up the terminal log.
-        for (i, (p1, p2)) in img_qoi_img.iter().zip(qoi_img.iter()).enumerate() {
-            if p1 != p2 {
-                println!("{}", i);
-            }
-            assert_eq!(p1, p2)
-        }
+        Ok((header, alpha))
     }
 
-    #[test]
-    fn test_header() {
-        let width = u32::to_be_bytes(100);
-        let height = u32::to_be_bytes(200);
+    /// Decodes an image straight into BGRA8 bytes, the order Direct3D and some Vulkan formats
+    /// prefer. Returns the width, height, and 4 bytes per pixel.
+    ///
+    /// Red and blue are swapped as each pixel is written out, so no separate swizzle pass over the
+    /// image is needed.
+    pub fn decode_to_bgra8(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(u32, u32, Vec<u8>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
 
-        let data: [u8; 14] = [
-            b'q',
-            b'o',
-            b'i',
-            b'f',
-            width[0],
-            width[1],
-            width[2],
-            width[3],
-            height[0],
-            height[1],
-            height[2],
-            height[3],
-            Channels::RGB as u8,
-            Colorspace::Linear as u8,
-        ];
+        let mut buf = alloc(num_pixels.saturating_mul(4))?;
+        self.decode_pixels(data, num_pixels, |_, pix| {
+            buf.extend_from_slice(&[pix.b, pix.g, pix.r, pix.a]);
+        })?;
+        self.finish(data)?;
 
-        let good = Header {
-            magic: [b'q', b'o', b'i', b'f'],
-            width: 100,
-            height: 200,
-            channels: Channels::RGB,
-            colorspace: Colorspace::Linear,
-        };
+        Ok((header.width, header.height, buf))
+    }
+
+    /// Decodes an image into normalized RGBA floats, for HDR and shader pipelines. Returns the
+    /// width, height, and one `[r, g, b, a]` per pixel, each channel divided by 255.
+    ///
+    /// With `linearize`, the color channels of images whose header says [Colorspace::sRGB] are
+    /// also converted from sRGB to linear light, so they can be blended or lit directly. Alpha is
+    /// always linear, and images already in [Colorspace::Linear] are only rescaled. Every possible
+    /// byte is converted once up front, so this costs no more per pixel than the plain division.
+    pub fn decode_to_rgba_f32(
+        &mut self,
+        data: &mut impl Read,
+        linearize: bool,
+    ) -> Result<(u32, u32, Vec<[f32; 4]>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        let alpha: [f32; 256] = std::array::from_fn(|i| i as f32 / 255.);
+        let color = match (linearize, header.colorspace) {
+            (true, Colorspace::sRGB) => alpha.map(srgb_to_linear),
+            _ => alpha,
+        };
+
+        let mut buf = alloc(num_pixels)?;
+        self.decode_pixels(data, num_pixels, |_, pix| {
+            let f = |c: u8| color.get(c as usize).copied().unwrap_or_default();
+            let a = alpha.get(pix.a as usize).copied().unwrap_or_default();
+            buf.push([f(pix.r), f(pix.g), f(pix.b), a]);
+        })?;
+        self.finish(data)?;
+
+        Ok((header.width, header.height, buf))
+    }
+
+    /// Decodes an image like [decode](crate::dec::Decoder::decode), writing a line to `writer` for
+    /// every op as it is decoded. This is meant for debugging encoders, to see exactly which ops
+    /// produced an image and where things start to go wrong.
+    ///
+    /// Each line has the position of the first pixel the op produced, the op with its decoded
+    /// arguments, and the resulting pixel:
+    ///
+    /// ```text
+    /// [pix 0] OP_RGB(255, 0, 0) -> (r:255 g:0 b:0 a:255)
+    /// [pix 1] OP_RUN(15) -> (r:255 g:0 b:0 a:255)
+    /// [pix 16] OP_DIFF(-1, 0, 1) -> (r:254 g:0 b:1 a:255)
+    /// ```
+    ///
+    /// Run lengths are the number of pixels in the run, and differences are unbiased. Nothing is
+    /// written for the header or the end marker. If decoding fails, the trace stops at the last op
+    /// decoded successfully.
+    ///
+    /// Some images decode fine but can't have come from a correct encoder. These anomalies are
+    /// checked for after every op, and the first one is written to the trace and returned as an
+    /// [Error::DecodingError], whatever the build profile:
+    ///
+    /// - A `QOI_OP_INDEX` of a pixel that doesn't hash to that slot. This happens when the slot was
+    ///   never written, and so holds transparent black, which the encoder should have known. Slot
+    ///   0 is fine, as transparent black hashes to it.
+    /// - A `QOI_OP_RUN` that continues past the end of the image.
+    ///
+    /// ```text
+    /// [pix 1] OP_INDEX(5) -> (r:0 g:0 b:0 a:0)
+    /// [pix 1] ANOMALY: OP_INDEX(5) reads a slot that was never written
+    /// ```
+    pub fn decode_debug(
+        &mut self,
+        data: &mut impl Read,
+        writer: &mut impl Write,
+    ) -> Result<(Header, Vec<Pixel>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        let hash = self.hash;
+        // One bit for every slot of the index that has been written.
+        let mut written = 0u64;
+
+        let mut img = alloc(num_pixels)?;
+        self.decode_pixels_traced(
+            data,
+            num_pixels,
+            |_, pix| img.push(pix),
+            |pos, op, pix| {
+                writeln!(
+                    writer,
+                    "[pix {}] {} -> (r:{} g:{} b:{} a:{})",
+                    pos,
+                    describe_op(op),
+                    pix.r,
+                    pix.g,
+                    pix.b,
+                    pix.a
+                )?;
+
+                let slot = hash(pix) & 0x3f;
+                let anomaly = match *op {
+                    [tag] if tag & 0xc0 == ops::QOI_OP_INDEX && tag & 0x3f != slot => {
+                        let index = tag & 0x3f;
+                        Some(match written & (1 << index) {
+                            0 => Anomaly::UnwrittenSlot(index),
+                            _ => Anomaly::WrongSlot(index, slot),
+                        })
+                    }
+                    [tag] if tag & 0xc0 == ops::QOI_OP_RUN => {
+                        let end = pos + (tag & 0x3f) as usize + 1;
+                        let past = end.saturating_sub(num_pixels);
+                        (past > 0).then_some(Anomaly::RunPastEnd(tag & 0x3f, past))
+                    }
+                    _ => None,
+                };
+                written |= 1 << slot;
+
+                if let Some(anomaly) = anomaly {
+                    writeln!(writer, "[pix {}] ANOMALY: {}", pos, anomaly)?;
+                    return Err(Error::DecodingError(err_msg!(
+                        "Pixel {}: {}",
+                        pos,
+                        anomaly
+                    )))?;
+                }
+                Ok(())
+            },
+        )?;
+        self.finish(data)?;
+
+        Ok((header, img))
+    }
+
+    /// Resets the decoder, then reads the header and checks it against the limits. Returns the
+    /// header and the number of pixels in the image.
+    fn read_header(&mut self, data: &mut impl Read) -> Result<(Header, usize), anyhow::Error> {
+        // Reset the decoder's state, just in case this object is used more than once.
+        self.reset();
+
+        let mut buf = [0u8; 14];
+        data.read_exact(&mut buf)?;
+
+        if self.fix_swapped_header && matches!(buf, [.., 0 | 1, 3 | 4]) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                channels = buf[12],
+                colorspace = buf[13],
+                "channels and colorspace look swapped, swapping them back"
+            );
+            buf.swap(12, 13);
+            self.swapped_header = true;
+        }
+
+        let header = Header::from_bytes(&buf)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            width = header.width,
+            height = header.height,
+            channels = ?header.channels,
+            colorspace = ?header.colorspace,
+            "parsed header"
+        );
+
+        // Multiplying as u32 overflows for large (but valid) dimensions, so do it as u64 and check
+        // the limits before allocating anything.
+        let num_pixels = header.width as u64 * header.height as u64;
+        if num_pixels > self.limits.max_pixels {
+            return Err(Error::DecodingError(err_msg!(
+                "Image has {} pixels, which exceeds the limit of {}",
+                num_pixels, self.limits.max_pixels
+            )))?;
+        }
+        let num_pixels = usize::try_from(num_pixels)?;
+
+        Ok((header, num_pixels))
+    }
+
+    /// Decodes `num_pixels` pixels from the ops in `data`, passing each one to `f` along with its
+    /// position in the image.
+    ///
+    /// A stream that ends within 8 bytes of the header has no room for anything but (at most) the
+    /// end marker, which is reported as such rather than as a bare EOF. An image of 8 pixels or
+    /// fewer can still "decode" from the end marker bytes, as those are valid ops.
+    #[inline]
+    fn decode_pixels(
+        &mut self,
+        data: &mut impl Read,
+        num_pixels: usize,
+        f: impl FnMut(usize, Pixel),
+    ) -> Result<(), anyhow::Error> {
+        self.decode_pixels_traced(data, num_pixels, f, |_, _, _| Ok(()))
+    }
+
+    /// Decodes `num_pixels` pixels into a new `Vec`, the pixel loop of
+    /// [decode](crate::dec::Decoder::decode).
+    ///
+    /// Pixels are always produced in order, so pushing them puts each at its position. With the
+    /// `unsafe-perf` feature, each pixel is instead written straight to its position in the
+    /// reserved capacity, skipping the capacity check of every push.
+    fn decode_vec(
+        &mut self,
+        data: &mut impl Read,
+        num_pixels: usize,
+    ) -> Result<Vec<Pixel>, anyhow::Error> {
+        let mut img = alloc(num_pixels)?;
+
+        #[cfg(not(feature = "unsafe-perf"))]
+        self.decode_pixels(data, num_pixels, |_, pix| img.push(pix))?;
+
+        #[cfg(feature = "unsafe-perf")]
+        {
+            let spare = img.spare_capacity_mut();
+            debug_assert!(spare.len() >= num_pixels);
+            self.decode_pixels(data, num_pixels, |pos, pix| {
+                // SAFETY: decode_pixels only passes positions below `num_pixels`, and alloc
+                // reserved at least that many.
+                unsafe { spare.get_unchecked_mut(pos) }.write(pix);
+            })?;
+            // SAFETY: decode_pixels returned Ok, so it passed every position below `num_pixels`
+            // to the closure, and all of them have been written.
+            unsafe { img.set_len(num_pixels) };
+        }
+
+        Ok(img)
+    }
+
+    /// Like [decode_pixels](crate::dec::Decoder::decode_pixels), also calling `trace` after every
+    /// op with the position of the first pixel it produced, the bytes of the op, and the pixel.
+    #[inline]
+    fn decode_pixels_traced(
+        &mut self,
+        data: &mut impl Read,
+        num_pixels: usize,
+        f: impl FnMut(usize, Pixel),
+        trace: impl FnMut(usize, &[u8], Pixel) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = CountingReader::new(data);
+
+        self.decode_ops(&mut data, num_pixels, f, trace)
+            .map_err(|e| no_op_data(e, data.count(), num_pixels))
+    }
+
+    /// In strict mode, reads the end marker and checks that nothing follows it. Does nothing
+    /// otherwise.
+    fn finish(&self, data: &mut impl Read) -> Result<(), Error> {
+        if !self.strict {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("not strict, leaving the end marker and any trailing data unchecked");
+            return Ok(());
+        }
+
+        read_end_marker(data)?;
+
+        let mut buf = [0u8; 1];
+        loop {
+            match data.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    return Err(Error::TrailingData {
+                        bytes_remaining: None,
+                    })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The main decoding loop, used by [decode_pixels](crate::dec::Decoder::decode_pixels).
+    ///
+    /// The decoding code below was heavily based on the reference implementation found at:
+    /// https://github.com/phoboslab/qoi
+    #[inline]
+    fn decode_ops(
+        &mut self,
+        data: &mut impl Read,
+        num_pixels: usize,
+        mut f: impl FnMut(usize, Pixel),
+        mut trace: impl FnMut(usize, &[u8], Pixel) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        // Read does not guarantee that .read() will return enough bytes to fill the buffer it is
+        // given. You must either check that you were given fewer bytes and recall .read(), or use
+        // the alternative .read_exact(), which does that for you. Caveat here is that it attempts
+        // to fill the buffer and you must have a buffer of the correct size.
+        //
+        // The whole op is read into this buffer: the tag byte first, followed by up to 4 bytes of
+        // data. `len` is the number of bytes used by the current op.
+        let mut buf = [0u8; 5];
+        let mut len;
+
+        // The pixels a run would have had past the end of the image, which are dropped.
+        let mut overflow = 0;
+
+        // The current pixel and the buffer of previously seen pixels are kept in locals, packed
+        // into u32s, and only stored back at the end. Compared to updating `self.state` field by
+        // field, the locals make images without many runs decode 2-3x faster, and packing keeps
+        // run-heavy images from getting slower (see benches/decode.rs).
+        let mut state = self.state;
+        let mut seen = self.buffer;
+
+        // Decode every op in the image. Each iteration produces one pixel, or a whole run of them,
+        // so nothing is done per pixel beyond handing it to `f`.
+        let mut pos = 0;
+        while pos < num_pixels {
+            data.read_exact(&mut buf[..1])?;
+
+            // One lookup gives both the op and how many bytes follow it, which are then read in one
+            // go. This avoids matching on the full byte and then on its top two bits.
+            let (kind, extra) = op_info(buf[0]);
+            len = 1 + extra as usize;
+            if extra > 0 {
+                data.read_exact(buf.get_mut(1..len).unwrap_or_default())?;
+            }
+
+            state = apply_op(state, &mut seen, kind, &buf);
+            // Hash the pixel and set it in the global buffer
+            let hash = (self.hash)(Pixel::from_bits(state));
+            *seen_slot(&mut seen, hash) = state;
+
+            let pix = Pixel::from_bits(state);
+            trace(pos, buf.get(..len).unwrap_or_default(), pix)?;
+
+            if kind == OpKind::Run {
+                // Grab the number of pixels in the run, biased by one, and cut it short at the end
+                // of the image.
+                let run = (buf[0] & 0x3f) as usize + 1;
+                let end = usize::min(pos + run, num_pixels);
+                overflow = pos + run - end;
+                for pos in pos..end {
+                    f(pos, pix);
+                }
+                pos = end;
+            } else {
+                f(pos, pix);
+                pos += 1;
+            }
+        }
+
+        self.state = state;
+        self.buffer = seen;
+
+        if self.strict && overflow > 0 {
+            return Err(Error::DecodingError(err_msg!(
+                "A run continues for {} pixels past the end of the image",
+                overflow
+            )))?;
+        }
+        #[cfg(feature = "tracing")]
+        if overflow > 0 {
+            tracing::warn!(overflow, "a run continues past the end of the image, cutting it short");
+        }
+
+        Ok(())
+    }
+
+    /// Decodes an image and pads it out to power-of-two dimensions, which some texture formats and
+    /// atlases require.
+    ///
+    /// Each row is padded on the right and extra rows are added to the bottom, all using
+    /// transparent black pixels. The returned width and height are those of the padded image. An
+    /// empty dimension stays empty.
+    pub fn decode_pot(
+        &mut self,
+        data: &mut impl Read,
+    ) -> Result<(u32, u32, Vec<Pixel>), anyhow::Error> {
+        let (header, img) = self.decode(data)?;
+
+        let pot = |v: u32| match v {
+            0 => Some(0),
+            _ => v.checked_next_power_of_two(),
+        };
+        let (width, height) = match (pot(header.width), pot(header.height)) {
+            (Some(w), Some(h)) => (w, h),
+            _ => Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            })?,
+        };
+
+        // The padded image can be up to 4x larger than the original, so check it as well.
+        let num_pixels = width as u64 * height as u64;
+        if num_pixels > self.limits.max_pixels {
+            return Err(Error::DecodingError(err_msg!(
+                "Padded image has {} pixels, which exceeds the limit of {}",
+                num_pixels, self.limits.max_pixels
+            )))?;
+        }
+
+        let mut padded = alloc(usize::try_from(num_pixels)?)?;
+        if header.width > 0 {
+            for row in img.chunks_exact(header.width as usize) {
+                padded.extend_from_slice(row);
+                padded.resize(padded.len() + (width - header.width) as usize, Pixel::default());
+            }
+        }
+        padded.resize(num_pixels as usize, Pixel::default());
+
+        Ok((width, height, padded))
+    }
+
+    /// Decodes an image like [decode](crate::dec::Decoder::decode), parsing the ops straight out of
+    /// the reader's buffer instead of reading them one at a time.
+    ///
+    /// This is the fastest way to decode from a file or socket wrapped in a `BufReader`, and gets
+    /// close to decoding from a slice. Like `decode`, nothing past the image is consumed, unless
+    /// the decoder is strict and checks for trailing data.
+    pub fn decode_bufread(
+        &mut self,
+        data: &mut impl BufRead,
+    ) -> Result<(Header, Vec<Pixel>), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        let mut img = alloc(num_pixels)?;
+        let mut consumed = 0;
+        self.decode_ops_buffered(data, num_pixels, &mut consumed, |pix| img.push(pix))
+            .map_err(|e| no_op_data(e, consumed, num_pixels))?;
+        self.finish(data)?;
+
+        Ok((header, img))
+    }
+
+    /// Decodes an image without storing any pixels, to check that it is valid. Returns the header
+    /// and the number of pixels decoded.
+    ///
+    /// Everything [decode_bufread](crate::dec::Decoder::decode_bufread) does is done here,
+    /// including maintaining the index of seen pixels, so every error it finds is found here too.
+    /// On top of that, the end marker is always read and checked, strict or not. Nothing is
+    /// allocated, which makes this the fastest way through an image (see `benches/file.rs`).
+    pub fn decode_discard(
+        &mut self,
+        data: &mut impl BufRead,
+    ) -> Result<(Header, u64), anyhow::Error> {
+        let (header, num_pixels) = self.read_header(data)?;
+
+        let mut consumed = 0;
+        self.decode_ops_buffered(data, num_pixels, &mut consumed, |_| {})
+            .map_err(|e| no_op_data(e, consumed, num_pixels))?;
+        match self.strict {
+            true => self.finish(data)?,
+            false => read_end_marker(data)?,
+        }
+
+        Ok((header, num_pixels as u64))
+    }
+
+    /// The decoding loop of [decode_bufread](crate::dec::Decoder::decode_bufread). This is the same
+    /// as [decode_ops](crate::dec::Decoder::decode_ops), except that ops are parsed from the slice
+    /// returned by `fill_buf`, which is consumed once per fill. Only an op that straddles the end of
+    /// the buffer is copied out with `read_exact`. `consumed` counts the bytes consumed so far.
+    fn decode_ops_buffered(
+        &mut self,
+        data: &mut impl BufRead,
+        num_pixels: usize,
+        consumed: &mut u64,
+        mut f: impl FnMut(Pixel),
+    ) -> Result<(), anyhow::Error> {
+        let mut state = self.state;
+        let mut seen = self.buffer;
+        let mut op = [0u8; 5];
+        let mut pos = 0;
+        let mut overflow = 0;
+        let mut emit = |count: u8, pix: Pixel| {
+            for _ in 0..count {
+                f(pix);
+            }
+        };
+
+        while pos < num_pixels as u64 {
+            let avail = data.fill_buf()?;
+            if avail.is_empty() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+            }
+            let avail_len = avail.len();
+
+            // Parse every op that is entirely in the buffer.
+            let left = num_pixels as u64 - pos;
+            let res = decode_slice_ops(avail, &mut state, &mut seen, self.hash, left, &mut emit);
+            data.consume(res.consumed);
+            *consumed += res.consumed as u64;
+            pos += res.pixels;
+            overflow = res.overflow;
+
+            // Stopping short of both the end of the image and the end of the buffer means an op
+            // straddles the end of the buffer.
+            if pos < num_pixels as u64 && res.consumed < avail_len {
+                data.read_exact(&mut op[..1])?;
+                let len = 1 + op_info(op[0]).1 as usize;
+                data.read_exact(op.get_mut(1..len).unwrap_or_default())?;
+                *consumed += len as u64;
+
+                let left = num_pixels as u64 - pos;
+                let op = op.get(..len).unwrap_or_default();
+                let res = decode_slice_ops(op, &mut state, &mut seen, self.hash, left, &mut emit);
+                pos += res.pixels;
+                overflow = res.overflow;
+            }
+        }
+
+        self.state = state;
+        self.buffer = seen;
+
+        if self.strict && overflow > 0 {
+            return Err(Error::DecodingError(err_msg!(
+                "A run continues for {} pixels past the end of the image",
+                overflow
+            )))?;
+        }
+        #[cfg(feature = "tracing")]
+        if overflow > 0 {
+            tracing::warn!(overflow, "a run continues past the end of the image, cutting it short");
+        }
+
+        Ok(())
+    }
+
+    /// Decodes a QOI image that is already entirely in memory.
+    ///
+    /// This is a convenience over [decode](crate::dec::Decoder::decode) for callers holding a
+    /// `&[u8]`, such as fuzzers or images embedded with `include_bytes!`.
+    pub fn decode_slice(&mut self, data: &[u8]) -> Result<(Header, Vec<Pixel>), anyhow::Error> {
+        let mut data = data;
+        self.decode(&mut data).map_err(|e| match e.downcast_ref::<Error>() {
+            // The byte that showed there was trailing data has already been read.
+            Some(Error::TrailingData { .. }) => Error::TrailingData {
+                bytes_remaining: Some(data.len() as u64 + 1),
+            }
+            .into(),
+            _ => e,
+        })
+    }
+}
+
+/// Decodes a sequence of concatenated QOI images from `reader`, calling `on_frame` with each one.
+///
+/// This is meant for raw frame streams, such as the output of a capture tool piped into a
+/// program. The pixel buffer handed to `on_frame` is reused from frame to frame, so no allocation
+/// happens while the dimensions stay the same. Returning `ControlFlow::Break` from `on_frame`
+/// stops decoding early.
+///
+/// Returns the number of frames passed to `on_frame`. Running out of data exactly between two
+/// frames is the normal way for the sequence to end, while running out in the middle of a frame is
+/// an error (`UnexpectedEof` where possible). The reader is read one byte at a time, so it should
+/// be buffered.
+pub fn decode_frames<R: Read>(
+    mut reader: R,
+    mut on_frame: impl FnMut(Header, &[Pixel]) -> ControlFlow<()>,
+) -> Result<usize, Error> {
+    let mut dec = Decoder::new();
+    let mut img: Vec<Pixel> = Vec::new();
+    let mut frames = 0;
+
+    loop {
+        // Check for the end of the stream before committing to another frame.
+        let mut first = [0u8; 1];
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(frames),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+        let mut data = (&first[..]).chain(&mut reader);
+
+        let (header, num_pixels) = dec.read_header(&mut data).map_err(into_error)?;
+
+        img.clear();
+        img.try_reserve_exact(num_pixels).map_err(|_| {
+            Error::DecodingError(err_msg!("Failed to allocate {} pixels", num_pixels))
+        })?;
+        dec.decode_pixels(&mut data, num_pixels, |_, pix| img.push(pix))
+            .map_err(into_error)?;
+
+        read_end_marker(&mut data)?;
+
+        frames += 1;
+        if on_frame(header, &img).is_break() {
+            return Ok(frames);
+        }
+    }
+}
+
+/// Decodes the image in `reader` straight to a raw pixel file at `out_path`, in the given channel
+/// order and with no header, returning the header of the image.
+///
+/// Pixels are written out as they are decoded, so memory use doesn't depend on the size of the
+/// image and there is no pixel limit. This is meant for images too large to hold in memory. The
+/// end marker is checked after the last pixel, and the file is left partially written if decoding
+/// fails. See [write_raw_sidecar] to record the dimensions next to the file.
+pub fn decode_to_file(
+    reader: &mut impl Read,
+    out_path: &Path,
+    order: ChannelOrder,
+) -> Result<Header, Error> {
+    let file_err = |source| Error::FileIo {
+        path: out_path.to_path_buf(),
+        source,
+    };
+
+    let mut dec = Decoder::with_limits(DecodeLimits {
+        max_pixels: u64::MAX,
+    });
+    let (header, num_pixels) = dec.read_header(reader).map_err(into_error)?;
+
+    let mut out = BufWriter::new(File::create(out_path).map_err(file_err)?);
+
+    // The pixel callback can't fail, so the first write error is kept here and returned from the
+    // trace callback at the end of the op, which stops decoding.
+    let write_err = Cell::new(None);
+    let mut buf = [0u8; 4];
+    dec.decode_pixels_traced(
+        reader,
+        num_pixels,
+        |_, pix| {
+            if let Err(e) = out.write_all(order.put(pix, &mut buf)) {
+                write_err.set(Some(e));
+            }
+        },
+        |_, _, _| match write_err.take() {
+            Some(e) => Err(file_err(e))?,
+            None => Ok(()),
+        },
+    )
+    .map_err(into_error)?;
+    read_end_marker(reader)?;
+
+    out.flush().map_err(file_err)?;
+    Ok(header)
+}
+
+/// The contents of the JSON file written by [write_raw_sidecar]: what is needed to make sense of a
+/// raw pixel file written by [decode_to_file].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RawSidecar {
+    pub width: u32,
+    pub height: u32,
+    pub channels: Channels,
+    pub colorspace: Colorspace,
+    pub order: ChannelOrder,
+}
+
+/// Writes the dimensions, channels, colorspace, and channel order of a raw pixel file written by
+/// [decode_to_file] to a JSON file next to it, named after it with `.json` appended. Returns the
+/// path of the JSON file. The file holds a [RawSidecar], and can be read back into one.
+#[cfg(feature = "serde")]
+pub fn write_raw_sidecar(
+    header: &Header,
+    order: ChannelOrder,
+    raw_path: &Path,
+) -> Result<PathBuf, Error> {
+    let sidecar = RawSidecar {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        order,
+    };
+
+    let mut path = raw_path.as_os_str().to_owned();
+    path.push(".json");
+    let path = PathBuf::from(path);
+    let file_err = |source| Error::FileIo {
+        path: path.clone(),
+        source,
+    };
+
+    let mut json = serde_json::to_vec(&sidecar).map_err(|e| file_err(e.into()))?;
+    json.push(b'\n');
+    std::fs::write(&path, json).map_err(file_err)?;
+    Ok(path)
+}
+
+/// Applies a single op to `state`, the previous pixel, returning the new pixel. `op` holds the tag
+/// byte followed by the op's data, and `kind` is the kind of the tag. Both are packed with
+/// [Pixel::to_bits], as are the pixels in `seen`. Runs return `state` unchanged, and adding the
+/// new pixel to `seen` is left to the caller.
+#[inline(always)]
+fn apply_op(state: u32, seen: &mut [u32; 64], kind: OpKind, op: &[u8; 5]) -> u32 {
+    let [r, g, b, a] = state.to_ne_bytes();
+    match kind {
+        OpKind::Rgb => u32::from_ne_bytes([op[1], op[2], op[3], a]),
+        OpKind::Rgba => u32::from_ne_bytes([op[1], op[2], op[3], op[4]]),
+        // Grab the pixel at this index
+        OpKind::Index => *seen_slot(seen, op[0]),
+        OpKind::Diff => {
+            // Grab the three differences (r,g,b). Each are 2-bits.
+            let dr = (op[0] >> 4) & 0x03;
+            let dg = (op[0] >> 2) & 0x03;
+            let db = op[0] & 0x03;
+
+            // Set each pixel value from the differences.
+            // Each is biased by 2 (e.g., 0b00 = -2, 0b11 = 1).
+            u32::from_ne_bytes([
+                u8::wrapping_add(r, u8::wrapping_sub(dr, 2)),
+                u8::wrapping_add(g, u8::wrapping_sub(dg, 2)),
+                u8::wrapping_add(b, u8::wrapping_sub(db, 2)),
+                a,
+            ])
+        }
+        OpKind::Luma => {
+            // Grab the green difference (6-bits).
+            let dg = u8::wrapping_sub(op[0] & 0x3f, 32);
+
+            // Grab the dr - dg and db - dg values (4-bits) from the second byte.
+            let dr_dg = (op[1] >> 4) & 0x0f;
+            let db_dg = op[1] & 0x0f;
+
+            let mid = u8::wrapping_sub(dg, 8);
+            // Set each pixel value from the differences.
+            u32::from_ne_bytes([
+                u8::wrapping_add(r, u8::wrapping_add(mid, dr_dg)),
+                u8::wrapping_add(g, dg),
+                u8::wrapping_add(b, u8::wrapping_add(mid, db_dg)),
+                a,
+            ])
+        }
+        OpKind::Run => state,
+    }
+}
+
+/// Where [decode_slice_ops] stopped.
+pub(crate) struct SliceOps {
+    /// The number of bytes of ops parsed.
+    pub consumed: usize,
+    /// The number of pixels decoded.
+    pub pixels: u64,
+    /// The pixels the last run would have had past `max_pixels`, which were dropped.
+    pub overflow: u8,
+}
+
+/// Decodes every op that is entirely in `data`, stopping at the first one that isn't or once
+/// `max_pixels` pixels have been decoded. `state` and `seen` are the previous pixel and the
+/// previously seen pixels, packed with [Pixel::to_bits], and are updated as the ops are applied.
+///
+/// `f` is called with each new pixel and the number of times it repeats, which is only above 1
+/// for runs. Runs are cut short at `max_pixels`. This is the core of both
+/// [decode_bufread](crate::dec::Decoder::decode_bufread) and
+/// [feed_all](crate::stream::StreamDecoder::feed_all).
+#[inline(always)]
+pub(crate) fn decode_slice_ops(
+    data: &[u8],
+    state: &mut u32,
+    seen: &mut [u32; 64],
+    hash: fn(Pixel) -> u8,
+    max_pixels: u64,
+    mut f: impl FnMut(u8, Pixel),
+) -> SliceOps {
+    let mut op = [0u8; 5];
+    let mut i = 0;
+    let mut pixels = 0;
+    let mut overflow = 0;
+
+    while pixels < max_pixels {
+        let Some(&tag) = data.get(i) else {
+            break;
+        };
+        let (kind, extra) = op_info(tag);
+        let len = 1 + extra as usize;
+        // Copying a fixed 5 bytes is quicker than copying `len`, if there are 5.
+        let Some(bytes) = data.get(i..i + 5).or_else(|| data.get(i..i + len)) else {
+            break;
+        };
+        for (dst, src) in op.iter_mut().zip(bytes) {
+            *dst = *src;
+        }
+        i += len;
+
+        *state = apply_op(*state, seen, kind, &op);
+        *seen_slot(seen, hash(Pixel::from_bits(*state))) = *state;
+
+        let mut count = 1;
+        if kind == OpKind::Run {
+            let run = (tag & 0x3f) + 1;
+            count = u64::min(run as u64, max_pixels - pixels) as u8;
+            overflow = run - count;
+        }
+        pixels += count as u64;
+        f(count, Pixel::from_bits(*state));
+    }
+
+    SliceOps {
+        consumed: i,
+        pixels,
+        overflow,
+    }
+}
+
+/// Replaces running out of data before reading any ops (at most an end marker's worth of bytes
+/// after the header) with a clearer error. `bytes_read` counts from the end of the header.
+fn no_op_data(e: anyhow::Error, bytes_read: u64, num_pixels: usize) -> anyhow::Error {
+    let eof = e
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof);
+
+    if eof && bytes_read <= ops::QOI_END_MARKER.len() as u64 {
+        Error::DecodingError(err_msg!(
+            "Header claims {} pixels but stream has no op data",
+            num_pixels
+        ))
+        .into()
+    } else {
+        e
+    }
+}
+
+/// Reads the 8 byte end marker, failing if it is missing or wrong.
+fn read_end_marker(data: &mut impl Read) -> Result<(), Error> {
+    let mut end = [0u8; 8];
+    data.read_exact(&mut end)?;
+    if end != ops::QOI_END_MARKER {
+        return Err(Error::DecodingError(err_msg!(
+            "Expected the QOI end marker, found {:?}",
+            end
+        )));
+    }
+    Ok(())
+}
+
+/// Something [decode_debug](crate::dec::Decoder::decode_debug) found that a correct encoder
+/// wouldn't have written. It is only formatted for the trace, and for the error with the `fmt`
+/// feature.
+enum Anomaly {
+    /// A `QOI_OP_INDEX` of a slot that was never written.
+    UnwrittenSlot(u8),
+    /// A `QOI_OP_INDEX` of a slot holding a pixel that hashes to another one.
+    WrongSlot(u8, u8),
+    /// A `QOI_OP_RUN`, with its biased length, and how many pixels it continues past the end.
+    RunPastEnd(u8, usize),
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Anomaly::UnwrittenSlot(index) => {
+                write!(f, "OP_INDEX({}) reads a slot that was never written", index)
+            }
+            Anomaly::WrongSlot(index, slot) => {
+                write!(f, "OP_INDEX({}) reads a pixel that hashes to {}", index, slot)
+            }
+            Anomaly::RunPastEnd(run, past) => write!(
+                f,
+                "OP_RUN({}) continues {} pixels past the end of the image",
+                run + 1,
+                past
+            ),
+        }
+    }
+}
+
+/// Describes a single op (its tag byte followed by its data) for
+/// [decode_debug](crate::dec::Decoder::decode_debug).
+fn describe_op(op: &[u8]) -> String {
+    match *op {
+        [ops::QOI_OP_RGB, r, g, b] => format!("OP_RGB({}, {}, {})", r, g, b),
+        [ops::QOI_OP_RGBA, r, g, b, a] => format!("OP_RGBA({}, {}, {}, {})", r, g, b, a),
+        [tag] if tag & 0xc0 == ops::QOI_OP_INDEX => format!("OP_INDEX({})", tag & 0x3f),
+        [tag] if tag & 0xc0 == ops::QOI_OP_DIFF => format!(
+            "OP_DIFF({}, {}, {})",
+            ((tag >> 4) & 0x03) as i8 - 2,
+            ((tag >> 2) & 0x03) as i8 - 2,
+            (tag & 0x03) as i8 - 2
+        ),
+        [tag, diffs] if tag & 0xc0 == ops::QOI_OP_LUMA => format!(
+            "OP_LUMA({}, {}, {})",
+            (tag & 0x3f) as i8 - 32,
+            (diffs >> 4) as i8 - 8,
+            (diffs & 0x0f) as i8 - 8
+        ),
+        [tag] if tag & 0xc0 == ops::QOI_OP_RUN => format!("OP_RUN({})", (tag & 0x3f) + 1),
+        _ => format!("OP_UNKNOWN({:?})", op),
+    }
+}
+
+/// The sRGB transfer function (IEC 61966-2-1), from an encoded value to linear light. Both are in
+/// `0.0..=1.0`.
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The inverse of [srgb_to_linear], from linear light to an encoded value.
+pub(crate) fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Converts the `anyhow` errors used internally by [Decoder] back into an [Error].
+pub(crate) fn into_error(e: anyhow::Error) -> Error {
+    match e.downcast::<Error>() {
+        Ok(e) => e,
+        Err(e) => match e.downcast::<std::io::Error>() {
+            Ok(e) => Error::Io(e),
+            Err(e) => Error::DecodingError(err_msg!("{}", e)),
+        },
+    }
+}
+
+/// Checks whether an encoded image is a single solid color, returning that color if it is.
+///
+/// Solid images (common for backgrounds and placeholder sprites) encode as one op followed by
+/// nothing but runs. This walks the ops without storing any pixels and bails out with `None` as
+/// soon as a second color shows up, so for most non-solid images only the first few ops are read.
+/// Empty images return `None`.
+pub fn detect_solid(data: &[u8]) -> Result<Option<Pixel>, Error> {
+    let mut sdec = StreamDecoder::new();
+    let mut color: Option<Pixel> = None;
+
+    for &byte in data {
+        match sdec.feed(byte)? {
+            // Every pixel from a single output is the same, so only the first needs checking.
+            StreamDecoderOutput::Pixels(mut it) => match (color, it.next()) {
+                (None, pix) => color = pix,
+                (Some(c), Some(pix)) if c != pix => return Ok(None),
+                _ => {}
+            },
+            StreamDecoderOutput::Finished => break,
+            _ => {}
+        }
+    }
+
+    sdec.finish()?;
+    Ok(color)
+}
+
+/// Checks whether two encoded images decode to the same image: the same header and the same
+/// pixels.
+///
+/// Two encoders can choose different ops for the same pixels (a run instead of an index, or
+/// `QOI_OP_RGBA` where `QOI_OP_DIFF` would do), so their output can differ byte for byte and
+/// still be equivalent. This compares what the images decode to instead, which is what matters
+/// when testing an encoder. Fails if either image can't be decoded.
+pub fn qoi_semantically_equal(a: &[u8], b: &[u8]) -> Result<bool, Error> {
+    let (header_a, pixels_a) = Decoder::new().decode_slice(a).map_err(into_error)?;
+    let (header_b, pixels_b) = Decoder::new().decode_slice(b).map_err(into_error)?;
+
+    Ok(header_a == header_b && pixels_a == pixels_b)
+}
+
+/// Copies a QOI image from `reader` to `writer`, letting `patch` change its header on the way.
+///
+/// Only the channels and colorspace may be changed, since neither affects how the ops decode.
+/// Changing the width or height would no longer match the pixel data, so it is rejected with
+/// `Error::InvalidDimensions`, and changing the magic bytes is rejected with a `HeaderParseError`.
+/// Nothing is written if the patch is rejected.
+///
+/// Everything after the header is copied through unmodified and without being decoded, so this
+/// works on streams of any size (in a proxy, for example). Returns the number of bytes written.
+pub fn rewrite_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    patch: impl FnOnce(&mut Header),
+) -> Result<u64, Error> {
+    let mut buf = [0u8; 14];
+    reader.read_exact(&mut buf)?;
+    let header = Header::from_bytes(&buf).map_err(into_error)?;
+
+    let mut patched = header.clone();
+    patch(&mut patched);
+
+    if patched.width != header.width || patched.height != header.height {
+        return Err(Error::InvalidDimensions {
+            width: patched.width,
+            height: patched.height,
+        });
+    }
+    if patched.magic != header.magic {
+        return Err(Error::HeaderParseError(err_msg!(
+            "The magic bytes can't be changed: {:?}",
+            patched.magic
+        )));
+    }
+
+    writer.write_all(&patched.to_bytes())?;
+    let copied = std::io::copy(reader, writer)?;
+    Ok(14 + copied)
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use crate::dec::{Decoder, DecodeLimits, Pixel, PixelRgb, QoiPixel};
+    use crate::dec::{is_qoi, Channels, Colorspace, Header};
+    use std::cmp::Ordering;
+
+    #[test]
+    fn test_header() {
+        let width = u32::to_be_bytes(100);
+        let height = u32::to_be_bytes(200);
+
+        let data: [u8; 14] = [
+            b'q',
+            b'o',
+            b'i',
+            b'f',
+            width[0],
+            width[1],
+            width[2],
+            width[3],
+            height[0],
+            height[1],
+            height[2],
+            height[3],
+            Channels::RGB as u8,
+            Colorspace::Linear as u8,
+        ];
+
+        let good = Header {
+            magic: [b'q', b'o', b'i', b'f'],
+            width: 100,
+            height: 200,
+            channels: Channels::RGB,
+            colorspace: Colorspace::Linear,
+        };
 
         assert_eq!(good, Header::from_bytes(&data).unwrap());
+
+        // Bad channels and colorspace bytes are reported with their value.
+        let mut bad = data;
+        bad[12] = 5;
+        let err = Header::from_bytes(&bad).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(crate::utils::Error::InvalidChannels(5))));
+        bad[12] = 4;
+        bad[13] = 2;
+        let err = Header::from_bytes(&bad).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(crate::utils::Error::InvalidColorspace(2))));
+    }
+
+    #[test]
+    fn test_is_qoi() {
+        assert!(is_qoi(&std::fs::read("tests/dice.qoi").unwrap()));
+        assert!(!is_qoi(&std::fs::read("tests/dice.png").unwrap()));
+        assert!(!is_qoi(b"qoi"));
+        assert!(!is_qoi(&[]));
+    }
+
+    #[test]
+    fn test_zero_dimensions() {
+        use crate::dec::ops;
+        use crate::utils::Error;
+
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let header = Header::new(width, height, Channels::RGBA, Colorspace::sRGB);
+            let err = Header::from_bytes(&header.to_bytes()).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(&Error::InvalidDimensions { width: w, height: h }) if (w, h) == (width, height)
+                ),
+                "{}x{}: {:#}",
+                width,
+                height,
+                err
+            );
+
+            // Nothing after the header is read, and nothing panics.
+            let mut data = header.to_bytes().to_vec();
+            data.extend(ops::QOI_END_MARKER);
+            assert!(Decoder::new().decode_slice(&data).is_err());
+            assert!(Decoder::new().decode_bufread(&mut &data[..]).is_err());
+            assert!(crate::read_qoi(&mut &data[..]).is_err());
+
+            // The streaming paths reject them the same way.
+            let invalid = |res: Result<_, Error>| {
+                matches!(res, Err(Error::InvalidDimensions { width: w, height: h })
+                    if (w, h) == (width, height))
+            };
+            assert!(invalid(Decoder::new().decode_nonblocking(&mut &data[..]).map(drop)));
+            let mut img = Vec::new();
+            assert!(invalid(crate::stream::ChunkedDriver::new(&data[..]).run(&mut img).map(drop)));
+            let name = format!("qoi-parser-zero-{}x{}.qoi", width, height);
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, &data).unwrap();
+            assert!(invalid(crate::stream::decode_pipelined(&path, 64).map(drop)));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_overflowing_dimensions() {
+        // 65536 * 65536 overflows a u32. This used to panic in debug builds before the pixel count
+        // was computed as a u64.
+        let data = std::fs::read("tests/corpus/invalid/giant-dimensions.qoi").unwrap();
+        assert!(Decoder::new().decode_slice(&data).is_err());
+    }
+
+    #[test]
+    fn test_decode_to_bgra8() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let (width, height, bgra) = Decoder::new().decode_to_bgra8(&mut &data[..]).unwrap();
+        assert_eq!((width, height), (header.width, header.height));
+
+        let expected: Vec<u8> = img.iter().flat_map(|p| [p.b, p.g, p.r, p.a]).collect();
+        assert!(bgra == expected);
+    }
+
+    #[test]
+    fn test_decode_to_rgba_f32() {
+        let header = Header::new(2, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(&[0xff, 255, 128, 0, 255, 0xff, 0, 0, 0, 128]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let (width, height, raw) = Decoder::new()
+            .decode_to_rgba_f32(&mut &data[..], false)
+            .unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(raw[0], [1.0, 128. / 255., 0.0, 1.0]);
+        assert_eq!(raw[1], [0.0, 0.0, 0.0, 128. / 255.]);
+
+        // sRGB 128 is about 0.2158 in linear light. 0 and 255 stay put, and alpha isn't converted.
+        let (_, _, lin) = Decoder::new()
+            .decode_to_rgba_f32(&mut &data[..], true)
+            .unwrap();
+        assert_eq!(lin[0][0], 1.0);
+        assert!((lin[0][1] - 0.21586).abs() < 1e-4, "{}", lin[0][1]);
+        assert_eq!(lin[0][2], 0.0);
+        assert_eq!(lin[1][3], 128. / 255.);
+
+        // Images that are already linear are only rescaled.
+        data[13] = Colorspace::Linear as u8;
+        let (_, _, lin) = Decoder::new()
+            .decode_to_rgba_f32(&mut &data[..], true)
+            .unwrap();
+        assert_eq!(lin, raw);
+    }
+
+    #[test]
+    fn test_decode_bufread() {
+        use crate::dec::ops;
+        use crate::utils::Error;
+        use std::io::{BufReader, Read};
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        // Small buffers split most ops across two fills.
+        for capacity in [1, 2, 3, 7, 64, 8192] {
+            let mut reader = BufReader::with_capacity(capacity, &data[..]);
+            let (h, img) = Decoder::new().decode_bufread(&mut reader).unwrap();
+            assert_eq!(h, header);
+            assert!(img == expected, "capacity {}", capacity);
+
+            // Only the end marker is left.
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, ops::QOI_END_MARKER);
+
+            let mut reader = BufReader::with_capacity(capacity, &data[..]);
+            let (_, img) = Decoder::new().strict(true).decode_bufread(&mut reader).unwrap();
+            assert!(img == expected);
+        }
+
+        // The same errors as decode.
+        let mut trailing = data.clone();
+        trailing.push(0);
+        let err = Decoder::new().strict(true).decode_bufread(&mut &trailing[..]).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::TrailingData { .. })));
+
+        let err = Decoder::new().decode_bufread(&mut &data[..1000]).unwrap_err();
+        let eof = err.downcast_ref::<std::io::Error>().map(|e| e.kind());
+        assert_eq!(eof, Some(std::io::ErrorKind::UnexpectedEof));
+
+        // Just the header and an end marker's worth of bytes.
+        let err = Decoder::new().decode_bufread(&mut &data[..20]).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::DecodingError(_))));
+    }
+
+    #[test]
+    fn test_decode_reuse() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        let mut dec = Decoder::new();
+        let mut buf = Vec::new();
+        assert_eq!(dec.decode_reuse(&mut &data[..], &mut buf).unwrap(), header);
+        assert!(buf == expected);
+
+        // The second decode of the same size reuses the allocation.
+        let ptr = buf.as_ptr();
+        let capacity = buf.capacity();
+        assert_eq!(dec.decode_reuse(&mut &data[..], &mut buf).unwrap(), header);
+        assert!(buf == expected);
+        assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, capacity));
+
+        // A smaller image doesn't shrink it, and no old pixels are left behind.
+        let small = Header::new(2, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = small.to_bytes().to_vec();
+        data.extend_from_slice(&[0xfe, 1, 2, 3, 0xc0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        dec.decode_reuse(&mut &data[..], &mut buf).unwrap();
+        assert_eq!(buf, [Pixel::new(1, 2, 3, 255); 2]);
+        assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, capacity));
+    }
+
+    #[test]
+    fn test_decode_debug() {
+        let header = Header::new(20, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(&[
+            0xfe, 255, 0, 0, // RGB
+            0xc0 | 14, // RUN of 15
+            0x5b,      // DIFF -1, 0, 1
+            0x32,      // INDEX 50, the red pixel
+            0xa2, 0x97, // LUMA 2, 1, -1
+            0xff, 1, 2, 3, 4, // RGBA
+        ]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let mut trace = Vec::new();
+        let (_, img) = Decoder::new()
+            .decode_debug(&mut &data[..], &mut trace)
+            .unwrap();
+        assert_eq!(img, Decoder::new().decode_slice(&data).unwrap().1);
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "[pix 0] OP_RGB(255, 0, 0) -> (r:255 g:0 b:0 a:255)",
+                "[pix 1] OP_RUN(15) -> (r:255 g:0 b:0 a:255)",
+                "[pix 16] OP_DIFF(-1, 0, 1) -> (r:254 g:0 b:1 a:255)",
+                "[pix 17] OP_INDEX(50) -> (r:255 g:0 b:0 a:255)",
+                "[pix 18] OP_LUMA(2, 1, -1) -> (r:2 g:2 b:1 a:255)",
+                "[pix 19] OP_RGBA(1, 2, 3, 4) -> (r:1 g:2 b:3 a:4)",
+            ]
+        );
+
+        // Tracing doesn't change the decoded image.
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut trace = Vec::new();
+        let (_, img) = Decoder::new()
+            .decode_debug(&mut &data[..], &mut trace)
+            .unwrap();
+        assert!(img == Decoder::new().decode_slice(&data).unwrap().1);
+        assert!(String::from_utf8(trace).unwrap().lines().all(|l| l.starts_with("[pix ")));
+    }
+
+    #[test]
+    fn test_diff_luma_arithmetic() {
+        use crate::dec::{apply_op, OpKind};
+
+        // The arithmetic of the reference implementation, one channel at a time. apply_op is
+        // written for speed, so any rework of it must still match this exactly.
+        fn diff(p: Pixel, tag: u8) -> Pixel {
+            let d = |shift: u8| ((tag >> shift) & 0x03).wrapping_sub(2);
+            Pixel::new(
+                p.r.wrapping_add(d(4)),
+                p.g.wrapping_add(d(2)),
+                p.b.wrapping_add(d(0)),
+                p.a,
+            )
+        }
+        fn luma(p: Pixel, tag: u8, second: u8) -> Pixel {
+            let dg = (tag & 0x3f).wrapping_sub(32);
+            let mid = dg.wrapping_sub(8);
+            Pixel::new(
+                p.r.wrapping_add(mid.wrapping_add(second >> 4)),
+                p.g.wrapping_add(dg),
+                p.b.wrapping_add(mid.wrapping_add(second & 0x0f)),
+                p.a,
+            )
+        }
+
+        // Every op byte, from previous pixels whose channels cover every value between them.
+        let mut seen = [0u32; 64];
+        for v in 0..=255u8 {
+            let prev = Pixel::new(v, v ^ 0x5a, 255 - v, v.wrapping_mul(7));
+            for tag in 0x40..0x80u8 {
+                let op = [tag, 0, 0, 0, 0];
+                let pix = apply_op(prev.to_bits(), &mut seen, OpKind::Diff, &op);
+                assert_eq!(Pixel::from_bits(pix), diff(prev, tag), "DIFF {:#04x}", tag);
+            }
+            for tag in 0x80..0xc0u8 {
+                for second in 0..=255u8 {
+                    let op = [tag, second, 0, 0, 0];
+                    let pix = apply_op(prev.to_bits(), &mut seen, OpKind::Luma, &op);
+                    assert_eq!(
+                        Pixel::from_bits(pix),
+                        luma(prev, tag, second),
+                        "LUMA {:#04x} {:#04x}",
+                        tag,
+                        second
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_debug_anomalies() {
+        use crate::utils::Error;
+
+        let decode = |ops: &[u8]| {
+            let header = Header::new(3, 1, Channels::RGBA, Colorspace::sRGB);
+            let mut data = header.to_bytes().to_vec();
+            data.extend_from_slice(ops);
+            data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+            // The normal decoder accepts all of these.
+            assert!(Decoder::new().decode_slice(&data).is_ok());
+
+            let mut trace = Vec::new();
+            let res = Decoder::new().decode_debug(&mut &data[..], &mut trace);
+            (res, String::from_utf8(trace).unwrap())
+        };
+
+        // An index into a slot that no pixel has been stored in.
+        let (res, trace) = decode(&[0xfe, 255, 0, 0, 0x05, 0xc0]);
+        let err = res.unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::DecodingError(_))));
+        assert_eq!(
+            trace.lines().last(),
+            Some("[pix 1] ANOMALY: OP_INDEX(5) reads a slot that was never written")
+        );
+
+        // Transparent black from slot 0 is what the reference encoder produces for it.
+        let (res, _) = decode(&[0x00, 0xc1]);
+        assert!(res.is_ok());
+
+        // A run past the end of the image.
+        let (res, trace) = decode(&[0xfe, 255, 0, 0, 0xc3]);
+        assert!(res.is_err());
+        assert_eq!(
+            trace.lines().last(),
+            Some("[pix 1] ANOMALY: OP_RUN(4) continues 2 pixels past the end of the image")
+        );
+    }
+
+    #[test]
+    fn test_index_bounds() {
+        use crate::stream::{StreamDecoder, StreamDecoderOutput};
+
+        // Pixels that land in the first and last slots of the buffer. Their hashes are 192 and 127,
+        // both past the end of it before masking.
+        let first = Pixel::new(0, 0, 0, 64);
+        let last = Pixel::new(46, 0, 0, 255);
+        assert_eq!(Decoder::hash_pixel(first), 192);
+        assert_eq!(Decoder::hash_pixel(last), 127);
+
+        let header = Header::new(4, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(&[0xff, 0, 0, 0, 64, 0xff, 46, 0, 0, 255]);
+        data.extend_from_slice(&[0x00, 0x3f]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        let expected = [first, last, first, last];
+
+        let (_, img) = Decoder::new().strict(true).decode_slice(&data).unwrap();
+        assert_eq!(img, expected);
+
+        let mut sdec = StreamDecoder::new();
+        let mut img = Vec::new();
+        for &byte in &data {
+            match sdec.feed(byte).unwrap() {
+                StreamDecoderOutput::Pixels(it) => img.extend(it),
+                StreamDecoderOutput::Finished => break,
+                _ => {}
+            }
+        }
+        assert_eq!(img, expected);
+    }
+
+    #[test]
+    fn test_rgb_keeps_alpha() {
+        use crate::stream::{StreamDecoder, StreamDecoderOutput};
+
+        // QOI_OP_RGB has no alpha byte, so it keeps the alpha of the previous pixel. Here that is
+        // 128, set by QOI_OP_RGBA, rather than the 255 of the initial pixel.
+        let header = Header::new(3, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(&[0xff, 10, 20, 30, 128]);
+        data.extend_from_slice(&[0xfe, 40, 50, 60]);
+        data.extend_from_slice(&[0xfe, 70, 80, 90]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        let expected = [
+            Pixel::new(10, 20, 30, 128),
+            Pixel::new(40, 50, 60, 128),
+            Pixel::new(70, 80, 90, 128),
+        ];
+
+        let (_, img) = Decoder::new().strict(true).decode_slice(&data).unwrap();
+        assert_eq!(img, expected);
+
+        let mut img = Vec::new();
+        StreamDecoder::new()
+            .feed_slice(&data, |out| {
+                if let StreamDecoderOutput::Pixels(it) = out {
+                    img.extend(it);
+                }
+            })
+            .unwrap();
+        assert_eq!(img, expected);
+    }
+
+    #[test]
+    fn test_op_table() {
+        use crate::dec::{op_info, OpKind};
+
+        assert_eq!(op_info(0x00), (OpKind::Index, 0));
+        assert_eq!(op_info(0x3f), (OpKind::Index, 0));
+        assert_eq!(op_info(0x40), (OpKind::Diff, 0));
+        assert_eq!(op_info(0x7f), (OpKind::Diff, 0));
+        assert_eq!(op_info(0x80), (OpKind::Luma, 1));
+        assert_eq!(op_info(0xbf), (OpKind::Luma, 1));
+        assert_eq!(op_info(0xc0), (OpKind::Run, 0));
+        assert_eq!(op_info(0xfd), (OpKind::Run, 0));
+        assert_eq!(op_info(0xfe), (OpKind::Rgb, 3));
+        assert_eq!(op_info(0xff), (OpKind::Rgba, 4));
+    }
+
+    #[test]
+    fn test_every_op_byte() {
+        use crate::dec::ops;
+        use crate::stream::{StreamDecoder, StreamDecoderOutput};
+
+        // Tests run with overflow checks on, so any arithmetic in the op handling that doesn't
+        // wrap panics here. Each op byte is tried after pixels at both ends of the value range,
+        // and with the bytes after it (its payload, or the next ops) at both ends too.
+        let header = Header::new(3, 3, Channels::RGBA, Colorspace::sRGB);
+        let prefixes: [&[u8]; 3] = [
+            &[],
+            &[ops::QOI_OP_RGBA, 0, 0, 0, 0],
+            &[ops::QOI_OP_RGBA, 255, 255, 255, 255],
+        ];
+
+        for op in 0..=255u8 {
+            for prefix in prefixes {
+                for tail in [0x00, 0xff, op] {
+                    let mut data = header.to_bytes().to_vec();
+                    data.extend_from_slice(prefix);
+                    data.extend([op, tail, tail, tail, tail]);
+                    data.extend(ops::QOI_END_MARKER);
+
+                    let _ = Decoder::new().decode_slice(&data);
+                    let _ = Decoder::new().strict(true).decode_slice(&data);
+
+                    let mut sdec = StreamDecoder::new();
+                    for &byte in &data {
+                        match sdec.feed(byte) {
+                            Ok(StreamDecoderOutput::Pixels(it)) => it.for_each(drop),
+                            Ok(_) => {}
+                            Err(_) => break,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_op_data() {
+        use crate::dec::ops;
+
+        let header = Header::new(10, 10, Channels::RGBA, Colorspace::sRGB);
+
+        // Just the header, and the header followed directly by the end marker.
+        let mut data = header.to_bytes().to_vec();
+        for _ in 0..2 {
+            let err = Decoder::new().decode_slice(&data).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<crate::utils::Error>(),
+                Some(crate::utils::Error::DecodingError(_))
+            ));
+            #[cfg(feature = "fmt")]
+            assert_eq!(
+                err.to_string(),
+                "Failed to decode: Header claims 100 pixels but stream has no op data"
+            );
+            data.extend_from_slice(&ops::QOI_END_MARKER);
+        }
+
+        // Running out of bytes further into the image is still a plain EOF.
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let err = Decoder::new().decode_slice(&data[..100]).unwrap_err();
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn test_fix_swapped_header() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let mut swapped = data.clone();
+        swapped.swap(12, 13);
+        assert_eq!(swapped[12..14], [header.colorspace as u8, header.channels as u8]);
+        assert!(Decoder::new().decode_slice(&swapped).is_err());
+
+        let (fixed_header, fixed) = Decoder::new()
+            .fix_swapped_header(true)
+            .decode_slice(&swapped)
+            .unwrap();
+        assert_eq!(fixed_header, header);
+        assert!(fixed == img);
+
+        // Correct headers are left alone, and other invalid values are still rejected.
+        let dec = || Decoder::new().fix_swapped_header(true);
+        assert_eq!(dec().decode_slice(&data).unwrap().0, header);
+        for bytes in [[0, 2], [1, 1], [2, 4], [5, 0]] {
+            let mut bad = data.clone();
+            bad[12..14].copy_from_slice(&bytes);
+            assert!(dec().decode_slice(&bad).is_err(), "{:?} was accepted", bytes);
+        }
+    }
+
+    #[test]
+    fn test_strict() {
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        let (_, img) = Decoder::new().strict(true).decode_slice(&data).unwrap();
+        assert!(img == expected);
+
+        // A second image after the first.
+        let mut doubled = data.clone();
+        doubled.extend_from_slice(&data);
+        assert!(Decoder::new().decode_slice(&doubled).is_ok());
+        let err = Decoder::new().strict(true).decode_slice(&doubled).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::TrailingData { bytes_remaining: Some(n) }) if *n == data.len() as u64
+        ));
+
+        // Other readers can't say how much is left.
+        let err = Decoder::new()
+            .strict(true)
+            .decode(&mut std::io::Cursor::new(&doubled))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::TrailingData { bytes_remaining: None })
+        ));
+
+        // The end marker has to be there.
+        assert!(Decoder::new().decode_slice(&data[..data.len() - 1]).is_ok());
+        assert!(Decoder::new()
+            .strict(true)
+            .decode_slice(&data[..data.len() - 1])
+            .is_err());
+
+        // decode_counting must not read the end marker twice.
+        let (_, _, count) = Decoder::new()
+            .strict(true)
+            .decode_counting(&mut &data[..])
+            .unwrap();
+        assert_eq!(count, data.len() as u64);
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+
+        let limits = DecodeLimits { max_pixels: 100 };
+        assert!(Decoder::with_limits(limits).decode_slice(&data).is_err());
+
+        // The limit is inclusive.
+        assert!(Decoder::new().max_pixels(800 * 600 - 1).decode_slice(&data).is_err());
+        assert!(Decoder::new().max_pixels(800 * 600).decode_slice(&data).is_ok());
+
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+        assert_eq!(img.len() as u32, header.width * header.height);
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(Decoder::hash_pixel(Pixel::new(0, 0, 0, 0)), 0);
+        assert_eq!(Decoder::hash_pixel(Pixel::new(1, 1, 1, 1)), 26);
+        assert_eq!(Decoder::hash_pixel(Pixel::new(0, 0, 0, 255)) % 64, 53);
+        assert_eq!(
+            Decoder::hash_pixel(Pixel::new(255, 255, 255, 255)),
+            u8::wrapping_mul(255, 26)
+        );
+    }
+
+    #[test]
+    fn test_with_hash() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+
+        // Explicitly passing the spec hash must not change anything.
+        let (_, img) = Decoder::new().decode_slice(&data).unwrap();
+        let (_, img_hash) = Decoder::with_hash(Decoder::hash_pixel)
+            .decode_slice(&data)
+            .unwrap();
+        assert!(img == img_hash);
+
+        // Any other hash places pixels in different slots, so the INDEX ops go wrong.
+        let (_, img_other) = Decoder::with_hash(|p| p.r ^ p.g ^ p.b ^ p.a)
+            .decode_slice(&data)
+            .unwrap();
+        assert!(img != img_other);
+    }
+
+    #[test]
+    fn test_pixel_add() {
+        let p = Pixel::new(200, 200, 200, 255) + Pixel::new(100, 100, 100, 0);
+        assert_eq!(p, Pixel::new(255, 255, 255, 255));
+
+        let mut p = Pixel::new(1, 2, 3, 4);
+        p += Pixel::new(10, 20, 30, 40);
+        assert_eq!(p, Pixel::new(11, 22, 33, 44));
+    }
+
+    #[test]
+    fn test_pixel_with() {
+        let p = Pixel::new(1, 2, 3, 4);
+        assert_eq!(p.with_alpha(0).with_red(255), Pixel::new(255, 2, 3, 0));
+        assert_eq!(p.with_green(20).with_blue(30), Pixel::new(1, 20, 30, 4));
+    }
+
+    #[test]
+    fn test_pixel_tint() {
+        let p = Pixel::new(100, 201, 0, 77);
+        assert_eq!(p.scale(0.5), Pixel::new(50, 101, 0, 77));
+        assert_eq!(p.scale(2.0), Pixel::new(200, 255, 0, 77));
+        assert_eq!(p.scale(-1.0), Pixel::new(0, 0, 0, 77));
+        assert_eq!(p.scale(1.0), p);
+
+        let white = Pixel::new(255, 255, 255, 255);
+        for p in [p, Pixel::new(255, 1, 128, 254), Pixel::default()] {
+            assert_eq!(p.multiply(white), p);
+            assert_eq!(white.multiply(p), p);
+            assert_eq!(p.multiply(Pixel::default()), Pixel::default());
+        }
+        let half = Pixel::new(128, 128, 128, 255);
+        assert_eq!(Pixel::new(200, 100, 0, 255).multiply(half), Pixel::new(100, 50, 0, 255));
+    }
+
+    #[test]
+    fn test_pixel_ord() {
+        let black = Pixel::new(0, 0, 0, 255);
+        let blue = Pixel::new(0, 0, 255, 255);
+        let red = Pixel::new(255, 0, 0, 255);
+        let green = Pixel::new(0, 255, 0, 255);
+        let white = Pixel::new(255, 255, 255, 255);
+
+        let mut pixels = vec![white, green, black, red, blue];
+        pixels.sort();
+        assert_eq!(pixels, [black, blue, red, green, white]);
+        assert_eq!(pixels.iter().max(), Some(&white));
+        assert_eq!(pixels.binary_search(&red), Ok(2));
+        assert_eq!(white.luminance(), 255.0);
+
+        // Ties in luminance fall back to the channels, so only equal pixels compare equal.
+        assert!(Pixel::new(0, 0, 0, 0) < black);
+        assert_eq!(Pixel::new(1, 2, 3, 4).cmp(&Pixel::new(1, 2, 3, 4)), Ordering::Equal);
+        let (a, b) = (Pixel::new(0, 23, 0, 255), Pixel::new(3, 0, 219, 255));
+        assert_eq!(a.luminance(), b.luminance());
+        assert!(a < b);
+    }
+
+    #[test]
+    fn test_pixel_channel_bytes() {
+        let p = Pixel::new(1, 2, 3, 4);
+        assert_eq!(p.to_rgb_bytes(), [1, 2, 3]);
+        assert_eq!(p.to_rgba_bytes(), [1, 2, 3, 4]);
+        assert_eq!(p.to_rgba_bytes(), p.to_bytes());
+    }
+
+    #[test]
+    fn test_pixels_to_bytes() {
+        use crate::dec::{pixels_into_bytes, pixels_to_bytes, pixels_to_rgb_bytes};
+
+        let img = vec![Pixel::new(1, 2, 3, 4), Pixel::new(5, 6, 7, 8)];
+        assert_eq!(pixels_to_bytes(&img), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(pixels_to_rgb_bytes(&img), [1, 2, 3, 5, 6, 7]);
+
+        let mut with_room = Vec::with_capacity(10);
+        with_room.extend_from_slice(&img);
+        let bytes = pixels_into_bytes(with_room);
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(bytes.capacity(), 40);
+
+        assert!(pixels_to_bytes(&[]).is_empty());
+        assert!(pixels_into_bytes(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_pixel_bits() {
+        let p = Pixel::new(1, 2, 3, 4);
+        assert_eq!(p.to_bits().to_ne_bytes(), [1, 2, 3, 4]);
+        assert_eq!(Pixel::from_bits(p.to_bits()), p);
+        assert_eq!(Pixel::from_bits(u32::MAX), Pixel::new(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn test_decode_pot() {
+        use crate::stream::EncoderReader;
+        use std::io::Read;
+
+        let img: Vec<Pixel> = (0..100 * 100)
+            .map(|i| Pixel::new((i % 100) as u8, (i / 100) as u8, 0, 255))
+            .collect();
+
+        let mut data = Vec::new();
+        EncoderReader::new(img.iter().copied(), 100, 100, Channels::RGBA, Colorspace::sRGB)
+            .read_to_end(&mut data)
+            .unwrap();
+
+        let (width, height, padded) = Decoder::new().decode_pot(&mut &data[..]).unwrap();
+        assert_eq!((width, height), (128, 128));
+        assert_eq!(padded.len(), 128 * 128);
+
+        // Original pixels stay in place, with padding to the right and below.
+        assert_eq!(padded[0], img[0]);
+        assert_eq!(padded[99], img[99]);
+        assert_eq!(padded[100], Pixel::default());
+        assert_eq!(padded[128 + 5], img[100 + 5]);
+        assert_eq!(padded[99 * 128 + 99], img[99 * 100 + 99]);
+        assert_eq!(padded[100 * 128], Pixel::default());
+    }
+
+    #[test]
+    fn test_detect_solid() {
+        use crate::dec::detect_solid;
+        use crate::testgen;
+
+        let color = Pixel::new(12, 34, 56, 78);
+        let data = testgen::encode(&testgen::solid(100, 50, color), 100, 50);
+        assert_eq!(detect_solid(&data).unwrap(), Some(color));
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        assert_eq!(detect_solid(&data).unwrap(), None);
+
+        let data = testgen::encode(&testgen::gradient(10, 10), 10, 10);
+        assert_eq!(detect_solid(&data).unwrap(), None);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing() {
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        // Collects everything the subscriber writes so that it can be checked afterwards.
+        #[derive(Clone, Default)]
+        struct Logs(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let overshoot = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            Decoder::new().decode_slice(&data).unwrap();
+            Decoder::new().decode_slice(&overshoot).unwrap();
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("parsed header width=800 height=600 channels=RGBA"));
+        assert!(logs.contains(&format!(
+            "decode{{width=800 height=600 channels=4 bytes_in={}}}",
+            data.len() - 8
+        )));
+        // The lenient recoveries.
+        assert!(logs.contains("WARN"));
+        assert!(logs.contains("leaving the end marker and any trailing data unchecked"));
+        assert!(logs.contains("a run continues past the end of the image, cutting it short"));
+        assert!(logs.contains("overflow=61"));
+    }
+
+    #[test]
+    fn test_decode_counting() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        // Anything after the image is left unread.
+        let mut stream = data.clone();
+        stream.extend_from_slice(b"trailing");
+        let mut reader = &stream[..];
+
+        let (_, img, count) = Decoder::new().decode_counting(&mut reader).unwrap();
+        assert_eq!(count, data.len() as u64);
+        assert!(img == expected);
+        assert_eq!(reader, b"trailing");
+
+        // A missing end marker is an error.
+        assert!(Decoder::new()
+            .decode_counting(&mut &data[..data.len() - 1])
+            .is_err());
+    }
+
+    #[test]
+    fn test_decode_with_metrics() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        let (header, img, metrics) = Decoder::new()
+            .decode_with_metrics(&mut &data[..])
+            .unwrap();
+        assert!(img == expected);
+
+        // The end marker is not read.
+        assert_eq!(metrics.bytes_read, data.len() as u64 - 8);
+        assert_eq!(metrics.pixel_count, header.width as u64 * header.height as u64);
+        assert_eq!(metrics.bytes_produced, metrics.pixel_count * 4);
+
+        assert!(metrics.total_run_pixels > 0 && metrics.total_run_pixels < metrics.pixel_count);
+        assert!(metrics.max_run > 0 && metrics.max_run <= 62);
+
+        // Everything after the first pixel of a solid image is runs, most of them as long as
+        // they can be.
+        let img = vec![Pixel::new(9, 9, 9, 255); 200];
+        let data = crate::testgen::encode(&img, 20, 10);
+        let (_, _, metrics) = Decoder::new()
+            .decode_with_metrics(&mut &data[..])
+            .unwrap();
+        assert_eq!(metrics.max_run, 62);
+        assert_eq!(metrics.total_run_pixels, 199);
+        assert!(!metrics.swapped_header_fixed && !metrics.run_clamped);
+
+        // Files that only decode leniently are flagged.
+        let data = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
+        let (_, _, metrics) = Decoder::new()
+            .decode_with_metrics(&mut &data[..])
+            .unwrap();
+        assert!(metrics.run_clamped);
+        assert_eq!(metrics.max_run, 1);
+
+        let mut data = std::fs::read("tests/dice.qoi").unwrap();
+        data.swap(12, 13);
+        let (_, _, metrics) = Decoder::new()
+            .fix_swapped_header(true)
+            .decode_with_metrics(&mut &data[..])
+            .unwrap();
+        assert!(metrics.swapped_header_fixed && !metrics.run_clamped);
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let ratio = |w, h| Header::new(w, h, Channels::RGBA, Colorspace::sRGB).aspect_ratio();
+
+        assert_eq!(ratio(1920, 1080), (16, 9));
+        assert_eq!(ratio(1280, 720), (16, 9));
+        assert_eq!(ratio(800, 600), (4, 3));
+        assert_eq!(ratio(2560, 1080), (64, 27));
+        assert_eq!(ratio(1080, 1920), (9, 16));
+        assert_eq!(ratio(7, 5), (7, 5));
+
+        // Square images, 1x1 included.
+        assert_eq!(ratio(1, 1), (1, 1));
+        assert_eq!(ratio(512, 512), (1, 1));
+        assert_eq!(ratio(u32::MAX, u32::MAX), (1, 1));
+
+        assert_eq!(ratio(0, 5), (0, 1));
+        assert_eq!(ratio(0, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_semantically_equal() {
+        use crate::dec::{ops, qoi_semantically_equal};
+        use crate::enc::Encoder;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, mut img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+        assert!(qoi_semantically_equal(&data, &encoded).unwrap());
+
+        // A naive encoder that writes every pixel as QOI_OP_RGBA. Every byte after the header
+        // differs, but the image is the same.
+        let mut naive = header.to_bytes().to_vec();
+        for p in &img {
+            naive.extend_from_slice(&[ops::QOI_OP_RGBA, p.r, p.g, p.b, p.a]);
+        }
+        naive.extend_from_slice(&ops::QOI_END_MARKER);
+        assert_ne!(naive.len(), data.len());
+        assert!(qoi_semantically_equal(&data, &naive).unwrap());
+
+        // A single pixel or header field is enough to tell them apart.
+        img[1234].g ^= 1;
+        let changed = Encoder::new().encode_to_vec(&header, &img).unwrap();
+        assert!(!qoi_semantically_equal(&data, &changed).unwrap());
+
+        let mut linear = data.clone();
+        linear[13] = Colorspace::Linear as u8;
+        assert!(!qoi_semantically_equal(&data, &linear).unwrap());
+
+        assert!(qoi_semantically_equal(&data, &data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_stream() {
+        use crate::dec::rewrite_stream;
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+
+        let mut out = Vec::new();
+        let written = rewrite_stream(&mut &data[..], &mut out, |h| {
+            h.channels = Channels::RGB;
+            h.colorspace = Colorspace::Linear;
+        })
+        .unwrap();
+        assert_eq!(written, data.len() as u64);
+
+        // Only the channels and colorspace bytes differ.
+        let changed: Vec<usize> = (0..data.len()).filter(|&i| data[i] != out[i]).collect();
+        assert_eq!(changed, vec![12, 13]);
+        assert_eq!(&out[12..14], [3, 1]);
+
+        let (header, img) = Decoder::new().decode_slice(&out).unwrap();
+        let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+        assert_eq!(header.colorspace, Colorspace::Linear);
+        assert!(img == expected);
+
+        // Changes that don't match the pixel data are rejected before anything is written.
+        let mut out = Vec::new();
+        let res = rewrite_stream(&mut &data[..], &mut out, |h| h.width += 1);
+        assert!(matches!(res, Err(Error::InvalidDimensions { .. })));
+        let res = rewrite_stream(&mut &data[..], &mut out, |h| h.magic = *b"qoiF");
+        assert!(matches!(res, Err(Error::HeaderParseError(_))));
+        assert!(out.is_empty());
+
+        let res = rewrite_stream(&mut &b"png"[..], &mut out, |_| {});
+        assert!(matches!(res, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_decode_frames() {
+        use crate::dec::decode_frames;
+        use crate::testgen::{self, Pattern};
+        use std::ops::ControlFlow;
+
+        let frames = [
+            testgen::generate(Pattern::Mixed, 8, 4, 0),
+            testgen::generate(Pattern::Gradient, 8, 4, 0),
+            testgen::generate(Pattern::Noise, 5, 7, 1),
+        ];
+        let dims = [(8, 4), (8, 4), (5, 7)];
+        let mut data = Vec::new();
+        for (img, &(w, h)) in frames.iter().zip(dims.iter()) {
+            data.extend(testgen::encode(img, w, h));
+        }
+        let one_frame = testgen::encode(&frames[0], 8, 4).len();
+        let two_frames = one_frame + testgen::encode(&frames[1], 8, 4).len();
+
+        let mut seen = Vec::new();
+        let count = decode_frames(&data[..], |header, img| {
+            seen.push(((header.width, header.height), img.to_vec()));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(seen.len(), 3);
+        for (((dim, img), &expected_dim), expected) in seen.iter().zip(&dims).zip(&frames) {
+            assert_eq!(*dim, expected_dim);
+            assert!(img == expected);
+        }
+
+        let count = decode_frames(&data[..one_frame], |_, _| ControlFlow::Continue(())).unwrap();
+        assert_eq!(count, 1);
+        let count = decode_frames(&[][..], |_, _| ControlFlow::Continue(())).unwrap();
+        assert_eq!(count, 0);
+
+        // Breaking stops after the current frame.
+        let count = decode_frames(&data[..], |_, _| ControlFlow::Break(())).unwrap();
+        assert_eq!(count, 1);
+
+        // Ending partway through a frame is an error.
+        let res = decode_frames(&data[..two_frames + 40], |_, _| ControlFlow::Continue(()));
+        assert!(matches!(
+            res,
+            Err(crate::utils::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_strict_spec() {
+        use crate::dec::ops;
+        use crate::utils::Error;
+
+        // Off-spec, but decodable: the run covers 62 pixels of a 1x1 image.
+        let data = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
+        let (_, img) = Decoder::new().decode_slice(&data).unwrap();
+        assert_eq!(img.len(), 1);
+        let err = Decoder::new().strict(true).decode_slice(&data).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::DecodingError(_))));
+
+        // A run that ends exactly on the last pixel is fine.
+        let header = Header::new(3, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend([ops::QOI_OP_RUN | 2]);
+        data.extend(ops::QOI_END_MARKER);
+        assert!(Decoder::new().strict(true).decode_slice(&data).is_ok());
+
+        // Zero dimensions are invalid, strict or not.
+        let header = Header::new(0, 5, Channels::RGBA, Colorspace::sRGB);
+        let mut data = header.to_bytes().to_vec();
+        data.extend(ops::QOI_END_MARKER);
+        for strict in [false, true] {
+            let err = Decoder::new().strict(strict).decode_slice(&data).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::InvalidDimensions { width: 0, height: 5 })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_run_at_end() {
+        use crate::dec::ops;
+
+        let red = Pixel::new(255, 0, 0, 255);
+        let header = Header::new(5, 1, Channels::RGBA, Colorspace::sRGB);
+        for (run, strict_ok) in [(4, true), (7, false)] {
+            let mut data = header.to_bytes().to_vec();
+            data.extend([ops::QOI_OP_RGB, 255, 0, 0, ops::QOI_OP_RUN | (run - 1)]);
+            data.extend(ops::QOI_END_MARKER);
+
+            // The run fills the image exactly, and nothing after it is read.
+            let mut rest = &data[..];
+            let (_, img) = Decoder::new().decode(&mut rest).unwrap();
+            assert_eq!(img, [red; 5]);
+            assert_eq!(rest, ops::QOI_END_MARKER);
+
+            let (_, buffered) = Decoder::new().decode_bufread(&mut &data[..]).unwrap();
+            assert_eq!(buffered, img);
+            assert_eq!(Decoder::new().strict(true).decode_slice(&data).is_ok(), strict_ok);
+        }
+    }
+
+    #[test]
+    fn test_decode_nonblocking() {
+        use crate::dec::DecodeProgress;
+        use std::io::{ErrorKind, Read};
+
+        /// Hands out `chunk` bytes at a time, returning WouldBlock after each chunk.
+        struct Blocking<'a> {
+            data: &'a [u8],
+            chunk: usize,
+            served: usize,
+        }
+        impl Read for Blocking<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.served == self.chunk {
+                    self.served = 0;
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+                let len = buf.len().min(self.chunk - self.served).min(self.data.len());
+                buf[..len].copy_from_slice(&self.data[..len]);
+                self.data = &self.data[len..];
+                self.served += len;
+                Ok(len)
+            }
+        }
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (expected_header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        let mut reader = Blocking {
+            data: &data,
+            chunk: 4096,
+            served: 0,
+        };
+        let mut dec = Decoder::new();
+        let mut blocked = 0;
+        let (header, img) = loop {
+            match dec.decode_nonblocking(&mut reader).unwrap() {
+                DecodeProgress::More => blocked += 1,
+                DecodeProgress::Done(header, img) => break (header, img),
+            }
+        };
+        assert!(blocked > 1);
+        assert_eq!(header, expected_header);
+        assert!(img == expected);
+
+        // Running out of data is an error, not another More.
+        let mut reader = Blocking {
+            data: &data[..100],
+            chunk: 10,
+            served: 0,
+        };
+        let mut dec = Decoder::new();
+        let err = loop {
+            match dec.decode_nonblocking(&mut reader) {
+                Ok(DecodeProgress::More) => {}
+                Ok(DecodeProgress::Done(..)) => panic!("decoded a truncated image"),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, crate::utils::Error::Io(e) if e.kind() == ErrorKind::UnexpectedEof));
+
+        // The whole end marker is read, so a second image can follow right after the first.
+        let other = crate::testgen::encode(&crate::testgen::mixed(7, 5, 1), 7, 5);
+        let (_, other_img) = Decoder::new().decode_slice(&other).unwrap();
+        let both = [&data[..], &other[..]].concat();
+        let mut reader = Blocking {
+            data: &both,
+            chunk: 1000,
+            served: 0,
+        };
+        let mut dec = Decoder::new();
+        let mut images = Vec::new();
+        while images.len() < 2 {
+            if let DecodeProgress::Done(_, img) = dec.decode_nonblocking(&mut reader).unwrap() {
+                images.push(img);
+            }
+        }
+        assert!(images[0] == expected);
+        assert!(images[1] == other_img);
+        assert!(reader.data.is_empty());
+
+        // A bad end marker is an error, strict or not. Strictly, so is anything after the marker.
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 0;
+        for strict in [false, true] {
+            let res = Decoder::new().strict(strict).decode_nonblocking(&mut &bad[..]);
+            assert!(matches!(res, Err(crate::utils::Error::DecodingError(_))));
+        }
+        let res = Decoder::new().strict(true).decode_nonblocking(&mut &both[..]);
+        assert!(matches!(res, Err(crate::utils::Error::TrailingData { .. })));
+
+        // Strictly, a run past the end of the image is an error too.
+        let overshoot = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
+        assert!(Decoder::new().decode_nonblocking(&mut &overshoot[..]).is_ok());
+        let res = Decoder::new().strict(true).decode_nonblocking(&mut &overshoot[..]);
+        assert!(matches!(res, Err(crate::utils::Error::DecodingError(_))));
+
+        // Other hashes aren't supported.
+        let res = Decoder::with_hash(Decoder::hash_pixel).decode_nonblocking(&mut &data[..]);
+        assert!(matches!(res, Err(crate::utils::Error::DecodingError(_))));
+    }
+
+    #[test]
+    fn test_decode_discard() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, pixels) = Decoder::new().decode_discard(&mut &data[..]).unwrap();
+        assert_eq!(header, Decoder::new().decode_slice(&data).unwrap().0);
+        assert_eq!(pixels, 800 * 600);
+
+        // Corrupt copies fail in the same way as a strict decode_bufread, which also checks the end
+        // marker. Ops that straddle the end of a small buffer are covered too.
+        let cuts = [0, 5, 14, 15, 1000, data.len() - 8, data.len() - 1];
+        let mut corrupt: Vec<Vec<u8>> = cuts.iter().map(|&len| data[..len].to_vec()).collect();
+        for i in [0, 13, 200, data.len() - 1] {
+            let mut bad = data.clone();
+            bad[i] ^= 0x55;
+            corrupt.push(bad);
+        }
+        let mut trailing = data.clone();
+        trailing.push(0);
+        corrupt.push(trailing);
+
+        for bad in &corrupt {
+            for capacity in [3, 4096] {
+                let expected = Decoder::new()
+                    .strict(true)
+                    .decode_bufread(&mut std::io::BufReader::with_capacity(capacity, &bad[..]))
+                    .map(|(header, img)| (header, img.len() as u64));
+                let res = Decoder::new()
+                    .strict(true)
+                    .decode_discard(&mut std::io::BufReader::with_capacity(capacity, &bad[..]));
+                // anyhow's Debug includes a backtrace, so compare the error chains instead.
+                let show = |r: Result<(Header, u64), anyhow::Error>| match r {
+                    Ok(v) => format!("{:?}", v),
+                    Err(e) => format!("{:#}", e),
+                };
+                assert_eq!(show(res), show(expected));
+            }
+        }
+
+        // Outside of strict mode, the end marker is still checked.
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 0;
+        assert!(Decoder::new().decode_discard(&mut &bad[..]).is_err());
+        assert!(Decoder::new().decode_discard(&mut &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_decode_to_file() {
+        use crate::dec::{
+            decode_to_file, pixels_to_bytes, pixels_to_rgb_bytes, ChannelOrder,
+        };
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let path = std::env::temp_dir().join("qoi-parser-decode-to-file.raw");
+        for (order, expected) in [
+            (ChannelOrder::Rgba, pixels_to_bytes(&img)),
+            (ChannelOrder::Bgra, Decoder::new().decode_to_bgra8(&mut &data[..]).unwrap().2),
+            (ChannelOrder::Rgb, pixels_to_rgb_bytes(&img)),
+        ] {
+            assert_eq!(decode_to_file(&mut &data[..], &path, order).unwrap(), header);
+            let raw = std::fs::read(&path).unwrap();
+            assert_eq!(raw.len(), img.len() * order.bytes_per_pixel());
+            assert!(raw == expected, "{:?} differs", order);
+        }
+
+        // A missing end marker is an error, even though every pixel was written.
+        let res = decode_to_file(&mut &data[..data.len() - 1], &path, ChannelOrder::Rgba);
+        assert!(res.is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let path = std::env::temp_dir().join("qoi-parser-missing-dir/out.raw");
+        match decode_to_file(&mut &data[..], &path, ChannelOrder::Rgba) {
+            Err(Error::FileIo { path: p, source }) => {
+                assert_eq!(p, path);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            res => panic!("expected Error::FileIo, got {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_raw_sidecar() {
+        use crate::dec::{write_raw_sidecar, ChannelOrder, RawSidecar};
+
+        let header = Header::new(800, 600, Channels::RGBA, Colorspace::sRGB);
+        let path = std::env::temp_dir().join("qoi-parser-sidecar.raw");
+        let sidecar = write_raw_sidecar(&header, ChannelOrder::Bgra, &path).unwrap();
+        assert_eq!(sidecar, std::env::temp_dir().join("qoi-parser-sidecar.raw.json"));
+
+        let json = std::fs::read_to_string(&sidecar).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+        let parsed: RawSidecar = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            parsed,
+            RawSidecar {
+                width: 800,
+                height: 600,
+                channels: Channels::RGBA,
+                colorspace: Colorspace::sRGB,
+                order: ChannelOrder::Bgra,
+            }
+        );
+        // The order is spelled the same as on the command line.
+        assert!(json.contains("\"order\":\"bgra\""), "{}", json);
+    }
+
+    #[test]
+    fn test_decode_with_deadline() {
+        use crate::dec::DecodeProgress;
+        use std::time::{Duration, Instant};
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (expected_header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        // A deadline that has already passed still makes progress on every call.
+        let mut reader = &data[..];
+        let mut dec = Decoder::new();
+        let mut calls = 0;
+        let (header, img) = loop {
+            calls += 1;
+            match dec.decode_with_deadline(&mut reader, Instant::now()).unwrap() {
+                DecodeProgress::More => {}
+                DecodeProgress::Done(header, img) => break (header, img),
+            }
+        };
+        assert!(calls > 1);
+        assert_eq!(header, expected_header);
+        assert!(img == expected);
+
+        // With time to spare, it finishes in one call.
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let res = Decoder::new().decode_with_deadline(&mut &data[..], deadline);
+        assert!(matches!(res, Ok(DecodeProgress::Done(..))));
+
+        // An image followed by more data stops right after its end marker, wherever the deadline
+        // falls, and the next image decodes from there.
+        let other = crate::testgen::encode(&crate::testgen::mixed(7, 5, 1), 7, 5);
+        let both = [&data[..], &other[..]].concat();
+        let mut reader = &both[..];
+        let mut dec = Decoder::new();
+        let mut images = Vec::new();
+        while images.len() < 2 {
+            if let DecodeProgress::Done(_, img) =
+                dec.decode_with_deadline(&mut reader, Instant::now()).unwrap()
+            {
+                images.push(img);
+                if images.len() == 1 {
+                    assert_eq!(reader.len(), other.len());
+                }
+            }
+        }
+        assert!(images[0] == expected);
+        assert!(images[1] == Decoder::new().decode_slice(&other).unwrap().1);
+
+        // The marker is checked, and strictly, nothing may follow it.
+        let mut bad = data.clone();
+        bad[data.len() - 3] = 1;
+        assert!(Decoder::new().decode_with_deadline(&mut &bad[..], deadline).is_err());
+        let res = Decoder::new().strict(true).decode_with_deadline(&mut &both[..], deadline);
+        assert!(matches!(res, Err(crate::utils::Error::TrailingData { .. })));
+    }
+
+    #[test]
+    fn test_decode_alpha() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let (header, alpha) = Decoder::new().decode_alpha(&mut &data[..]).unwrap();
+        assert_eq!(alpha.len() as u32, header.width * header.height);
+        assert!(alpha.iter().zip(img.iter()).all(|(&a, p)| a == p.a));
+    }
+
+    #[test]
+    fn test_decode_as() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let (rgba_header, rgba) = Decoder::new().decode_as::<Pixel>(&mut &data[..]).unwrap();
+        assert_eq!(rgba_header, header);
+        assert!(rgba == img);
+
+        let (rgb_header, rgb) = Decoder::new().decode_as::<PixelRgb>(&mut &data[..]).unwrap();
+        assert_eq!(rgb_header, header);
+        assert_eq!(std::mem::size_of_val(&rgb[..]), img.len() * 3);
+        let stripped: Vec<PixelRgb> = img.iter().map(|&p| p.into()).collect();
+        assert!(rgb == stripped);
+        assert!(rgb.iter().all(|p| p.alpha() == 255));
+
+        // Both types give back the RGBA they were built from, less alpha for PixelRgb.
+        let p = Pixel::new(1, 2, 3, 4);
+        assert_eq!(Pixel::from_rgba(1, 2, 3, 4).to_rgba(), [1, 2, 3, 4]);
+        assert_eq!(PixelRgb::from_rgba(1, 2, 3, 4).to_rgba(), [1, 2, 3, 255]);
+        assert_eq!(PixelRgb::from(p), PixelRgb::new(1, 2, 3));
+    }
+
+    #[test]
+    fn test_decode_each() {
+        let dice = std::fs::read("tests/dice.qoi").unwrap();
+        // Runs longer than a row, then a mix of every op.
+        let solid = crate::testgen::solid(100, 30, Pixel::new(1, 2, 3, 4));
+        let solid = crate::testgen::encode(&solid, 100, 30);
+        let mixed = crate::testgen::encode(&crate::testgen::mixed(100, 30, 7), 100, 30);
+
+        for data in [dice, solid, mixed] {
+            let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+            let mut each = Vec::new();
+            let each_header = Decoder::new()
+                .decode_each(&mut &data[..], |pos, pix| each.push((pos, pix)))
+                .unwrap();
+            assert_eq!(each_header, header);
+            assert_eq!(each.len(), img.len());
+            assert!(each.iter().enumerate().all(|(i, &(pos, pix))| pos == i && pix == img[i]));
+        }
+
+        // Pixels decoded before an error have already been passed on.
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut count = 0;
+        let err = Decoder::new().decode_each(&mut &data[..data.len() / 2], |_, _| count += 1);
+        assert!(err.is_err());
+        assert!(count > 0);
     }
 }
@@ -0,0 +1,910 @@
+use crate::dec::{ops, Channels, Colorspace, Decoder, Header, Pixel};
+use crate::utils::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The most bytes a single call to [EncoderState::encode] can produce. This is a flushed run
+/// followed by a `QOI_OP_RGBA` (1 + 5 bytes).
+pub(crate) const MAX_ENCODED_PIXEL: usize = 6;
+
+/// The longest run a single `QOI_OP_RUN` can store. 63 and 64 would collide with the
+/// `QOI_OP_RGB` and `QOI_OP_RGBA` tags.
+pub(crate) const MAX_RUN: u8 = 62;
+
+/// The number of pixels [Encoder] encodes at a time, between checks of how much output is waiting
+/// to be written.
+const BATCH: usize = 4096;
+
+/// The size of the chunks encoded data is collected into before being written.
+const CHUNK: usize = 64 * 1024;
+
+/// The state shared by the encoders while encoding.
+///
+/// This mirrors the decoder state: the previous pixel and the buffer of previously seen pixels at
+/// their hashed position, packed with [Pixel::to_bits] so that comparing two pixels is a single
+/// `u32` comparison. On top of that it tracks the length of the run currently being built. It only
+/// encodes pixels into ops; writing the header and end marker is up to the caller.
+///
+/// The encoding below was heavily based on the reference implementation found at:
+/// https://github.com/phoboslab/qoi
+pub(crate) struct EncoderState {
+    prev: u32,
+    buffer: [u32; 64],
+    run: u8,
+}
+
+impl Default for EncoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EncoderState {
+    pub(crate) fn new() -> Self {
+        EncoderState {
+            prev: Pixel::new(0, 0, 0, 255).to_bits(),
+            buffer: [0; 64],
+            run: 0,
+        }
+    }
+
+    /// Encodes a single pixel into `out`, returning the number of bytes written.
+    ///
+    /// Nothing is written while a run is being built. Once the run is broken (or hits its maximum
+    /// length) it is written out before the op for the new pixel.
+    pub(crate) fn encode(&mut self, px: Pixel, out: &mut [u8; MAX_ENCODED_PIXEL]) -> usize {
+        let bits = px.to_bits();
+        if bits == self.prev {
+            self.run += 1;
+            if self.run == MAX_RUN {
+                return self.flush(out);
+            }
+            return 0;
+        }
+
+        let mut len = self.flush(out);
+
+        let hash = Decoder::hash_pixel(px) % 64;
+        if self.buffer[hash as usize] == bits {
+            out[len] = ops::QOI_OP_INDEX | hash;
+            len += 1;
+        } else {
+            self.buffer[hash as usize] = bits;
+
+            let prev = Pixel::from_bits(self.prev);
+            if px.a == prev.a {
+                // The differences are taken with wrapping, so a difference of 255 becomes -1.
+                let vr = px.r.wrapping_sub(prev.r) as i8;
+                let vg = px.g.wrapping_sub(prev.g) as i8;
+                let vb = px.b.wrapping_sub(prev.b) as i8;
+
+                let vg_r = vr as i16 - vg as i16;
+                let vg_b = vb as i16 - vg as i16;
+
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    // Each difference is biased by 2.
+                    out[len] = ops::QOI_OP_DIFF
+                        | ((vr + 2) as u8) << 4
+                        | ((vg + 2) as u8) << 2
+                        | (vb + 2) as u8;
+                    len += 1;
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    // The green difference is biased by 32, the other two by 8.
+                    out[len] = ops::QOI_OP_LUMA | (vg + 32) as u8;
+                    out[len + 1] = ((vg_r + 8) as u8) << 4 | (vg_b + 8) as u8;
+                    len += 2;
+                } else {
+                    out[len] = ops::QOI_OP_RGB;
+                    out[len + 1] = px.r;
+                    out[len + 2] = px.g;
+                    out[len + 3] = px.b;
+                    len += 4;
+                }
+            } else {
+                out[len] = ops::QOI_OP_RGBA;
+                out[len + 1..len + 5].copy_from_slice(&px.to_bytes());
+                len += 5;
+            }
+        }
+
+        self.prev = bits;
+        len
+    }
+
+    /// Encodes every pixel in `pixels`, appending the ops to `out`. This produces the same ops as
+    /// calling [encode](EncoderState::encode) on each pixel.
+    ///
+    /// Runs are found by a tight loop that only compares packed pixels against the previous one,
+    /// consuming up to a full run at a time, so the hash and the buffer of previously seen pixels
+    /// are only touched for pixels that break a run.
+    pub(crate) fn encode_slice(&mut self, pixels: &[Pixel], out: &mut Vec<u8>) {
+        let mut buf = [0u8; MAX_ENCODED_PIXEL];
+        let mut rest = pixels;
+        while !rest.is_empty() {
+            let prev = self.prev;
+            let n = rest
+                .iter()
+                .take((MAX_RUN - self.run) as usize)
+                .take_while(|p| p.to_bits() == prev)
+                .count();
+            self.run += n as u8;
+            rest = &rest[n..];
+
+            if self.run == MAX_RUN {
+                let len = self.flush(&mut buf);
+                out.extend_from_slice(&buf[..len]);
+                continue;
+            }
+
+            // The run, if any, ended before this pixel.
+            let Some((&px, tail)) = rest.split_first() else {
+                break;
+            };
+            let len = self.encode(px, &mut buf);
+            out.extend_from_slice(&buf[..len]);
+            rest = tail;
+        }
+    }
+
+    /// Writes out the run currently being built, if any. This must be called after the last pixel
+    /// so that a trailing run is not lost.
+    pub(crate) fn flush(&mut self, out: &mut [u8]) -> usize {
+        if self.run == 0 {
+            return 0;
+        }
+
+        // Runs are biased by one, so a run of 1 is stored as 0.
+        out[0] = ops::QOI_OP_RUN | (self.run - 1);
+        self.run = 0;
+        1
+    }
+}
+
+/// Writes the [end marker](crate::dec::QOI_END_MARKER) that must follow the last op of an image.
+pub fn write_end_marker(w: &mut impl Write) -> std::io::Result<()> {
+    w.write_all(&ops::QOI_END_MARKER)
+}
+
+/// An upper bound on the size of `pixels` once encoded, header and end marker included.
+///
+/// Every pixel could take 5 bytes, but runs are much cheaper than that. This makes a single pass
+/// over the pixels, splitting out runs the same way the encoder does. Every run costs 1 byte per
+/// 62 pixels, and every other pixel is assumed to be the largest op it could be: `QOI_OP_RGB` (4
+/// bytes) when the alpha is unchanged, or `QOI_OP_RGBA` (5 bytes) otherwise. The estimate is never
+/// below the real size, and is exact for images made only of runs and alpha changes.
+pub fn estimate_encoded_size(pixels: &[Pixel]) -> usize {
+    let mut size = 14 + ops::QOI_END_MARKER.len();
+    let mut prev = Pixel::new(0, 0, 0, 255);
+    let mut run = 0;
+
+    for &px in pixels {
+        if px == prev {
+            run += 1;
+            if run == MAX_RUN {
+                size += 1;
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            size += 1;
+            run = 0;
+        }
+        size += if px.a == prev.a { 4 } else { 5 };
+        prev = px;
+    }
+
+    if run > 0 {
+        size += 1;
+    }
+    size
+}
+
+/// Converts raw pixel data with 3 (RGB) or 4 (RGBA) bytes per pixel into pixels. RGB pixels are
+/// fully opaque.
+fn bytes_to_pixels(bytes: &[u8], channels: usize) -> Vec<Pixel> {
+    bytes
+        .chunks_exact(channels)
+        .map(|p| match *p {
+            [r, g, b] => Pixel::new(r, g, b, 255),
+            [r, g, b, a] => Pixel::new(r, g, b, a),
+            _ => unreachable!("chunks_exact only produces 3 or 4 byte chunks here"),
+        })
+        .collect()
+}
+
+/// An encoder for QOI images.
+///
+/// This is the counterpart to [Decoder](crate::dec::Decoder): it takes a whole image in memory and
+/// writes out the encoded image, header and end marker included. See
+/// [EncoderReader](crate::stream::EncoderReader) for the streaming implementation.
+pub struct Encoder {
+    state: EncoderState,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    /// Creates a new Encoder with its default state, ready for encoding.
+    pub fn new() -> Self {
+        Encoder {
+            state: EncoderState::new(),
+        }
+    }
+
+    /// Encodes `pixels` as a QOI image described by `header`, writing it to `writer`. Returns the
+    /// number of bytes written.
+    ///
+    /// The pixels are in row-major order and there must be exactly `width * height` of them.
+    /// Writes are batched internally, so `writer` does not need to be buffered.
+    pub fn encode(
+        &mut self,
+        header: &Header,
+        pixels: &[Pixel],
+        writer: &mut impl Write,
+    ) -> Result<u64, Error> {
+        if pixels.len() as u64 != header.width as u64 * header.height as u64 {
+            return Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+
+        self.encode_batches(header, pixels.chunks(BATCH), writer)
+    }
+
+    /// Encodes raw, 8-bit pixel data as a QOI image described by `header`, writing it to `writer`.
+    /// Returns the number of bytes written.
+    ///
+    /// `bytes` holds 3 (RGB) or 4 (RGBA) bytes per pixel depending on the header's channels, and
+    /// must be exactly `width * height` pixels long. RGB pixels are encoded as fully opaque.
+    pub fn encode_bytes(
+        &mut self,
+        header: &Header,
+        bytes: &[u8],
+        writer: &mut impl Write,
+    ) -> Result<u64, Error> {
+        let channels = header.channels as usize;
+        if bytes.len() as u64 != header.width as u64 * header.height as u64 * channels as u64 {
+            return Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+
+        let batches = bytes
+            .chunks(channels * BATCH)
+            .map(|batch| bytes_to_pixels(batch, channels));
+        self.encode_batches(header, batches, writer)
+    }
+
+    /// Encodes raw, 8-bit pixel data read from `reader` as a QOI image described by `header`,
+    /// writing it to `writer`. Returns the number of bytes written.
+    ///
+    /// This is [encode_bytes](crate::enc::Encoder::encode_bytes) for data that doesn't fit in
+    /// memory, such as a large raw file: it is read and encoded a few thousand pixels at a time.
+    /// `reader` must hold exactly `width * height` pixels. As the image is written while reading,
+    /// `writer` is left with a partial image when it holds fewer or more, and
+    /// [Error::InvalidDimensions] is returned.
+    pub fn encode_reader(
+        &mut self,
+        header: &Header,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+    ) -> Result<u64, Error> {
+        let channels = header.channels as usize;
+        let mut remaining = header.width as u64 * header.height as u64;
+        let mut buf = vec![0u8; channels * BATCH];
+        let mut error = None;
+
+        let batches = std::iter::from_fn(|| {
+            if remaining == 0 {
+                return None;
+            }
+            let len = remaining.min(BATCH as u64) as usize * channels;
+            let batch = buf.get_mut(..len)?;
+            if let Err(e) = reader.read_exact(batch) {
+                error = Some(e);
+                return None;
+            }
+            remaining -= (len / channels) as u64;
+            Some(bytes_to_pixels(batch, channels))
+        });
+        let written = self.encode_batches(header, batches, writer)?;
+
+        let wrong_length = match error {
+            // The reader ran out before the last pixel,
+            Some(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => true,
+            Some(e) => return Err(e.into()),
+            // or has more after it.
+            None => reader.read(&mut [0u8])? != 0,
+        };
+        if wrong_length {
+            return Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+
+        Ok(written)
+    }
+
+    /// The main encoding loop, over batches of at most `BATCH` pixels. The caller is responsible
+    /// for checking that the batches add up to exactly `width * height` pixels.
+    fn encode_batches<B: AsRef<[Pixel]>>(
+        &mut self,
+        header: &Header,
+        batches: impl Iterator<Item = B>,
+        writer: &mut impl Write,
+    ) -> Result<u64, Error> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "encode",
+            width = header.width,
+            height = header.height,
+            channels = header.channels as u8,
+            bytes_out = tracing::field::Empty,
+        )
+        .entered();
+
+        // Reset the encoder's state, just in case this object is used more than once.
+        self.state = EncoderState::new();
+
+        let mut chunk: Vec<u8> = Vec::with_capacity(CHUNK);
+        let mut written = 0;
+
+        chunk.extend_from_slice(&header.to_bytes());
+
+        for batch in batches {
+            self.state.encode_slice(batch.as_ref(), &mut chunk);
+
+            // A batch adds at most `BATCH * MAX_ENCODED_PIXEL` bytes, so writing the chunk out
+            // once it is that close to full keeps it from growing past its capacity.
+            if chunk.len() > CHUNK - BATCH * MAX_ENCODED_PIXEL {
+                writer.write_all(&chunk)?;
+                written += chunk.len() as u64;
+                chunk.clear();
+            }
+        }
+
+        let mut out = [0u8; MAX_ENCODED_PIXEL];
+
+        let len = self.state.flush(&mut out);
+        chunk.extend_from_slice(&out[..len]);
+        write_end_marker(&mut chunk)?;
+
+        writer.write_all(&chunk)?;
+        written += chunk.len() as u64;
+
+        #[cfg(feature = "tracing")]
+        span.record("bytes_out", written);
+
+        Ok(written)
+    }
+
+    /// Encodes `pixels` as a QOI image described by `header` into a new file at `path`, replacing
+    /// it if it exists. Returns the number of bytes written.
+    ///
+    /// IO errors are returned as [Error::FileIo], with the path. The file isn't wrapped in a
+    /// `BufWriter`, since [encode](crate::enc::Encoder::encode) already writes in large chunks.
+    pub fn encode_to_path(
+        &mut self,
+        header: &Header,
+        pixels: &[Pixel],
+        path: impl AsRef<Path>,
+    ) -> Result<u64, Error> {
+        // Checked before creating the file, so that it isn't left empty.
+        if pixels.len() as u64 != header.width as u64 * header.height as u64 {
+            return Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+
+        let path = path.as_ref();
+        let with_path = |source| Error::FileIo {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let mut file = File::create(path).map_err(with_path)?;
+        self.encode(header, pixels, &mut file).map_err(|e| match e {
+            Error::Io(source) => with_path(source),
+            e => e,
+        })
+    }
+
+    /// Encodes `pixels` as a QOI image described by `header` into a new `Vec<u8>`.
+    ///
+    /// The `Vec` is allocated up front using [estimate_encoded_size], so it never has to grow.
+    pub fn encode_to_vec(&mut self, header: &Header, pixels: &[Pixel]) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::with_capacity(estimate_encoded_size(pixels));
+        self.encode(header, pixels, &mut data)?;
+        Ok(data)
+    }
+}
+
+impl Encoder {
+    /// Writes the header of a `width`x`height` image to `writer` and returns an [EncoderSession]
+    /// to feed it the pixels one at a time.
+    ///
+    /// The header is written and `writer` flushed right away, before any pixel is encoded, so the
+    /// start of the image can already be on its way (say, over a socket) while the pixels are
+    /// still being produced.
+    pub fn begin<W: Write>(
+        writer: &mut W,
+        width: u32,
+        height: u32,
+        channels: Channels,
+        colorspace: Colorspace,
+    ) -> Result<EncoderSession<'_, W>, Error> {
+        let header = Header::new(width, height, channels, colorspace);
+        writer.write_all(&header.to_bytes())?;
+        writer.flush()?;
+
+        Ok(EncoderSession {
+            writer,
+            state: EncoderState::new(),
+            width,
+            height,
+            fed: 0,
+            chunk: Vec::with_capacity(CHUNK),
+            written: 14,
+        })
+    }
+}
+
+/// An image being encoded a pixel at a time, started by [Encoder::begin].
+///
+/// The ops are collected and written in large chunks, so `writer` does not need to be buffered.
+/// Dropping a session without calling [finish](EncoderSession::finish) leaves `writer` with a
+/// partial image.
+pub struct EncoderSession<'a, W: Write> {
+    writer: &'a mut W,
+    state: EncoderState,
+    width: u32,
+    height: u32,
+    fed: u64,
+    chunk: Vec<u8>,
+    written: u64,
+}
+
+impl<W: Write> EncoderSession<'_, W> {
+    /// Encodes the next pixel, in row-major order. Fails with [Error::InvalidDimensions] once
+    /// every pixel of the image has been fed.
+    pub fn feed_pixel(&mut self, pixel: Pixel) -> Result<(), Error> {
+        if self.fed == self.num_pix() {
+            return Err(self.invalid_dimensions());
+        }
+        self.fed += 1;
+
+        let mut out = [0u8; MAX_ENCODED_PIXEL];
+        let len = self.state.encode(pixel, &mut out);
+        self.chunk.extend_from_slice(&out[..len]);
+
+        if self.chunk.len() > CHUNK - MAX_ENCODED_PIXEL {
+            self.writer.write_all(&self.chunk)?;
+            self.written += self.chunk.len() as u64;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes out the rest of the image and the end marker, and flushes `writer`. Returns the
+    /// number of bytes written over the whole session, header included.
+    ///
+    /// Fails with [Error::InvalidDimensions], without writing anything more, if fewer than `width
+    /// * height` pixels were fed.
+    pub fn finish(mut self) -> Result<u64, Error> {
+        if self.fed != self.num_pix() {
+            return Err(self.invalid_dimensions());
+        }
+
+        let mut out = [0u8; MAX_ENCODED_PIXEL];
+        let len = self.state.flush(&mut out);
+        self.chunk.extend_from_slice(&out[..len]);
+        write_end_marker(&mut self.chunk)?;
+
+        self.writer.write_all(&self.chunk)?;
+        self.writer.flush()?;
+        Ok(self.written + self.chunk.len() as u64)
+    }
+
+    fn num_pix(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn invalid_dimensions(&self) -> Error {
+        Error::InvalidDimensions {
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{ops, Channels, Colorspace, Decoder, Header, Pixel};
+    use crate::enc::Encoder;
+    use crate::utils::Error;
+
+    #[test]
+    fn test_encoder() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+
+        // The reference encoder made dice.qoi, so we should match it byte for byte.
+        assert!(encoded == data);
+    }
+
+    #[test]
+    fn test_run_bias() {
+        let header = Header::new(20, 10, Channels::RGBA, Colorspace::sRGB);
+        let color = Pixel::new(10, 20, 30, 255);
+        let img = vec![color; 200];
+
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+
+        // The first pixel can't be a run. The other 199 are split into runs of 62, 62, 62, and 13,
+        // each stored with a bias of -1. 63 and 64 would collide with QOI_OP_RGB/QOI_OP_RGBA.
+        let ops = &encoded[14..encoded.len() - 8];
+        assert_eq!(
+            ops,
+            [
+                ops::QOI_OP_RGB,
+                10,
+                20,
+                30,
+                ops::QOI_OP_RUN | 61,
+                ops::QOI_OP_RUN | 61,
+                ops::QOI_OP_RUN | 61,
+                ops::QOI_OP_RUN | 12,
+            ]
+        );
+        assert_eq!(&encoded[encoded.len() - 8..], ops::QOI_END_MARKER);
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+
+        // The starting pixel is opaque black, so this is all runs.
+        let img = vec![Pixel::new(0, 0, 0, 255); 200];
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+        assert_eq!(encoded.len(), 14 + 4 + 8);
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+    }
+
+    #[test]
+    fn test_run_then_index() {
+        let header = Header::new(12, 1, Channels::RGBA, Colorspace::sRGB);
+        let a = Pixel::new(10, 20, 30, 255);
+        let b = Pixel::new(200, 100, 50, 255);
+        let mut img = vec![a; 10];
+        img.extend([b, a]);
+
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+
+        // Only the first pixel of the run goes into the index. The pixels of the run itself don't
+        // touch it, so `a` is still found there once `b` has broken the run.
+        let ops = &encoded[14..encoded.len() - 8];
+        assert_eq!(
+            ops,
+            [
+                ops::QOI_OP_RGB,
+                10,
+                20,
+                30,
+                ops::QOI_OP_RUN | 8,
+                ops::QOI_OP_RGB,
+                200,
+                100,
+                50,
+                ops::QOI_OP_INDEX | (Decoder::hash_pixel(a) % 64),
+            ]
+        );
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+
+        // A run of the starting pixel never puts it in the index, as in the reference encoder, so
+        // it comes back as QOI_OP_RGB rather than QOI_OP_INDEX.
+        let black = Pixel::new(0, 0, 0, 255);
+        let mut img = vec![black; 10];
+        img.extend([Pixel::new(100, 100, 100, 255), black]);
+
+        let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+        let ops = &encoded[14..encoded.len() - 8];
+        assert_eq!(
+            ops,
+            [
+                ops::QOI_OP_RUN | 9,
+                ops::QOI_OP_RGB,
+                100,
+                100,
+                100,
+                ops::QOI_OP_RGB,
+                0,
+                0,
+                0,
+            ]
+        );
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+    }
+
+    #[test]
+    fn test_end_marker() {
+        use crate::enc::write_end_marker;
+
+        // The spec ends every image with seven 0x00 bytes and a 0x01.
+        assert_eq!(crate::QOI_END_MARKER, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+        let mut out = vec![0xff];
+        write_end_marker(&mut out).unwrap();
+        assert_eq!(out, [0xff, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_pixel_count() {
+        let header = Header::new(20, 10, Channels::RGBA, Colorspace::sRGB);
+        assert!(Encoder::new()
+            .encode_to_vec(&header, &[Pixel::default(); 199])
+            .is_err());
+    }
+
+    #[test]
+    fn test_estimate_encoded_size() {
+        use crate::enc::estimate_encoded_size;
+        use crate::testgen::{self, Pattern};
+
+        for pattern in Pattern::ALL {
+            let img = testgen::generate(pattern, 61, 37, 9);
+            let header = Header::new(61, 37, Channels::RGBA, Colorspace::sRGB);
+            let encoded = Encoder::new().encode_to_vec(&header, &img).unwrap();
+
+            let estimate = estimate_encoded_size(&img);
+            assert!(estimate >= encoded.len(), "{:?}: {} < {}", pattern, estimate, encoded.len());
+            assert!(estimate <= img.len() * 5 + 22);
+            // encode_to_vec never had to reallocate.
+            assert_eq!(encoded.capacity(), estimate);
+        }
+
+        // Runs are counted exactly: one RGB op, then runs of 62, 62, 62, and 13.
+        let img = vec![Pixel::new(10, 20, 30, 255); 200];
+        assert_eq!(estimate_encoded_size(&img), 14 + 4 + 4 + 8);
+
+        // The starting pixel is opaque black, so this is all runs.
+        assert_eq!(estimate_encoded_size(&[Pixel::new(0, 0, 0, 255); 124]), 14 + 2 + 8);
+        assert_eq!(estimate_encoded_size(&[]), 22);
+    }
+
+    #[test]
+    fn test_encode_to_path() {
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let path = std::env::temp_dir().join("qoi-parser-encode-to-path.qoi");
+        let written = Encoder::new().encode_to_path(&header, &img, &path).unwrap();
+        let encoded = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, encoded.len() as u64);
+        assert_eq!(encoded, Encoder::new().encode_to_vec(&header, &img).unwrap());
+
+        // A file can't be created inside a directory that doesn't exist.
+        let path = std::env::temp_dir().join("qoi-parser-missing-dir/out.qoi");
+        match Encoder::new().encode_to_path(&header, &img, &path) {
+            Err(Error::FileIo { path: p, source }) => {
+                assert_eq!(p, path);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            res => panic!("expected Error::FileIo, got {:?}", res),
+        }
+
+        // Nothing is created for the wrong number of pixels.
+        let path = std::env::temp_dir().join("qoi-parser-encode-to-path-short.qoi");
+        let res = Encoder::new().encode_to_path(&header, &img[1..], &path);
+        assert!(matches!(res, Err(Error::InvalidDimensions { .. })));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_encode_bytes() {
+        let img = vec![Pixel::new(1, 2, 3, 255), Pixel::new(4, 5, 6, 255)];
+        let rgba = crate::dec::pixels_to_bytes(&img);
+        let rgb = crate::dec::pixels_to_rgb_bytes(&img);
+
+        let header = Header::new(2, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut encoded = Vec::new();
+        Encoder::new().encode_bytes(&header, &rgba, &mut encoded).unwrap();
+        assert_eq!(encoded, Encoder::new().encode_to_vec(&header, &img).unwrap());
+
+        let header = Header::new(2, 1, Channels::RGB, Colorspace::sRGB);
+        let mut encoded = Vec::new();
+        Encoder::new().encode_bytes(&header, &rgb, &mut encoded).unwrap();
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+
+        // Passing RGBA bytes for an RGB header is the wrong length.
+        assert!(Encoder::new()
+            .encode_bytes(&header, &rgba, &mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_encode_reader() {
+        // More than one batch, with a partial one at the end.
+        let img = crate::testgen::mixed(97, 61, 5);
+        let rgba = crate::dec::pixels_to_bytes(&img);
+        let rgb = crate::dec::pixels_to_rgb_bytes(&img);
+
+        for (channels, bytes) in [(Channels::RGBA, &rgba), (Channels::RGB, &rgb)] {
+            let header = Header::new(97, 61, channels, Colorspace::sRGB);
+            let mut expected = Vec::new();
+            Encoder::new().encode_bytes(&header, bytes, &mut expected).unwrap();
+
+            let mut encoded = Vec::new();
+            let written = Encoder::new()
+                .encode_reader(&header, &mut &bytes[..], &mut encoded)
+                .unwrap();
+            assert_eq!(written, encoded.len() as u64);
+            assert!(encoded == expected);
+
+            // Too short and too long are both the wrong size for the header.
+            for len in [bytes.len() - 1, bytes.len() + 1] {
+                let mut bytes = bytes.clone();
+                bytes.resize(len, 0);
+                let err = Encoder::new()
+                    .encode_reader(&header, &mut &bytes[..], &mut Vec::new())
+                    .unwrap_err();
+                assert!(matches!(err, Error::InvalidDimensions { width: 97, height: 61 }));
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoder_session() {
+        let img = crate::testgen::mixed(97, 61, 5);
+        let header = Header::new(97, 61, Channels::RGBA, Colorspace::sRGB);
+        let expected = Encoder::new().encode_to_vec(&header, &img).unwrap();
+
+        // The header is written before any pixel is fed.
+        let mut encoded = Vec::new();
+        drop(Encoder::begin(&mut encoded, 97, 61, Channels::RGBA, Colorspace::sRGB).unwrap());
+        assert_eq!(encoded, header.to_bytes());
+
+        let mut encoded = Vec::new();
+        let mut session =
+            Encoder::begin(&mut encoded, 97, 61, Channels::RGBA, Colorspace::sRGB).unwrap();
+        for &px in &img {
+            session.feed_pixel(px).unwrap();
+        }
+        let err = session.feed_pixel(img[0]).unwrap_err();
+        assert!(matches!(err, Error::InvalidDimensions { width: 97, height: 61 }));
+        assert_eq!(session.finish().unwrap(), expected.len() as u64);
+        assert!(encoded == expected);
+
+        // Finishing early fails, and writes nothing past what was already encoded.
+        let mut encoded = Vec::new();
+        let mut session =
+            Encoder::begin(&mut encoded, 2, 2, Channels::RGB, Colorspace::Linear).unwrap();
+        session.feed_pixel(img[0]).unwrap();
+        let err = session.finish().unwrap_err();
+        assert!(matches!(err, Error::InvalidDimensions { width: 2, height: 2 }));
+        assert_eq!(encoded.len(), 14);
+    }
+
+    /// The encoder as it was before runs were found a slice at a time and pixels compared packed:
+    /// one pixel per step, straight from the reference implementation.
+    fn reference_encode(header: &Header, pixels: &[Pixel]) -> Vec<u8> {
+        let mut out = header.to_bytes().to_vec();
+        let mut prev = Pixel::new(0, 0, 0, 255);
+        let mut seen = [Pixel::default(); 64];
+        let mut run = 0u8;
+
+        for (i, &px) in pixels.iter().enumerate() {
+            if px == prev {
+                run += 1;
+                if run == 62 || i == pixels.len() - 1 {
+                    out.push(ops::QOI_OP_RUN | (run - 1));
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                out.push(ops::QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+
+            let hash = Decoder::hash_pixel(px) % 64;
+            if seen[hash as usize] == px {
+                out.push(ops::QOI_OP_INDEX | hash);
+            } else if px.a != prev.a {
+                seen[hash as usize] = px;
+                out.push(ops::QOI_OP_RGBA);
+                out.extend(px.to_bytes());
+            } else {
+                seen[hash as usize] = px;
+                let vr = px.r.wrapping_sub(prev.r) as i8;
+                let vg = px.g.wrapping_sub(prev.g) as i8;
+                let vb = px.b.wrapping_sub(prev.b) as i8;
+                let vg_r = vr as i16 - vg as i16;
+                let vg_b = vb as i16 - vg as i16;
+
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    out.push(
+                        ops::QOI_OP_DIFF
+                            | ((vr + 2) as u8) << 4
+                            | ((vg + 2) as u8) << 2
+                            | (vb + 2) as u8,
+                    );
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    out.push(ops::QOI_OP_LUMA | (vg + 32) as u8);
+                    out.push(((vg_r + 8) as u8) << 4 | (vg_b + 8) as u8);
+                } else {
+                    out.push(ops::QOI_OP_RGB);
+                    out.extend([px.r, px.g, px.b]);
+                }
+            }
+            prev = px;
+        }
+
+        out.extend(ops::QOI_END_MARKER);
+        out
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        use crate::testgen::{self, Pattern};
+
+        let check = |name: &str, header: &Header, img: &[Pixel]| {
+            let expected = reference_encode(header, img);
+            let encoded = Encoder::new().encode_to_vec(header, img).unwrap();
+            assert!(encoded == expected, "{}: encode differs", name);
+
+            let mut from_bytes = Vec::new();
+            Encoder::new()
+                .encode_bytes(header, &crate::dec::pixels_to_bytes(img), &mut from_bytes)
+                .unwrap();
+            assert!(from_bytes == expected, "{}: encode_bytes differs", name);
+        };
+
+        // Sizes around the batch size, so that runs cross from one batch into the next.
+        for pattern in Pattern::ALL {
+            for (width, height, seed) in [(64, 64, 1), (4095, 1, 2), (4097, 3, 3), (1, 1, 4)] {
+                let img = testgen::generate(pattern, width, height, seed);
+                let header = Header::new(width, height, Channels::RGBA, Colorspace::sRGB);
+                check(&format!("{:?} {}x{}", pattern, width, height), &header, &img);
+            }
+        }
+
+        // Runs of every length up to a few full runs, ending exactly on a batch boundary too.
+        for len in [1, 61, 62, 63, 124, 125, 4096, 4096 * 2 + 1] {
+            let mut img = vec![Pixel::new(0, 0, 0, 255); len];
+            img.extend(vec![Pixel::new(9, 9, 9, 255); len]);
+            let header = Header::new(img.len() as u32, 1, Channels::RGBA, Colorspace::sRGB);
+            check(&format!("runs of {}", len), &header, &img);
+        }
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+        check("dice.qoi", &header, &img);
+    }
+}
@@ -0,0 +1,416 @@
+//! A C interface to the streaming decoder, for pipelines that receive QOI images in chunks (off a
+//! socket, for example) and want the pixels pushed to a callback as they are decoded.
+//!
+//! The declarations are in `include/qoip.h`. Build the library for C with
+//! `cargo rustc --release --lib --features ffi --crate-type staticlib` (or `cdylib`).
+//!
+//! Every function returns one of the `QOIP_*` codes and never unwinds into C: a panic is caught
+//! and reported as [QOIP_ERR_PANIC].
+
+use crate::dec::{Header, Pixel};
+use crate::stream::{StreamDecoder, StreamDecoderOutput};
+use std::ffi::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Success. For [qoip_stream_feed], the whole chunk was consumed and the image needs more bytes.
+pub const QOIP_OK: c_int = 0;
+/// [qoip_stream_feed] decoded the last pixel of the image and checked its end marker.
+pub const QOIP_DONE: c_int = 1;
+/// A required pointer was null.
+pub const QOIP_ERR_NULL: c_int = -1;
+/// The data is not a valid QOI image.
+pub const QOIP_ERR_DECODE: c_int = -2;
+/// The image (or its header) hasn't been fully decoded yet.
+pub const QOIP_ERR_INCOMPLETE: c_int = -3;
+/// A panic was caught. The handle should be freed.
+pub const QOIP_ERR_PANIC: c_int = -4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QoipPixel {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl From<Pixel> for QoipPixel {
+    fn from(p: Pixel) -> Self {
+        QoipPixel {
+            r: p.r,
+            g: p.g,
+            b: p.b,
+            a: p.a,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QoipHeader {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: u8,
+}
+
+/// Called with every run of identical pixels as it is decoded: the pixel, how many times it
+/// repeats, and the index of the first of them in the image. `user` is passed through untouched.
+pub type QoipPixelCb = Option<
+    unsafe extern "C" fn(
+        user: *mut c_void,
+        run_pixel: *const QoipPixel,
+        run_len: u32,
+        start_index: u64,
+    ),
+>;
+
+/// The opaque handle given to C.
+pub struct QoipStream {
+    sdec: StreamDecoder,
+    header: Option<Header>,
+    next_index: u64,
+    consumed: usize,
+    failed: bool,
+}
+
+/// Runs `f`, turning a panic into [QOIP_ERR_PANIC].
+fn guard(f: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(QOIP_ERR_PANIC)
+}
+
+/// Creates a new stream decoder. Returns null if it couldn't be created. Free it with
+/// [qoip_stream_free].
+#[no_mangle]
+pub extern "C" fn qoip_stream_new() -> *mut QoipStream {
+    panic::catch_unwind(|| Box::into_raw(Box::new(QoipStream::new())))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Feeds `len` bytes at `data` to the decoder, calling `cb` with every run of pixels decoded.
+///
+/// Returns [QOIP_OK] once the whole chunk is consumed, or [QOIP_DONE] as soon as the 8 byte end
+/// marker after the last pixel has been read and checked. In that case the rest of the chunk (the
+/// next image, say) is left alone, and [qoip_stream_consumed] tells how much of it was used, end
+/// marker included. A wrong end marker is [QOIP_ERR_DECODE]. After [QOIP_DONE] or an error, call
+/// [qoip_stream_finish] before feeding the next image.
+///
+/// # Safety
+///
+/// `handle` must come from [qoip_stream_new], and `data` must point to `len` readable bytes
+/// (it may be null if `len` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn qoip_stream_feed(
+    handle: *mut QoipStream,
+    data: *const u8,
+    len: usize,
+    cb: QoipPixelCb,
+    user: *mut c_void,
+) -> c_int {
+    guard(|| {
+        let (stream, cb) = match (handle.as_mut(), cb) {
+            (Some(stream), Some(cb)) if !data.is_null() || len == 0 => (stream, cb),
+            _ => return QOIP_ERR_NULL,
+        };
+        let data = match len {
+            0 => &[][..],
+            _ => std::slice::from_raw_parts(data, len),
+        };
+        stream.feed(data, |pix, run_len, start| cb(user, &pix, run_len, start))
+    })
+}
+
+/// The number of bytes of the chunk passed to the last [qoip_stream_feed] call that were used.
+/// This is the whole chunk unless the image, end marker included, finished partway through it.
+///
+/// # Safety
+///
+/// `handle` must come from [qoip_stream_new] or be null, in which case 0 is returned.
+#[no_mangle]
+pub unsafe extern "C" fn qoip_stream_consumed(handle: *const QoipStream) -> usize {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        handle.as_ref().map_or(0, |s| s.consumed)
+    }))
+    .unwrap_or(0)
+}
+
+/// Copies the header of the image being decoded into `out`. Returns [QOIP_ERR_INCOMPLETE] until
+/// all 14 header bytes have been fed.
+///
+/// # Safety
+///
+/// `handle` must come from [qoip_stream_new], and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn qoip_stream_header(
+    handle: *const QoipStream,
+    out: *mut QoipHeader,
+) -> c_int {
+    guard(|| {
+        let (stream, out) = match (handle.as_ref(), out.as_mut()) {
+            (Some(stream), Some(out)) => (stream, out),
+            _ => return QOIP_ERR_NULL,
+        };
+        match &stream.header {
+            Some(h) => {
+                *out = QoipHeader {
+                    width: h.width,
+                    height: h.height,
+                    channels: h.channels as u8,
+                    colorspace: h.colorspace as u8,
+                };
+                QOIP_OK
+            }
+            None => QOIP_ERR_INCOMPLETE,
+        }
+    })
+}
+
+/// Ends the current image and resets the decoder, ready for the next image. Returns [QOIP_OK] if
+/// every pixel and the end marker were decoded, [QOIP_ERR_DECODE] if decoding failed, or
+/// [QOIP_ERR_INCOMPLETE] if the image was cut short.
+///
+/// # Safety
+///
+/// `handle` must come from [qoip_stream_new].
+#[no_mangle]
+pub unsafe extern "C" fn qoip_stream_finish(handle: *mut QoipStream) -> c_int {
+    guard(|| {
+        let stream = match handle.as_mut() {
+            Some(stream) => stream,
+            None => return QOIP_ERR_NULL,
+        };
+        let res = match (stream.failed, stream.sdec.is_finished()) {
+            (true, _) => QOIP_ERR_DECODE,
+            (false, true) => QOIP_OK,
+            (false, false) => QOIP_ERR_INCOMPLETE,
+        };
+        *stream = QoipStream::new();
+        res
+    })
+}
+
+/// Frees a handle from [qoip_stream_new]. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must come from [qoip_stream_new] or be null, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn qoip_stream_free(handle: *mut QoipStream) {
+    if !handle.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+    }
+}
+
+impl QoipStream {
+    fn new() -> Self {
+        QoipStream {
+            sdec: StreamDecoder::new(),
+            header: None,
+            next_index: 0,
+            consumed: 0,
+            failed: false,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8], mut on_run: impl FnMut(QoipPixel, u32, u64)) -> c_int {
+        self.consumed = 0;
+        if self.failed {
+            return QOIP_ERR_DECODE;
+        }
+        if self.sdec.is_finished() {
+            return QOIP_DONE;
+        }
+
+        // feed_slice stops right after the end marker, leaving the rest of the chunk alone.
+        let res = self.sdec.feed_slice(data, |out| match out {
+            StreamDecoderOutput::HeaderDone(h) => self.header = Some(h),
+            StreamDecoderOutput::Pixels(mut it) => {
+                let run_len = it.len() as u32;
+                if let Some(pix) = it.next() {
+                    on_run(pix.into(), run_len, self.next_index);
+                    self.next_index += run_len as u64;
+                }
+            }
+            _ => {}
+        });
+        match res {
+            Ok(used) => self.consumed = used,
+            Err(_) => {
+                self.failed = true;
+                return QOIP_ERR_DECODE;
+            }
+        }
+
+        match self.sdec.is_finished() {
+            true => QOIP_DONE,
+            false => QOIP_OK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{Decoder, Pixel};
+    use crate::ffi::*;
+    use std::ffi::c_void;
+
+    /// Rebuilds the image from the runs handed to the callback, checking that they arrive in
+    /// order.
+    unsafe extern "C" fn collect(
+        user: *mut c_void,
+        run_pixel: *const QoipPixel,
+        run_len: u32,
+        start_index: u64,
+    ) {
+        let img = &mut *(user as *mut Vec<Pixel>);
+        assert_eq!(img.len() as u64, start_index);
+        let p = *run_pixel;
+        img.extend(std::iter::repeat_n(
+            Pixel::new(p.r, p.g, p.b, p.a),
+            run_len as usize,
+        ));
+    }
+
+    #[test]
+    fn test_stream_chunks() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        // Two images back to back, arriving in network sized chunks. The first chunk splits the
+        // header, and the end of the first image and the start of the second share a chunk.
+        let frames = [data.clone(), data].concat();
+        let chunks = std::iter::once(&frames[..9]).chain(frames[9..].chunks(1500));
+        let mut images = Vec::new();
+        let mut img: Vec<Pixel> = Vec::new();
+        let user = &mut img as *mut Vec<Pixel> as *mut c_void;
+
+        unsafe {
+            let handle = qoip_stream_new();
+            assert!(!handle.is_null());
+
+            for mut chunk in chunks {
+                while !chunk.is_empty() {
+                    match qoip_stream_feed(handle, chunk.as_ptr(), chunk.len(), Some(collect), user)
+                    {
+                        QOIP_OK => {
+                            assert_eq!(qoip_stream_consumed(handle), chunk.len());
+                            break;
+                        }
+                        QOIP_DONE => {
+                            // The end marker is consumed too.
+                            chunk = &chunk[qoip_stream_consumed(handle)..];
+
+                            let mut out = QoipHeader {
+                                width: 0,
+                                height: 0,
+                                channels: 0,
+                                colorspace: 0,
+                            };
+                            assert_eq!(qoip_stream_header(handle, &mut out), QOIP_OK);
+                            assert_eq!((out.width, out.height), (header.width, header.height));
+                            assert_eq!(qoip_stream_finish(handle), QOIP_OK);
+                            images.push(std::mem::take(&mut *(user as *mut Vec<Pixel>)));
+                        }
+                        err => panic!("feed failed with {}", err),
+                    }
+                }
+            }
+
+            qoip_stream_free(handle);
+        }
+
+        assert_eq!(images.len(), 2);
+        assert!(images.iter().all(|img| *img == expected));
+    }
+
+    #[test]
+    fn test_stream_end_marker() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut img: Vec<Pixel> = Vec::new();
+        let user = &mut img as *mut Vec<Pixel> as *mut c_void;
+
+        unsafe {
+            let handle = qoip_stream_new();
+
+            // Cut off in the end marker, every pixel is out but the image isn't done.
+            let short = &data[..data.len() - 3];
+            assert_eq!(
+                qoip_stream_feed(handle, short.as_ptr(), short.len(), Some(collect), user),
+                QOIP_OK
+            );
+            assert_eq!(qoip_stream_finish(handle), QOIP_ERR_INCOMPLETE);
+
+            // A wrong end marker.
+            let mut bad = data.clone();
+            *bad.last_mut().unwrap() = 2;
+            (*(user as *mut Vec<Pixel>)).clear();
+            assert_eq!(
+                qoip_stream_feed(handle, bad.as_ptr(), bad.len(), Some(collect), user),
+                QOIP_ERR_DECODE
+            );
+            assert_eq!(qoip_stream_finish(handle), QOIP_ERR_DECODE);
+
+            // Feeding after the end of the image uses nothing until the decoder is finished.
+            (*(user as *mut Vec<Pixel>)).clear();
+            assert_eq!(
+                qoip_stream_feed(handle, data.as_ptr(), data.len(), Some(collect), user),
+                QOIP_DONE
+            );
+            assert_eq!(qoip_stream_consumed(handle), data.len());
+            assert_eq!(
+                qoip_stream_feed(handle, data.as_ptr(), data.len(), Some(collect), user),
+                QOIP_DONE
+            );
+            assert_eq!(qoip_stream_consumed(handle), 0);
+            assert_eq!(qoip_stream_finish(handle), QOIP_OK);
+
+            qoip_stream_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        unsafe {
+            assert_eq!(qoip_stream_finish(std::ptr::null_mut()), QOIP_ERR_NULL);
+            qoip_stream_free(std::ptr::null_mut());
+
+            let handle = qoip_stream_new();
+            let user = std::ptr::null_mut();
+            assert_eq!(
+                qoip_stream_feed(handle, std::ptr::null(), 4, Some(collect), user),
+                QOIP_ERR_NULL
+            );
+            assert_eq!(
+                qoip_stream_feed(handle, b"qoif".as_ptr(), 4, None, user),
+                QOIP_ERR_NULL
+            );
+
+            let mut out = QoipHeader {
+                width: 0,
+                height: 0,
+                channels: 0,
+                colorspace: 0,
+            };
+            assert_eq!(qoip_stream_header(handle, &mut out), QOIP_ERR_INCOMPLETE);
+            assert_eq!(qoip_stream_finish(handle), QOIP_ERR_INCOMPLETE);
+
+            // Errors stick until the decoder is finished.
+            let bad = b"qoix";
+            assert_eq!(
+                qoip_stream_feed(handle, bad.as_ptr(), 4, Some(collect), user),
+                QOIP_ERR_DECODE
+            );
+            assert_eq!(
+                qoip_stream_feed(handle, b"q".as_ptr(), 1, Some(collect), user),
+                QOIP_ERR_DECODE
+            );
+            assert_eq!(qoip_stream_finish(handle), QOIP_ERR_DECODE);
+            assert_eq!(
+                qoip_stream_feed(handle, b"qoif".as_ptr(), 4, Some(collect), user),
+                QOIP_OK
+            );
+
+            qoip_stream_free(handle);
+        }
+    }
+}
@@ -0,0 +1,893 @@
+use crate::dec::{
+    into_error, linear_to_srgb, srgb_to_linear, Channels, Colorspace, Decoder, Header, Pixel,
+};
+use crate::enc::Encoder;
+use crate::utils::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A fully decoded QOI image: its header and every pixel in row-major order.
+///
+/// The number of pixels always matches the width and height in the header. The fields are kept
+/// private so that this can't be broken after construction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QoiImage {
+    header: Header,
+    pixels: Vec<Pixel>,
+}
+
+impl QoiImage {
+    /// Creates an image from a header and its pixels. Fails if the number of pixels doesn't match
+    /// the width and height in the header.
+    pub fn new(header: Header, pixels: Vec<Pixel>) -> Result<Self, Error> {
+        if pixels.len() as u64 != header.width as u64 * header.height as u64 {
+            return Err(Error::InvalidDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+
+        Ok(QoiImage { header, pixels })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn width(&self) -> u32 {
+        self.header.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.header.height
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    /// Iterates over the rows of the image from top to bottom. Every row is `width` pixels long,
+    /// as the image always holds exactly `width * height` pixels.
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &[Pixel]> + ExactSizeIterator {
+        self.pixels.chunks_exact(self.row_len())
+    }
+
+    /// Like [rows](crate::img::QoiImage::rows), with each row mutable.
+    pub fn rows_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = &mut [Pixel]> + ExactSizeIterator {
+        let width = self.row_len();
+        self.pixels.chunks_exact_mut(width)
+    }
+
+    /// The length of the chunks the rows are taken in. An image with a width of 0 has no pixels,
+    /// but chunks can't have a size of 0.
+    fn row_len(&self) -> usize {
+        usize::max(self.header.width as usize, 1)
+    }
+
+    /// Iterates over the rows of the image from top to bottom, along with their index.
+    ///
+    /// Rows can also be taken from the bottom up with `.rev()`, which is handy for formats like BMP
+    /// that store rows bottom to top, or for flipping an image vertically.
+    pub fn iter_rows(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (usize, &[Pixel])> + ExactSizeIterator {
+        self.rows().enumerate()
+    }
+
+    /// Iterates over every pixel in row-major order along with its `(x, y)` coordinates, like
+    /// `image`'s `enumerate_pixels`.
+    pub fn enumerate_pixels(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (u32, u32, Pixel)> + ExactSizeIterator + '_ {
+        // A width of 0 means there are no pixels, so this never divides by 0.
+        let width = self.header.width;
+        self.pixels.iter().enumerate().map(move |(i, &p)| {
+            let i = i as u64;
+            ((i % width as u64) as u32, (i / width as u64) as u32, p)
+        })
+    }
+
+    /// Replaces every pixel with the result of `f`, which is given the pixel's `(x, y)`
+    /// coordinates and its current value. Pixels are visited in row-major order.
+    pub fn map_pixels(&mut self, mut f: impl FnMut(u32, u32, Pixel) -> Pixel) -> &mut Self {
+        for (y, row) in self.rows_mut().enumerate() {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = f(x as u32, y as u32, *p);
+            }
+        }
+        self
+    }
+
+    /// Resizes the image to `new_width`x`new_height` using nearest-neighbor sampling.
+    ///
+    /// Each output pixel at `(x, y)` is copied from `(x * width / new_width, y * height /
+    /// new_height)` in this image. This only uses integer arithmetic, so the result is the same on
+    /// every platform. An image with no pixels has nothing to sample from, so it scales to
+    /// transparent black.
+    pub fn scale_nearest(&self, new_width: u32, new_height: u32) -> QoiImage {
+        let (width, height) = (self.header.width as u64, self.header.height as u64);
+        let len = new_width as usize * new_height as usize;
+
+        let pixels = if self.pixels.is_empty() {
+            vec![Pixel::default(); len]
+        } else {
+            let mut pixels = Vec::with_capacity(len);
+            for y in 0..new_height as u64 {
+                let row = (y * height / new_height as u64 * width) as usize;
+                for x in 0..new_width as u64 {
+                    let src_x = (x * width / new_width as u64) as usize;
+                    pixels.push(self.pixels[row + src_x]);
+                }
+            }
+            pixels
+        };
+
+        let header = Header {
+            width: new_width,
+            height: new_height,
+            ..self.header.clone()
+        };
+        QoiImage { header, pixels }
+    }
+
+    /// Copies the `width`x`height` rectangle with its top left corner at `(x, y)` into a new image,
+    /// with the same channels and colorspace. The result can be encoded again as it is, which makes
+    /// this the building block of cropping a QOI file.
+    ///
+    /// Fails with [Error::InvalidDimensions] if the rectangle doesn't fit inside the image. An
+    /// empty rectangle is fine, as long as its corner is inside or on the edge of the image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<QoiImage, Error> {
+        let fits =
+            |start: u32, len: u32, max: u32| start.checked_add(len).is_some_and(|end| end <= max);
+        if !fits(x, width, self.header.width) || !fits(y, height, self.header.height) {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        let (x, width) = (x as usize, width as usize);
+        let mut pixels = Vec::with_capacity(width * height as usize);
+        for (_, row) in self.iter_rows().skip(y as usize).take(height as usize) {
+            pixels.extend_from_slice(&row[x..x + width]);
+        }
+
+        let header = Header {
+            width: width as u32,
+            height,
+            ..self.header.clone()
+        };
+        Ok(QoiImage { header, pixels })
+    }
+
+    /// Mirrors the image top to bottom, in place.
+    pub fn flip_vertical(&mut self) -> &mut Self {
+        let width = self.row_len();
+        let half = self.header.height as usize / 2;
+        // With an odd height, the middle row stays where it is. It's the first row of `bottom`,
+        // which the zip never reaches.
+        let (top, bottom) = self.pixels.split_at_mut(half * width);
+        for (a, b) in top
+            .chunks_exact_mut(width)
+            .zip(bottom.rchunks_exact_mut(width))
+        {
+            a.swap_with_slice(b);
+        }
+        self
+    }
+
+    /// Mirrors the image left to right, in place.
+    pub fn flip_horizontal(&mut self) -> &mut Self {
+        for row in self.rows_mut() {
+            row.reverse();
+        }
+        self
+    }
+
+    /// Rotates the image a quarter turn clockwise. The width and height are swapped.
+    pub fn rotate90(&self) -> QoiImage {
+        // Each column of this image, read from the bottom up, is a row of the result. Rows of the
+        // result are written whole, so only the reads are strided.
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for x in 0..self.header.width as usize {
+            pixels.extend(self.rows().rev().map(|row| row[x]));
+        }
+        self.quarter_turned(pixels)
+    }
+
+    /// Rotates the image half a turn.
+    pub fn rotate180(&self) -> QoiImage {
+        let pixels = self.pixels.iter().rev().copied().collect();
+        QoiImage {
+            header: self.header.clone(),
+            pixels,
+        }
+    }
+
+    /// Rotates the image a quarter turn counterclockwise. The width and height are swapped.
+    pub fn rotate270(&self) -> QoiImage {
+        // Each column of this image, read from the top down, is a row of the result, starting
+        // with the rightmost column.
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for x in (0..self.header.width as usize).rev() {
+            pixels.extend(self.rows().map(|row| row[x]));
+        }
+        self.quarter_turned(pixels)
+    }
+
+    /// The image made of `pixels`, with the width and height of this one swapped.
+    fn quarter_turned(&self, pixels: Vec<Pixel>) -> QoiImage {
+        let header = Header {
+            width: self.header.height,
+            height: self.header.width,
+            ..self.header.clone()
+        };
+        QoiImage { header, pixels }
+    }
+
+    /// Replaces the red, green, and blue channels of every pixel with their entry in `lut`. Alpha
+    /// is left alone.
+    ///
+    /// Any per-channel curve can be precomputed into the table, such as gamma correction, contrast,
+    /// or sRGB to linear conversion.
+    pub fn apply_lookup_table(&mut self, lut: &[u8; 256]) -> &mut Self {
+        self.apply_channel_lookup_tables(lut, lut, lut)
+    }
+
+    /// Like [apply_lookup_table](crate::img::QoiImage::apply_lookup_table), with a separate table
+    /// for each of the red, green, and blue channels.
+    pub fn apply_channel_lookup_tables(
+        &mut self,
+        r: &[u8; 256],
+        g: &[u8; 256],
+        b: &[u8; 256],
+    ) -> &mut Self {
+        self.pixels.iter_mut().for_each(|p| {
+            p.r = r[p.r as usize];
+            p.g = g[p.g as usize];
+            p.b = b[p.b as usize];
+        });
+        self
+    }
+
+    /// Returns a copy of the image converted to the `target` colorspace, with the colorspace in
+    /// its header updated to match. Alpha is left alone, and an image already in `target` is
+    /// simply cloned.
+    ///
+    /// The red, green, and blue channels go through the full sRGB transfer function of IEC
+    /// 61966-2-1, not a plain 2.2 gamma, and are rounded to the nearest byte. 8 bits aren't enough
+    /// for linear light, so converting to linear loses detail in dark colors, and converting
+    /// back doesn't restore it.
+    pub fn convert_colorspace(&self, target: Colorspace) -> QoiImage {
+        let mut img = self.clone();
+        if self.header.colorspace == target {
+            return img;
+        }
+
+        let curve = match target {
+            Colorspace::Linear => srgb_to_linear,
+            Colorspace::sRGB => linear_to_srgb,
+        };
+        let lut = std::array::from_fn(|i| (curve(i as f32 / 255.) * 255.).round() as u8);
+        img.apply_lookup_table(&lut);
+        img.header.colorspace = target;
+        img
+    }
+
+    /// Splits the pixels into separate red, green, blue, and alpha channels, each `width *
+    /// height` bytes long and in row-major order. Images with
+    /// [Channels::RGB](crate::dec::Channels::RGB) have an alpha channel too, normally all 255.
+    pub fn split_channels(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>) {
+        let len = self.pixels.len();
+        let (mut r, mut g, mut b, mut a) = (
+            Vec::with_capacity(len),
+            Vec::with_capacity(len),
+            Vec::with_capacity(len),
+            Vec::with_capacity(len),
+        );
+        for p in &self.pixels {
+            r.push(p.r);
+            g.push(p.g);
+            b.push(p.b);
+            a.push(p.a);
+        }
+        (r, g, b, a)
+    }
+
+    /// Builds an RGBA, sRGB image from separate channels, the reverse of
+    /// [split_channels](crate::img::QoiImage::split_channels). Fails with
+    /// [Error::InvalidDimensions] unless every channel is exactly `width * height` bytes long.
+    pub fn merge_channels(
+        r: &[u8],
+        g: &[u8],
+        b: &[u8],
+        a: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<QoiImage, Error> {
+        let len = width as u64 * height as u64;
+        if [r, g, b, a].iter().any(|c| c.len() as u64 != len) {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        let pixels = r
+            .iter()
+            .zip(g)
+            .zip(b)
+            .zip(a)
+            .map(|(((&r, &g), &b), &a)| Pixel::new(r, g, b, a))
+            .collect();
+        let header = Header::new(width, height, Channels::RGBA, Colorspace::sRGB);
+        QoiImage::new(header, pixels)
+    }
+
+    /// Splits the image back into its header and pixels.
+    pub fn into_parts(self) -> (Header, Vec<Pixel>) {
+        (self.header, self.pixels)
+    }
+
+    /// Opens the image file at `path`. QOI files are recognized by their magic bytes. With the
+    /// `image` feature, any other format the image crate can read is opened with it, as an sRGB
+    /// image with [Channels::RGB](crate::dec::Channels::RGB) when it has no alpha. Without it,
+    /// anything other than QOI fails to parse.
+    ///
+    /// IO errors are returned as [Error::FileIo], with the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<QoiImage, Error> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|source| Error::FileIo {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        #[cfg(feature = "image")]
+        if !crate::dec::is_qoi(&data) {
+            return open_other(&data);
+        }
+
+        let (header, pixels) = Decoder::new().decode_slice(&data).map_err(into_error)?;
+        QoiImage::new(header, pixels)
+    }
+
+    /// Saves the image as a QOI file at `path`, replacing it if it exists. Returns the number of
+    /// bytes written.
+    ///
+    /// The image is written to a temporary file next to `path`, which is then renamed over it, so
+    /// `path` never holds a partial image. IO errors are returned as [Error::FileIo], with `path`.
+    pub fn save_qoi(&self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        write_atomic(path.as_ref(), |file| write_qoi(self, file))
+    }
+
+    /// Saves the image as a PNG file at `path`, replacing it if it exists. Images with
+    /// [Channels::RGB](crate::dec::Channels::RGB) are saved without alpha.
+    ///
+    /// Like [save_qoi](crate::img::QoiImage::save_qoi), this writes to a temporary file first.
+    #[cfg(feature = "image")]
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        use crate::dec::{pixels_to_bytes, pixels_to_rgb_bytes};
+        use ::image::{codecs::png::PngEncoder, ImageEncoder};
+
+        let bytes = match self.header.channels {
+            Channels::RGB => pixels_to_rgb_bytes(&self.pixels),
+            Channels::RGBA => pixels_to_bytes(&self.pixels),
+        };
+        write_atomic(path.as_ref(), |file| {
+            PngEncoder::new(file)
+                .write_image(
+                    &bytes,
+                    self.width(),
+                    self.height(),
+                    self.header.channels.into(),
+                )
+                .map_err(|e| match e {
+                    ::image::ImageError::IoError(e) => Error::Io(e),
+                    e => Error::Io(std::io::Error::other(e)),
+                })
+        })
+    }
+}
+
+/// Opens an image in any format but QOI with the image crate.
+#[cfg(feature = "image")]
+fn open_other(data: &[u8]) -> Result<QoiImage, Error> {
+    let img = ::image::load_from_memory(data)
+        .map_err(|e| Error::DecodingError(crate::utils::err_msg!("{}", e)))?;
+    let channels = match img.color().has_alpha() {
+        true => Channels::RGBA,
+        false => Channels::RGB,
+    };
+
+    let (header, pixels) = QoiImage::try_from(&img.into_rgba8())?.into_parts();
+    let header = Header::new(header.width, header.height, channels, header.colorspace);
+    QoiImage::new(header, pixels)
+}
+
+/// Creates a temporary file next to `path`, passes it to `write`, and renames it to `path` once
+/// everything has been written. The temporary file is removed if anything fails. IO errors are
+/// returned as [Error::FileIo], with `path`.
+///
+/// The temporary name is made unique with the process id and a counter, and is opened with
+/// `create_new`, so concurrent writers (or a stale file left by a crash) never share it.
+fn write_atomic<T>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let with_path = |source| Error::FileIo {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let Some(name) = path.file_name() else {
+        return Err(with_path(std::io::ErrorKind::InvalidInput.into()));
+    };
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let (tmp, file) = loop {
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(name);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        tmp_name.push(format!(".{}.{}.tmp", std::process::id(), n));
+        let tmp = path.with_file_name(tmp_name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(file) => break (tmp, file),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(with_path(e)),
+        }
+    };
+
+    let result = (|| {
+        let mut file = BufWriter::new(file);
+        let value = write(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(value)
+    })();
+
+    result.map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        match e {
+            Error::Io(source) => with_path(source),
+            e => e,
+        }
+    })
+}
+
+/// Reads a QOI image from `reader`, which must start at the first byte of the header.
+///
+/// This is the same as `Decoder::new().decode(reader)`, returning a [QoiImage]. Use a
+/// [Decoder] directly for limits, strict checking of the end marker, or to reuse its allocations.
+/// Wrap unbuffered readers (like a `File`) in a `BufReader`, as the ops are read a few bytes at a
+/// time.
+pub fn read_qoi(reader: &mut impl Read) -> Result<QoiImage, Error> {
+    let (header, pixels) = Decoder::new().decode(reader).map_err(into_error)?;
+    QoiImage::new(header, pixels)
+}
+
+/// Writes `image` to `writer` as a QOI image, header and end marker included. Returns the number
+/// of bytes written.
+///
+/// This is the same as [Encoder::encode] with the image's header and pixels.
+pub fn write_qoi(image: &QoiImage, writer: &mut impl Write) -> Result<u64, Error> {
+    Encoder::new().encode(image.header(), image.pixels(), writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{Channels, Colorspace, Header, Pixel};
+    use crate::img::{read_qoi, write_qoi, QoiImage};
+    use crate::utils::Error;
+
+    #[test]
+    fn test_open_save_qoi() {
+        let img = QoiImage::open("tests/dice.qoi").unwrap();
+        let path = std::env::temp_dir().join("qoi-parser-save-qoi.qoi");
+
+        // Change a pixel, save it, and open it again.
+        let mut changed = img.clone();
+        changed.pixels_mut()[0] = Pixel::new(1, 2, 3, 4);
+        let written = changed.save_qoi(&path).unwrap();
+        assert_eq!(written, std::fs::metadata(&path).unwrap().len());
+        let reopened = QoiImage::open(&path).unwrap();
+        assert_eq!(reopened.header(), img.header());
+        assert!(reopened == changed);
+
+        // Saving again replaces the file, and leaves no temporary file behind.
+        img.save_qoi(&path).unwrap();
+        assert!(QoiImage::open(&path).unwrap() == img);
+        let leftover = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(".qoi-parser-save-qoi.qoi.") && name.ends_with(".tmp")
+            });
+        assert!(!leftover);
+        std::fs::remove_file(&path).unwrap();
+
+        // A path that can't be written names the path.
+        let missing = std::env::temp_dir().join("qoi-parser-missing-dir/out.qoi");
+        match img.save_qoi(&missing) {
+            Err(Error::FileIo { path, source }) => {
+                assert_eq!(path, missing);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            other => panic!("expected a FileIo error, got {:?}", other),
+        }
+        assert!(matches!(
+            QoiImage::open(&missing),
+            Err(Error::FileIo { .. })
+        ));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_open_save_png() {
+        let img = QoiImage::open("tests/dice.qoi").unwrap();
+        let path = std::env::temp_dir().join("qoi-parser-save-png.png");
+
+        img.save_png(&path).unwrap();
+        let png = QoiImage::open(&path).unwrap();
+        assert_eq!(png.header(), img.header());
+        assert!(png == img);
+        std::fs::remove_file(&path).unwrap();
+
+        // RGB images are saved, and opened again, without alpha.
+        let header = Header::new(2, 1, Channels::RGB, Colorspace::sRGB);
+        let rgb = QoiImage::new(header, vec![Pixel::new(1, 2, 3, 255); 2]).unwrap();
+        rgb.save_png(&path).unwrap();
+        assert!(QoiImage::open(&path).unwrap() == rgb);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_new() {
+        let header = Header::new(2, 3, Channels::RGBA, Colorspace::sRGB);
+
+        assert!(QoiImage::new(header.clone(), vec![Pixel::default(); 6]).is_ok());
+        assert!(QoiImage::new(header.clone(), vec![Pixel::default(); 5]).is_err());
+        assert!(QoiImage::new(header, vec![Pixel::default(); 7]).is_err());
+    }
+
+    #[test]
+    fn test_iter_rows() {
+        let header = Header::new(3, 2, Channels::RGBA, Colorspace::sRGB);
+        let pixels: Vec<Pixel> = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let img = QoiImage::new(header, pixels.clone()).unwrap();
+
+        let rows: Vec<_> = img.iter_rows().collect();
+        assert_eq!(rows, vec![(0, &pixels[0..3]), (1, &pixels[3..6])]);
+
+        let rows: Vec<_> = img.iter_rows().rev().collect();
+        assert_eq!(rows, vec![(1, &pixels[3..6]), (0, &pixels[0..3])]);
+        assert_eq!(img.iter_rows().len(), 2);
+
+        let header = Header::new(0, 5, Channels::RGBA, Colorspace::sRGB);
+        let img = QoiImage::new(header, Vec::new()).unwrap();
+        assert_eq!(img.iter_rows().count(), 0);
+    }
+
+    #[test]
+    fn test_enumerate_pixels() {
+        let header = Header::new(3, 2, Channels::RGBA, Colorspace::sRGB);
+        let pixels: Vec<Pixel> = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let img = QoiImage::new(header, pixels.clone()).unwrap();
+
+        let coords: Vec<_> = img.enumerate_pixels().map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(coords, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        assert_eq!(img.enumerate_pixels().last(), Some((2, 1, pixels[5])));
+
+        let header = Header::new(0, 5, Channels::RGBA, Colorspace::sRGB);
+        let img = QoiImage::new(header, Vec::new()).unwrap();
+        assert_eq!(img.enumerate_pixels().count(), 0);
+    }
+
+    #[test]
+    fn test_rows() {
+        let header = Header::new(3, 2, Channels::RGBA, Colorspace::sRGB);
+        let pixels: Vec<Pixel> = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let mut img = QoiImage::new(header, pixels.clone()).unwrap();
+
+        assert_eq!(img.rows().len(), 2);
+        assert_eq!(
+            img.rows().collect::<Vec<_>>(),
+            [&pixels[0..3], &pixels[3..6]]
+        );
+        assert_eq!(img.rows().next_back(), Some(&pixels[3..6]));
+
+        // Reversing each row flips the image horizontally.
+        assert_eq!(img.rows_mut().len(), 2);
+        img.rows_mut().for_each(|row| row.reverse());
+        let flipped: Vec<u8> = img.pixels().iter().map(|p| p.r).collect();
+        assert_eq!(flipped, [2, 1, 0, 5, 4, 3]);
+
+        let header = Header::new(0, 5, Channels::RGBA, Colorspace::sRGB);
+        let mut img = QoiImage::new(header, Vec::new()).unwrap();
+        assert_eq!(img.rows().count(), 0);
+        assert_eq!(img.rows_mut().count(), 0);
+    }
+
+    #[test]
+    fn test_map_pixels() {
+        let header = Header::new(3, 2, Channels::RGBA, Colorspace::sRGB);
+        let pixels: Vec<Pixel> = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let mut img = QoiImage::new(header, pixels).unwrap();
+
+        // Every pixel sees its own coordinates and value, in row-major order.
+        let mut seen = Vec::new();
+        img.map_pixels(|x, y, p| {
+            seen.push((x, y, p.r));
+            Pixel::new(p.r, x as u8, y as u8, 255)
+        });
+        assert_eq!(
+            seen,
+            [
+                (0, 0, 0),
+                (1, 0, 1),
+                (2, 0, 2),
+                (0, 1, 3),
+                (1, 1, 4),
+                (2, 1, 5)
+            ]
+        );
+        for (x, y, p) in img.enumerate_pixels() {
+            assert_eq!(p, Pixel::new((y * 3 + x) as u8, x as u8, y as u8, 255));
+        }
+    }
+
+    #[test]
+    fn test_split_merge_channels() {
+        let img = read_qoi(&mut &std::fs::read("tests/dice.qoi").unwrap()[..]).unwrap();
+        let (width, height) = (img.width(), img.height());
+
+        let (r, g, b, a) = img.split_channels();
+        for c in [&r, &g, &b, &a] {
+            assert_eq!(c.len() as u32, width * height);
+        }
+        for (i, p) in img.pixels().iter().enumerate() {
+            assert_eq!([r[i], g[i], b[i], a[i]], p.to_bytes());
+        }
+
+        let merged = QoiImage::merge_channels(&r, &g, &b, &a, width, height).unwrap();
+        assert_eq!(merged.header(), img.header());
+        assert!(merged == img);
+
+        // Every channel has to be the right length.
+        let short = &a[1..];
+        assert!(matches!(
+            QoiImage::merge_channels(&r, &g, &b, short, width, height),
+            Err(Error::InvalidDimensions { .. })
+        ));
+        assert!(QoiImage::merge_channels(&r, &g, &b, &a, width, height + 1).is_err());
+    }
+
+    #[test]
+    fn test_crop() {
+        let header = Header::new(4, 4, Channels::RGB, Colorspace::Linear);
+        let pixels: Vec<Pixel> = (0..16).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let img = QoiImage::new(header, pixels.clone()).unwrap();
+
+        // The bottom right quadrant.
+        let quad = img.crop(2, 2, 2, 2).unwrap();
+        assert_eq!((quad.width(), quad.height()), (2, 2));
+        assert_eq!(quad.header().channels, Channels::RGB);
+        assert_eq!(quad.header().colorspace, Colorspace::Linear);
+        assert_eq!(
+            quad.pixels(),
+            [pixels[10], pixels[11], pixels[14], pixels[15]]
+        );
+
+        assert_eq!(img.crop(0, 0, 4, 4).unwrap(), img);
+        assert_eq!(img.crop(1, 3, 3, 1).unwrap().pixels(), &pixels[13..16]);
+        assert!(img.crop(4, 4, 0, 0).unwrap().pixels().is_empty());
+
+        assert!(img.crop(3, 0, 2, 1).is_err());
+        assert!(img.crop(0, 1, 1, 4).is_err());
+        assert!(img.crop(5, 0, 0, 0).is_err());
+        assert!(img.crop(1, 0, u32::MAX, 1).is_err());
+    }
+
+    /// A 3x2 image with a different red value in every pixel:
+    ///
+    /// ```text
+    /// 0 1 2
+    /// 3 4 5
+    /// ```
+    fn three_by_two() -> QoiImage {
+        let header = Header::new(3, 2, Channels::RGB, Colorspace::Linear);
+        let pixels = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        QoiImage::new(header, pixels).unwrap()
+    }
+
+    /// The red values of an image, row by row.
+    fn reds(img: &QoiImage) -> Vec<Vec<u8>> {
+        img.rows()
+            .map(|row| row.iter().map(|p| p.r).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_flip() {
+        let mut img = three_by_two();
+        img.flip_horizontal();
+        assert_eq!(reds(&img), [[2, 1, 0], [5, 4, 3]]);
+        img.flip_vertical();
+        assert_eq!(reds(&img), [[5, 4, 3], [2, 1, 0]]);
+
+        // The middle row of an odd height stays put.
+        let header = Header::new(2, 3, Channels::RGBA, Colorspace::sRGB);
+        let pixels = (0..6).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let mut img = QoiImage::new(header, pixels).unwrap();
+        img.flip_vertical();
+        assert_eq!(reds(&img), [[4, 5], [2, 3], [0, 1]]);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let img = three_by_two();
+
+        let turned = img.rotate90();
+        assert_eq!((turned.width(), turned.height()), (2, 3));
+        assert_eq!(turned.header().channels, Channels::RGB);
+        assert_eq!(turned.header().colorspace, Colorspace::Linear);
+        assert_eq!(reds(&turned), [[3, 0], [4, 1], [5, 2]]);
+
+        assert_eq!(reds(&img.rotate180()), [[5, 4, 3], [2, 1, 0]]);
+
+        let turned = img.rotate270();
+        assert_eq!((turned.width(), turned.height()), (2, 3));
+        assert_eq!(reds(&turned), [[2, 5], [1, 4], [0, 3]]);
+
+        // Images with no pixels still swap their dimensions.
+        let empty = QoiImage::new(Header::new(4, 0, Channels::RGBA, Colorspace::sRGB), vec![]);
+        let turned = empty.unwrap().rotate90();
+        assert_eq!((turned.width(), turned.height()), (0, 4));
+        assert!(turned.pixels().is_empty());
+    }
+
+    #[test]
+    fn test_lookup_tables() {
+        let header = Header::new(2, 1, Channels::RGBA, Colorspace::sRGB);
+        let pixels = vec![Pixel::new(0, 100, 255, 7), Pixel::new(10, 20, 30, 40)];
+        let mut img = QoiImage::new(header, pixels).unwrap();
+
+        let invert: [u8; 256] = std::array::from_fn(|i| 255 - i as u8);
+        img.apply_lookup_table(&invert);
+        assert_eq!(
+            img.pixels(),
+            [Pixel::new(255, 155, 0, 7), Pixel::new(245, 235, 225, 40)]
+        );
+
+        let identity: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let zero = [0; 256];
+        img.apply_lookup_table(&invert)
+            .apply_channel_lookup_tables(&zero, &identity, &invert);
+        assert_eq!(
+            img.pixels(),
+            [Pixel::new(0, 100, 0, 7), Pixel::new(0, 20, 225, 40)]
+        );
+    }
+
+    #[test]
+    fn test_scale_nearest() {
+        let header = Header::new(2, 2, Channels::RGB, Colorspace::Linear);
+        let [a, b, c, d] = [1, 2, 3, 4].map(|i| Pixel::new(i, 0, 0, 255));
+        let img = QoiImage::new(header, vec![a, b, c, d]).unwrap();
+
+        // Upscaling repeats each pixel.
+        let up = img.scale_nearest(4, 3);
+        assert_eq!((up.width(), up.height()), (4, 3));
+        assert_eq!(up.header().colorspace, Colorspace::Linear);
+        #[rustfmt::skip]
+        assert_eq!(up.pixels(), [
+            a, a, b, b,
+            a, a, b, b,
+            c, c, d, d,
+        ]);
+
+        // Downscaling keeps the top left pixel of each block.
+        let header = Header::new(4, 2, Channels::RGBA, Colorspace::sRGB);
+        let pixels: Vec<Pixel> = (0..8).map(|i| Pixel::new(i, 0, 0, 255)).collect();
+        let img = QoiImage::new(header, pixels.clone()).unwrap();
+        assert_eq!(img.scale_nearest(2, 1).pixels(), [pixels[0], pixels[2]]);
+
+        assert_eq!(img.scale_nearest(4, 2), img);
+        assert!(img.scale_nearest(0, 7).pixels().is_empty());
+
+        let header = Header::new(0, 0, Channels::RGBA, Colorspace::sRGB);
+        let empty = QoiImage::new(header, Vec::new()).unwrap();
+        assert_eq!(empty.scale_nearest(2, 2).pixels(), [Pixel::default(); 4]);
+    }
+
+    #[test]
+    fn test_read_write_qoi() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let img = read_qoi(&mut &data[..]).unwrap();
+        assert_eq!((img.width(), img.height()), (800, 600));
+
+        let mut out = Vec::new();
+        let written = write_qoi(&img, &mut out).unwrap();
+        assert_eq!(written, out.len() as u64);
+        assert_eq!(read_qoi(&mut &out[..]).unwrap(), img);
+
+        // Errors come back as the crate's Error, not anyhow's.
+        let err = read_qoi(&mut &data[..1000]).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::utils::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_convert_colorspace() {
+        let header = Header::new(3, 1, Channels::RGBA, Colorspace::sRGB);
+        let pixels = vec![
+            Pixel::new(0, 255, 128, 7),
+            Pixel::new(10, 11, 188, 255),
+            Pixel::new(64, 200, 1, 0),
+        ];
+        let img = QoiImage::new(header, pixels).unwrap();
+
+        assert_eq!(img.convert_colorspace(Colorspace::sRGB), img);
+
+        // sRGB 128 is 0.2158 in linear light, and 188 is 0.5.
+        let linear = img.convert_colorspace(Colorspace::Linear);
+        assert_eq!(linear.header().colorspace, Colorspace::Linear);
+        assert_eq!(linear.pixels()[0], Pixel::new(0, 255, 55, 7));
+        assert_eq!(linear.pixels()[1].b, 128);
+        assert_eq!(linear.pixels()[2].a, 0);
+
+        // Back again, bright values survive while dark ones lose precision.
+        let back = linear.convert_colorspace(Colorspace::sRGB);
+        assert_eq!(back.header().colorspace, Colorspace::sRGB);
+        assert_eq!(back.pixels()[0], Pixel::new(0, 255, 128, 7));
+        assert_eq!(back.pixels()[1].b, 188);
+        assert_ne!(back.pixels()[1].r, 10);
+    }
+
+    mod props {
+        use crate::dec::{Channels, Colorspace, Header};
+        use crate::img::QoiImage;
+        use crate::testgen::{self, Pattern};
+        use proptest::prelude::*;
+
+        fn image() -> impl Strategy<Value = QoiImage> {
+            let pattern = proptest::sample::select(Pattern::ALL.to_vec());
+            (pattern, 1..=24u32, 1..=24u32, any::<u64>()).prop_map(
+                |(pattern, width, height, seed)| {
+                    let header = Header::new(width, height, Channels::RGBA, Colorspace::sRGB);
+                    let pixels = testgen::generate(pattern, width, height, seed);
+                    QoiImage::new(header, pixels).unwrap()
+                },
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn prop_rotate_four_times(img in image()) {
+                let turned = img.rotate90().rotate90().rotate90().rotate90();
+                prop_assert_eq!(&turned, &img);
+                prop_assert_eq!(&img.rotate90().rotate90(), &img.rotate180());
+                prop_assert_eq!(&img.rotate90().rotate180(), &img.rotate270());
+            }
+
+            #[test]
+            fn prop_flip_twice(img in image()) {
+                let mut flipped = img.clone();
+                flipped.flip_vertical().flip_horizontal();
+                prop_assert_eq!(&flipped, &img.rotate180());
+                flipped.flip_horizontal().flip_vertical();
+                prop_assert_eq!(&flipped, &img);
+            }
+
+            #[test]
+            fn prop_crop_whole(img in image()) {
+                prop_assert_eq!(img.crop(0, 0, img.width(), img.height()).unwrap(), img);
+            }
+        }
+    }
+}
@@ -0,0 +1,566 @@
+//! Conversions between this crate's types and the types of other crates in the ecosystem. Each
+//! crate is behind a feature of the same name.
+
+#[cfg(feature = "image")]
+mod image_impls {
+    use crate::dec::{pixels_into_bytes, Channels, Colorspace, Header, Pixel};
+    use crate::enc::Encoder;
+    use crate::img::QoiImage;
+    use crate::utils::Error;
+    use std::io::Write;
+
+    impl From<::image::Rgba<u8>> for Pixel {
+        fn from(value: ::image::Rgba<u8>) -> Self {
+            let [r, g, b, a] = value.0;
+            Pixel::new(r, g, b, a)
+        }
+    }
+
+    impl From<Pixel> for ::image::Rgba<u8> {
+        fn from(value: Pixel) -> Self {
+            ::image::Rgba(value.to_bytes())
+        }
+    }
+
+    impl From<Channels> for ::image::ColorType {
+        fn from(value: Channels) -> Self {
+            match value {
+                Channels::RGB => ::image::ColorType::Rgb8,
+                Channels::RGBA => ::image::ColorType::Rgba8,
+            }
+        }
+    }
+
+    impl From<&Channels> for ::image::ColorType {
+        fn from(value: &Channels) -> Self {
+            (*value).into()
+        }
+    }
+
+    /// The image is assumed to be sRGB, as that is what the image crate assumes for 8-bit images.
+    impl TryFrom<&::image::RgbaImage> for QoiImage {
+        type Error = Error;
+
+        fn try_from(value: &::image::RgbaImage) -> Result<Self, Self::Error> {
+            let (width, height) = value.dimensions();
+
+            // The QOI spec does not allow empty images.
+            if width == 0 || height == 0 {
+                return Err(Error::InvalidDimensions { width, height });
+            }
+
+            let pixels = value
+                .as_raw()
+                .chunks_exact(4)
+                .map(|p| Pixel::new(p[0], p[1], p[2], p[3]))
+                .collect();
+
+            QoiImage::new(
+                Header::new(width, height, Channels::RGBA, Colorspace::sRGB),
+                pixels,
+            )
+        }
+    }
+
+    impl From<QoiImage> for ::image::RgbaImage {
+        fn from(value: QoiImage) -> Self {
+            let (header, pixels) = value.into_parts();
+
+            ::image::RgbaImage::from_raw(header.width, header.height, pixels_into_bytes(pixels))
+                .expect("QoiImage always holds width * height pixels")
+        }
+    }
+
+    /// An adapter that lets code written against the image crate's
+    /// [ImageEncoder](::image::ImageEncoder) trait write QOI images with this crate's
+    /// [Encoder](crate::enc::Encoder).
+    ///
+    /// Only `Rgb8` and `Rgba8` data is supported. The image is assumed to be sRGB.
+    pub struct QoiImageEncoder<W: Write> {
+        writer: W,
+    }
+
+    impl<W: Write> QoiImageEncoder<W> {
+        pub fn new(writer: W) -> Self {
+            QoiImageEncoder { writer }
+        }
+    }
+
+    impl<W: Write> ::image::ImageEncoder for QoiImageEncoder<W> {
+        fn write_image(
+            mut self,
+            buf: &[u8],
+            width: u32,
+            height: u32,
+            color_type: ::image::ColorType,
+        ) -> ::image::ImageResult<()> {
+            use ::image::error::{
+                EncodingError, ImageError, ImageFormatHint, UnsupportedError,
+                UnsupportedErrorKind,
+            };
+
+            let format = ImageFormatHint::Exact(::image::ImageFormat::Qoi);
+
+            let channels = match color_type {
+                ::image::ColorType::Rgb8 => Channels::RGB,
+                ::image::ColorType::Rgba8 => Channels::RGBA,
+                _ => {
+                    return Err(ImageError::Unsupported(
+                        UnsupportedError::from_format_and_kind(
+                            format,
+                            UnsupportedErrorKind::Color(color_type.into()),
+                        ),
+                    ))
+                }
+            };
+
+            let header = Header::new(width, height, channels, Colorspace::sRGB);
+            Encoder::new()
+                .encode_bytes(&header, buf, &mut self.writer)
+                .map_err(|e| ImageError::Encoding(EncodingError::new(format, e)))?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+pub use image_impls::QoiImageEncoder;
+
+#[cfg(feature = "palette")]
+mod palette_impls {
+    use crate::dec::{Colorspace, Pixel};
+    use ::palette::{LinSrgba, Srgba};
+
+    /// The bytes are taken as sRGB encoded. See [to_linear](crate::interop::to_linear) for pixels of
+    /// images with a linear colorspace.
+    impl From<Pixel> for Srgba<u8> {
+        fn from(value: Pixel) -> Self {
+            Srgba::new(value.r, value.g, value.b, value.a)
+        }
+    }
+
+    impl From<Srgba<u8>> for Pixel {
+        fn from(value: Srgba<u8>) -> Self {
+            let (r, g, b, a) = value.into_components();
+            Pixel::new(r, g, b, a)
+        }
+    }
+
+    /// Converts a pixel to linear RGB, for color-space-correct blending and color distances.
+    /// `colorspace` is the one in the image's header, which says whether the bytes are sRGB
+    /// encoded or already linear.
+    pub fn to_linear(pixel: Pixel, colorspace: Colorspace) -> LinSrgba<f32> {
+        match colorspace {
+            Colorspace::sRGB => Srgba::from(pixel).into_format::<f32, f32>().into_linear(),
+            Colorspace::Linear => {
+                LinSrgba::new(pixel.r, pixel.g, pixel.b, pixel.a).into_format::<f32, f32>()
+            }
+        }
+    }
+
+    /// The inverse of [to_linear], encoding `color` the way `colorspace` says. Values are rounded
+    /// to the nearest byte, so pixels round-trip exactly.
+    pub fn from_linear(color: LinSrgba<f32>, colorspace: Colorspace) -> Pixel {
+        let (r, g, b, a) = match colorspace {
+            Colorspace::sRGB => Srgba::<f32>::from_linear(color)
+                .into_format::<u8, u8>()
+                .into_components(),
+            Colorspace::Linear => color.into_format::<u8, u8>().into_components(),
+        };
+        Pixel::new(r, g, b, a)
+    }
+}
+
+#[cfg(feature = "palette")]
+pub use palette_impls::{from_linear, to_linear};
+
+#[cfg(feature = "png")]
+mod png_impls {
+    use crate::dec::{into_error, Channels, Header};
+    use crate::stream::{ChunkedDriver, PixelSink, PixelsIter};
+    use crate::utils::{err_msg, Error};
+    use std::io::{Read, Write};
+
+    /// Decodes the QOI image in `reader` and writes it to `writer` as a PNG, one row at a time.
+    /// Only a row of pixels is held in memory, so this works on images of any size. RGB images
+    /// become RGB PNGs and RGBA images RGBA ones.
+    ///
+    /// The end marker is checked, but as the PNG is written while decoding, `writer` is left
+    /// with a partial image when decoding fails.
+    pub fn decode_to_png(mut reader: impl Read, writer: impl Write) -> Result<Header, Error> {
+        // The PNG header has to be written before the rows, so the QOI header is read up front
+        // and then handed to the driver along with the rest.
+        let mut header_bytes = [0; 14];
+        reader.read_exact(&mut header_bytes)?;
+        let header = Header::from_bytes(&header_bytes).map_err(into_error)?;
+
+        // The width comes from the input, so a row that can't be allocated is an error rather than
+        // an abort. This is checked before anything is written.
+        let row_len = header.width as usize * header.channels as usize;
+        let mut row = Vec::new();
+        row.try_reserve_exact(row_len).map_err(|_| {
+            Error::DecodingError(err_msg!("Failed to allocate {} bytes for a row", row_len))
+        })?;
+
+        let mut encoder = ::png::Encoder::new(writer, header.width, header.height);
+        encoder.set_color(match header.channels {
+            Channels::RGB => ::png::ColorType::Rgb,
+            Channels::RGBA => ::png::ColorType::Rgba,
+        });
+        encoder.set_depth(::png::BitDepth::Eight);
+        let mut png = encoder.write_header().map_err(std::io::Error::from)?;
+
+        let mut sink = PngSink {
+            stream: png.stream_writer().map_err(std::io::Error::from)?,
+            channels: header.channels,
+            row,
+            row_len,
+            error: None,
+        };
+        ChunkedDriver::new((&header_bytes[..]).chain(reader)).run(&mut sink)?;
+
+        if let Some(e) = sink.error {
+            return Err(e.into());
+        }
+        sink.stream.finish().map_err(std::io::Error::from)?;
+        Ok(header)
+    }
+
+    /// Writes each row to the PNG stream as soon as it is complete. The first error is kept, as
+    /// the sink can't fail, and every pixel after it is dropped.
+    struct PngSink<'a, W: Write> {
+        stream: ::png::StreamWriter<'a, W>,
+        channels: Channels,
+        row: Vec<u8>,
+        row_len: usize,
+        error: Option<std::io::Error>,
+    }
+
+    impl<W: Write> PixelSink for PngSink<'_, W> {
+        fn pixels(&mut self, pixels: PixelsIter) {
+            if self.error.is_some() {
+                return;
+            }
+            for p in pixels {
+                match self.channels {
+                    Channels::RGB => self.row.extend_from_slice(&[p.r, p.g, p.b]),
+                    Channels::RGBA => self.row.extend_from_slice(&p.to_bytes()),
+                }
+                if self.row.len() == self.row_len {
+                    if let Err(e) = self.stream.write_all(&self.row) {
+                        self.error = Some(e);
+                        return;
+                    }
+                    self.row.clear();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+pub use png_impls::decode_to_png;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    use crate::dec::{Channels, Colorspace, Header, Pixel};
+    use arbitrary::{Arbitrary, Result, Unstructured};
+
+    /// The largest width and height of an arbitrary [Header], so that fuzz runs stay fast.
+    pub const MAX_ARBITRARY_DIM: u32 = 64;
+
+    impl<'a> Arbitrary<'a> for Pixel {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let [r, g, b, a] = u.arbitrary()?;
+            Ok(Pixel::new(r, g, b, a))
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (4, Some(4))
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for Channels {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(*u.choose(&[Channels::RGB, Channels::RGBA])?)
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for Colorspace {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(*u.choose(&[Colorspace::sRGB, Colorspace::Linear])?)
+        }
+    }
+
+    /// Headers always have the `qoif` magic bytes and dimensions between 1 and
+    /// [MAX_ARBITRARY_DIM].
+    impl<'a> Arbitrary<'a> for Header {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(Header::new(
+                u.int_in_range(1..=MAX_ARBITRARY_DIM)?,
+                u.int_in_range(1..=MAX_ARBITRARY_DIM)?,
+                u.arbitrary()?,
+                u.arbitrary()?,
+            ))
+        }
+    }
+
+    /// An arbitrary header along with exactly `width * height` pixels to go with it.
+    ///
+    /// Uniformly random pixels would almost always encode as `QOI_OP_RGBA`, so each pixel is
+    /// instead built from the ones before it the way the encoder's ops would see them: repeats
+    /// (runs), small and medium changes (`QOI_OP_DIFF` and `QOI_OP_LUMA`), colors seen earlier
+    /// (`QOI_OP_INDEX`), and new colors with and without an alpha change.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ArbitraryImage {
+        pub header: Header,
+        pub pixels: Vec<Pixel>,
+    }
+
+    impl<'a> Arbitrary<'a> for ArbitraryImage {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let header: Header = u.arbitrary()?;
+            let len = header.width as usize * header.height as usize;
+
+            let mut prev = Pixel::new(0, 0, 0, 255);
+            let mut seen: Vec<Pixel> = Vec::new();
+            let mut pixels = Vec::with_capacity(len);
+
+            for _ in 0..len {
+                let delta = |u: &mut Unstructured<'a>, range| -> Result<u8> {
+                    Ok(u.int_in_range::<i8>(range)? as u8)
+                };
+
+                let pix = match u.int_in_range(0..=5)? {
+                    0 => prev,
+                    1 => Pixel {
+                        r: prev.r.wrapping_add(delta(u, -2..=1)?),
+                        g: prev.g.wrapping_add(delta(u, -2..=1)?),
+                        b: prev.b.wrapping_add(delta(u, -2..=1)?),
+                        ..prev
+                    },
+                    2 => {
+                        let dg = delta(u, -32..=31)?;
+                        Pixel {
+                            r: prev.r.wrapping_add(dg).wrapping_add(delta(u, -8..=7)?),
+                            g: prev.g.wrapping_add(dg),
+                            b: prev.b.wrapping_add(dg).wrapping_add(delta(u, -8..=7)?),
+                            ..prev
+                        }
+                    }
+                    3 if !seen.is_empty() => *u.choose(&seen)?,
+                    4 => Pixel { a: prev.a, ..u.arbitrary()? },
+                    _ => u.arbitrary()?,
+                };
+
+                // Keep a handful of recent colors around for later pixels to reuse.
+                if seen.len() == 16 {
+                    seen.swap_remove(u.choose_index(16)?);
+                }
+                seen.push(pix);
+
+                pixels.push(pix);
+                prev = pix;
+            }
+
+            Ok(ArbitraryImage { header, pixels })
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+pub use arbitrary_impls::{ArbitraryImage, MAX_ARBITRARY_DIM};
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use crate::dec::{Channels, Colorspace, Header, Pixel};
+    use crate::img::QoiImage;
+
+    #[test]
+    fn test_image_roundtrip() {
+        let (width, height) = (7, 5);
+        let pixels = (0..width * height)
+            .map(|i| Pixel::new(i as u8, (i * 3) as u8, 255 - i as u8, (i * 7) as u8))
+            .collect();
+        let img = QoiImage::new(
+            Header::new(width, height, Channels::RGBA, Colorspace::sRGB),
+            pixels,
+        )
+        .unwrap();
+
+        let rgba: ::image::RgbaImage = img.clone().into();
+        assert_eq!(rgba.dimensions(), (width, height));
+        assert_eq!(Pixel::from(*rgba.get_pixel(6, 4)), img.pixels()[34]);
+
+        let back = QoiImage::try_from(&rgba).unwrap();
+        assert_eq!(back, img);
+
+        let empty = ::image::RgbaImage::new(0, 10);
+        assert!(QoiImage::try_from(&empty).is_err());
+    }
+
+    #[test]
+    fn test_pixel_rgba() {
+        let pix = Pixel::new(1, 2, 3, 4);
+        let rgba: ::image::Rgba<u8> = pix.into();
+        assert_eq!(rgba, ::image::Rgba([1, 2, 3, 4]));
+        assert_eq!(Pixel::from(rgba), pix);
+    }
+
+    #[test]
+    fn test_channels_color_type() {
+        assert_eq!(::image::ColorType::from(Channels::RGB), ::image::ColorType::Rgb8);
+        assert_eq!(::image::ColorType::from(&Channels::RGBA), ::image::ColorType::Rgba8);
+
+        let header = Header::new(1, 1, Channels::RGB, Colorspace::sRGB);
+        let color: ::image::ColorType = header.channels.into();
+        assert_eq!(color.bytes_per_pixel(), 3);
+    }
+
+    #[test]
+    fn test_image_encoder() {
+        use crate::dec::Decoder;
+        use crate::interop::QoiImageEncoder;
+        use crate::testgen::{self, Pattern};
+        use ::image::ImageEncoder;
+
+        let img = testgen::generate(Pattern::Mixed, 31, 9, 5);
+        let rgba = crate::dec::pixels_to_bytes(&img);
+
+        let mut encoded = Vec::new();
+        QoiImageEncoder::new(&mut encoded)
+            .write_image(&rgba, 31, 9, ::image::ColorType::Rgba8)
+            .unwrap();
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+
+        let decoded = ::image::load_from_memory_with_format(&encoded, ::image::ImageFormat::Qoi)
+            .unwrap()
+            .into_rgba8()
+            .into_raw();
+        assert_eq!(decoded, rgba);
+
+        // RGB data comes back out as opaque pixels.
+        let rgb = crate::dec::pixels_to_rgb_bytes(&img);
+        let mut encoded = Vec::new();
+        QoiImageEncoder::new(&mut encoded)
+            .write_image(&rgb, 31, 9, ::image::ColorType::Rgb8)
+            .unwrap();
+        let decoded = ::image::load_from_memory_with_format(&encoded, ::image::ImageFormat::Qoi)
+            .unwrap()
+            .into_rgb8()
+            .into_raw();
+        assert_eq!(decoded, rgb);
+
+        let res = QoiImageEncoder::new(&mut Vec::new()).write_image(
+            &[0; 31 * 9 * 2],
+            31,
+            9,
+            ::image::ColorType::La8,
+        );
+        assert!(matches!(res, Err(::image::ImageError::Unsupported(_))));
+    }
+}
+
+#[cfg(all(test, feature = "arbitrary"))]
+mod arbitrary_tests {
+    use crate::dec::{Decoder, Header};
+    use crate::enc::Encoder;
+    use crate::interop::{ArbitraryImage, MAX_ARBITRARY_DIM};
+    use crate::testgen;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    /// Raw bytes for `Unstructured` to draw from, different for every seed.
+    fn raw(seed: u64) -> Vec<u8> {
+        crate::dec::pixels_into_bytes(testgen::noise(64, 64, seed))
+    }
+
+    #[test]
+    fn test_arbitrary_header() {
+        for seed in 0..200 {
+            let data = raw(seed);
+            let header = Header::arbitrary(&mut Unstructured::new(&data)).unwrap();
+
+            assert_eq!(&header.magic, b"qoif");
+            assert!((1..=MAX_ARBITRARY_DIM).contains(&header.width));
+            assert!((1..=MAX_ARBITRARY_DIM).contains(&header.height));
+            assert!(Header::from_bytes(&header.to_bytes()).is_ok());
+        }
+
+        // Running out of data still gives a valid header.
+        let header = Header::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert!(header.width >= 1 && header.height >= 1);
+    }
+
+    #[test]
+    fn test_arbitrary_image() {
+        let mut ops = [0usize; 6];
+
+        for seed in 0..50 {
+            let data = raw(seed);
+            let img = ArbitraryImage::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            let num_pixels = img.header.width as usize * img.header.height as usize;
+            assert_eq!(img.pixels.len(), num_pixels);
+
+            let encoded = Encoder::new().encode_to_vec(&img.header, &img.pixels).unwrap();
+            let (header, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+            assert_eq!(header, img.header);
+            assert!(decoded == img.pixels);
+
+            // Tally the ops used, skipping the payloads of multi-byte ops.
+            let mut body = &encoded[14..encoded.len() - 8];
+            while let Some(&op) = body.first() {
+                let (kind, len) = match op {
+                    0xfe => (4, 4),
+                    0xff => (5, 5),
+                    _ => match op >> 6 {
+                        0 => (0, 1),
+                        1 => (1, 1),
+                        2 => (2, 2),
+                        _ => (3, 1),
+                    },
+                };
+                ops[kind] += 1;
+                body = &body[len..];
+            }
+        }
+
+        // The pixels are biased so that every op shows up.
+        assert!(ops.iter().all(|&n| n > 0), "{:?}", ops);
+    }
+}
+
+#[cfg(all(test, feature = "palette"))]
+mod palette_tests {
+    use crate::dec::{Colorspace, Pixel};
+    use crate::interop::{from_linear, to_linear};
+    use ::palette::Srgba;
+
+    #[test]
+    fn test_pixel_srgba() {
+        let pix = Pixel::new(1, 2, 3, 4);
+        let srgba: Srgba<u8> = pix.into();
+        assert_eq!(srgba, Srgba::new(1, 2, 3, 4));
+        assert_eq!(Pixel::from(srgba), pix);
+    }
+
+    #[test]
+    fn test_linear() {
+        // sRGB mid-grey is much darker once linear, while linear bytes are only rescaled.
+        let grey = Pixel::new(128, 128, 128, 255);
+        let lin = to_linear(grey, Colorspace::sRGB);
+        assert!((lin.red - 0.2158).abs() < 1e-3, "{:?}", lin);
+        assert_eq!(lin.alpha, 1.0);
+        let lin = to_linear(grey, Colorspace::Linear);
+        assert!((lin.red - 128. / 255.).abs() < 1e-6, "{:?}", lin);
+
+        for colorspace in [Colorspace::sRGB, Colorspace::Linear] {
+            for v in 0..=255 {
+                let pix = Pixel::new(v, 255 - v, v / 2, v);
+                assert_eq!(from_linear(to_linear(pix, colorspace), colorspace), pix);
+            }
+        }
+    }
+}
@@ -1,6 +1,17 @@
 pub mod dec;
+pub mod enc;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod img;
+pub mod interop;
+#[cfg(feature = "phash")]
+pub mod phash;
 pub mod stream;
+pub mod testgen;
 pub mod utils;
+pub mod view;
 
 pub use crate::dec::*;
+pub use crate::enc::*;
+pub use crate::img::*;
 pub use crate::utils::*;
@@ -0,0 +1,159 @@
+//! Perceptual hashes of decoded images, for finding images that look alike even when their bytes
+//! differ: re-encoded, slightly shifted, or with a few pixels touched up.
+//!
+//! The hash is the usual DCT-based pHash. The image is averaged down to 32x32 grayscale, and the
+//! lowest 8x8 frequencies of its discrete cosine transform are compared against their median, one
+//! bit each. Transforming an 8x8 image directly would tie every bit to a handful of pixels; taking
+//! only the low frequencies of a larger one is what makes the hash ignore fine detail. Compare two
+//! hashes with [hamming_distance]: identical images give 0, and anything below about 10 (out of
+//! 64) is usually the same picture.
+
+use std::f64::consts::PI;
+use std::io::Read;
+
+use crate::dec::{Decoder, Pixel};
+
+/// The size of the grayscale image the DCT is taken of.
+const SIZE: usize = 32;
+/// The size of the block of low frequencies that make up the hash.
+const LOW: usize = 8;
+
+impl Decoder {
+    /// Decodes the image in `data` and returns its [phash].
+    pub fn decode_phash(&mut self, data: &mut impl Read) -> Result<u64, anyhow::Error> {
+        let (header, img) = self.decode(data)?;
+        Ok(phash(&img, header.width, header.height))
+    }
+}
+
+/// The number of bits that differ between two hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// The perceptual hash of a `width`x`height` image. Bit 63 holds the lowest frequency, and the
+/// rest follow row by row. An image with no pixels, or with fewer or more than `width * height`,
+/// hashes to 0.
+///
+/// Transparent pixels count as black: each pixel's luma is scaled by its alpha.
+pub fn phash(pixels: &[Pixel], width: u32, height: u32) -> u64 {
+    let (width, height) = (width as usize, height as usize);
+    if pixels.is_empty() || pixels.len() != width * height {
+        return 0;
+    }
+
+    let gray = downsample(pixels, width, height);
+
+    // The DCT-II of each row, then of each column of the result, keeping only the low
+    // frequencies along the way. The scale factors are left out, since only the order of the
+    // coefficients matters.
+    let cos: Vec<[f64; SIZE]> = (0..LOW)
+        .map(|u| {
+            let mut row = [0.; SIZE];
+            for (x, c) in row.iter_mut().enumerate() {
+                *c = ((2 * x + 1) as f64 * u as f64 * PI / (2 * SIZE) as f64).cos();
+            }
+            row
+        })
+        .collect();
+
+    let mut rows = [[0.; LOW]; SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, coef) in row.iter_mut().enumerate() {
+            *coef = (0..SIZE).map(|x| gray[y * SIZE + x] * cos[u][x]).sum();
+        }
+    }
+
+    let mut coefs = [0.; LOW * LOW];
+    for v in 0..LOW {
+        for u in 0..LOW {
+            coefs[v * LOW + u] = (0..SIZE).map(|y| rows[y][u] * cos[v][y]).sum();
+        }
+    }
+
+    let mut sorted = coefs;
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[LOW * LOW / 2 - 1] + sorted[LOW * LOW / 2]) / 2.;
+
+    coefs
+        .iter()
+        .fold(0, |hash, &c| hash << 1 | (c > median) as u64)
+}
+
+/// Averages the luma of the image down to `SIZE`x`SIZE`. Each output value is the mean of the
+/// pixels in its share of the image, or of the nearest pixel when the image is smaller than that.
+fn downsample(pixels: &[Pixel], width: usize, height: usize) -> Vec<f64> {
+    // The pixels covered by output cell `i` along a side of `len` pixels.
+    let span = |i: usize, len: usize| {
+        let start = i * len / SIZE;
+        start..usize::max((i + 1) * len / SIZE, start + 1)
+    };
+
+    let mut gray = Vec::with_capacity(SIZE * SIZE);
+    for cy in 0..SIZE {
+        let ys = span(cy, height);
+        for cx in 0..SIZE {
+            let xs = span(cx, width);
+            let count = (ys.len() * xs.len()) as f64;
+            let sum: f64 = ys
+                .clone()
+                .flat_map(|y| &pixels[y * width + xs.start..y * width + xs.end])
+                .map(|p| luma(*p))
+                .sum();
+            gray.push(sum / count);
+        }
+    }
+    gray
+}
+
+/// The Rec. 601 luma of a pixel, scaled by its alpha.
+fn luma(p: Pixel) -> f64 {
+    (0.299 * p.r as f64 + 0.587 * p.g as f64 + 0.114 * p.b as f64) * p.a as f64 / 255.
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{Decoder, Pixel};
+    use crate::phash::{hamming_distance, phash};
+    use crate::testgen::{self, Pattern};
+
+    #[test]
+    fn test_phash() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let hash = Decoder::new().decode_phash(&mut &data[..]).unwrap();
+        assert_eq!(Decoder::new().decode_phash(&mut &data[..]).unwrap(), hash);
+
+        // Shifting the image right by a few pixels barely changes it.
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+        let (width, height) = (header.width as usize, header.height as usize);
+        let mut shifted = img.clone();
+        for y in 0..height {
+            for x in 0..width {
+                shifted[y * width + x] = img[y * width + x.saturating_sub(3)];
+            }
+        }
+        let distance = hamming_distance(hash, phash(&shifted, header.width, header.height));
+        assert!(distance <= 4, "shifted image is {} bits away", distance);
+
+        // A different picture of the same size is not close.
+        let other = testgen::generate(Pattern::Gradient, header.width, header.height, 1);
+        let distance = hamming_distance(hash, phash(&other, header.width, header.height));
+        assert!(
+            distance > 16,
+            "different image is only {} bits away",
+            distance
+        );
+    }
+
+    #[test]
+    fn test_phash_small() {
+        // Images smaller than the grayscale image still hash, by repeating their pixels.
+        let (dark, light) = (Pixel::new(10, 10, 10, 255), Pixel::new(240, 240, 240, 255));
+        let left = [dark, light, light, dark, light, light];
+        let right = [light, light, dark, light, light, dark];
+        assert_eq!(phash(&left, 3, 2), phash(&left, 3, 2));
+        assert!(hamming_distance(phash(&left, 3, 2), phash(&right, 3, 2)) > 16);
+
+        assert_eq!(phash(&[], 0, 0), 0);
+    }
+}
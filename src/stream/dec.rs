@@ -1,8 +1,13 @@
+#![warn(clippy::indexing_slicing)]
+
 use crate::dec::{
-    Channels, Colorspace, Pixel, ops, Decoder
+    Channels, Colorspace, Header, Pixel, ops, seen_slot, Decoder, decode_slice_ops, QOI_MAGIC
 };
-use crate::utils::Error;
+use crate::stream::driver::PixelSink;
+use crate::utils::{err_msg, Error, ErrorMessage};
+#[cfg(feature = "fmt")]
 use std::fmt::Display;
+use std::io::Read;
 
 /// The output of the StreamDecoder while decoding.
 ///
@@ -11,16 +16,21 @@ use std::fmt::Display;
 /// needed, then the `*Parsed` variants can be ignored. The `NeedsMore` variant also only exists
 /// for the user to potentially pre-buffer a number of bytes ahead of time, but can also be
 /// ignored.
+///
+/// `HeaderDone` is returned exactly once per image, for the last byte of the header. It carries the
+/// whole header, so any setup that depends on it (allocating the image, creating a texture) can
+/// happen there instead of piecing the header together from the `*Parsed` variants.
 pub enum StreamDecoderOutput {
-    Finished,                          // All pixels have been parsed.
-    NeedMore(u8),                      // Number of bytes needed. Between 1 and 4.
+    Finished,                          // The end marker has been checked, finishing the image.
+    NeedMore(u8),                      // Number of bytes needed. Between 1 and 7.
     Pixels(PixelsIter), // An iterator that retuns the number of pixels ready for paring.
     ImageWidthParsed(u32), // The image width has been read from the header.
     ImageHeightParsed(u32), // The image height has been read from the header.
     ImageChannelParsed(Channels), // The image height has been read from the header.
-    ImageColorspaceParsed(Colorspace), // The image height has been read from the header.
+    HeaderDone(Header), // The colorspace has been read, completing the header.
 }
 
+#[cfg(feature = "fmt")]
 impl Display for StreamDecoderOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use StreamDecoderOutput::*;
@@ -32,7 +42,10 @@ impl Display for StreamDecoderOutput {
             ImageWidthParsed(w) => format!("ImageWidthParsed: {}", w),
             ImageHeightParsed(h) => format!("ImageHeightParsed: {}", h),
             ImageChannelParsed(c) => format!("ImageChannelParsed: {}", c),
-            ImageColorspaceParsed(c) => format!("ImageColorspaceParsed: {}", c),
+            HeaderDone(h) => format!(
+                "HeaderDone: {}x{}, {}, {}",
+                h.width, h.height, h.channels, h.colorspace
+            ),
         };
         f.write_str(&val)
     }
@@ -40,7 +53,9 @@ impl Display for StreamDecoderOutput {
 
 /// The internal state of a StreamDecoder.
 ///
-/// `NotStarted` is the default value and Finished is the last value. `ParsingHeader` is jumped to when
+/// `NotStarted` is the default value and `Done` is the last value, reached once `Finished` has
+/// been returned to the user. `ParsingTrailer` holds the number of end marker bytes checked after
+/// the last pixel. `ParsingHeader` is jumped to when
 /// starting from `NotStarted`. The value in `ParsingHeader` is the number of header bytes that have
 /// been parsed. After the header finishes, `ParsingOp` is set to (0, -1), a sentinel that marks that
 /// the previous op has finished and the next byte passed into
@@ -51,20 +66,23 @@ impl Display for StreamDecoderOutput {
 enum StreamDecoderState {
     #[default]
     NotStarted,        // No bytes have been passed in.
-    Finished,          // All bytes in image have been parsed.
+    Done,              // Finished has been returned. Feeding more bytes is an error.
     ParsingHeader(u8), // Currently parsing the header. Contains number of bytes currently parsed.
     ParsingOp(u8, i8), // Contains the opcode of the op being parsed and the number of bytes parsed.
+    ParsingTrailer(u8), // All pixels have been parsed. Contains number of end marker bytes checked.
 }
 
+#[cfg(feature = "fmt")]
 impl Display for StreamDecoderState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use StreamDecoderState::*;
 
         let val = match self {
             NotStarted => "NotStarted".to_string(),
-            Finished => "Finished".to_string(),
+            Done => "Done".to_string(),
             ParsingHeader(header) => format!("ParsingHeader: {}", header),
             ParsingOp(op, c) => format!("ParsingOp: {}, {}", op, c),
+            ParsingTrailer(c) => format!("ParsingTrailer: {}", c),
         };
         f.write_str(&val)
     }
@@ -80,14 +98,24 @@ impl Display for StreamDecoderState {
 /// as they finish being decoded. This allows the user to handle storing or using the pixels as
 /// they wish and also reduces the memory usage by not storing all bytes in an image in memory.
 /// Images larger than the amount of memory in the system can be decoded using StreamDecoder.
+///
+/// Like [Decoder](crate::dec::Decoder), feeding it never panics, whatever the bytes are.
 pub struct StreamDecoder {
-    // 280 bytes total
+    // 328 bytes total
     state: StreamDecoderState, // 2 bytes
+    header: Header,            // 14 bytes
     last_pixel: Pixel,         // 4 bytes
     dec_buffer: [Pixel; 64],   // 256 bytes
     buffer: [u8; 4],           // 4 bytes
     num_pix: Option<u64>,      // 8 bytes
     cur_pix: u64,              // 8 bytes
+    bytes_fed: u64,            // 8 bytes
+    warn_unseen_index: bool,   // 1 byte
+    strict: bool,              // 1 byte
+    // The slot and pixel index of the first QOI_OP_INDEX of an unwritten slot, until taken. Slot
+    // 0 is never flagged, so it means there is no warning.
+    unseen_slot: u8,           // 1 byte
+    unseen_at: u64,            // 8 bytes
 }
 
 impl Default for StreamDecoder {
@@ -100,32 +128,240 @@ impl StreamDecoder {
     pub fn new() -> Self {
         StreamDecoder {
             state: StreamDecoderState::default(),
-            last_pixel: Pixel::default(),
+            header: Header::new(0, 0, Channels::RGBA, Colorspace::sRGB),
+            // The spec starts from opaque black, the same as reset().
+            last_pixel: Pixel::new(0, 0, 0, 255),
             dec_buffer: [Pixel::default(); 64],
             buffer: [0; 4],
             num_pix: None,
             cur_pix: 0,
+            bytes_fed: 0,
+            warn_unseen_index: false,
+            strict: false,
+            unseen_slot: 0,
+            unseen_at: 0,
         }
     }
 
+    /// Records a warning, to be picked up with
+    /// [take_warning](crate::stream::StreamDecoder::take_warning), for a `QOI_OP_INDEX` that reads
+    /// a slot of the index that was never written. A correct encoder never does this, so it points
+    /// to a bug in the encoder or a corrupted file. The pixel the op produced is returned as usual
+    /// and decoding goes on. Disabled by default.
+    ///
+    /// Slots only ever hold pixels that hash to them, except for the transparent black they start
+    /// out with. A slot holding a pixel that doesn't hash to it was never written. Slot 0 can't be
+    /// checked, as transparent black hashes to it, but reading it unwritten gives the right pixel
+    /// anyway.
+    ///
+    /// Every op is checked, so this turns off the fast path of
+    /// [feed_all](crate::stream::StreamDecoder::feed_all). The setting is kept across
+    /// [reset](crate::stream::StreamDecoder::reset()).
+    pub fn warn_unseen_index(mut self, warn: bool) -> Self {
+        self.warn_unseen_index = warn;
+        self
+    }
+
+    /// Makes a `QOI_OP_RUN` that runs past the last pixel an [Error::DecodingError], like
+    /// [Decoder::strict](crate::dec::Decoder::strict). Disabled by default, which cuts the run
+    /// short at the end of the image. The setting is kept across
+    /// [reset](crate::stream::StreamDecoder::reset()).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     /// Resets the state of a StreamDecoder. This must be explicitly called after finishing an
     /// image or after an image parse failure.
     ///
     /// We treat the state as
     pub fn reset(&mut self) {
         self.state = StreamDecoderState::NotStarted;
+        self.header = Header::new(0, 0, Channels::RGBA, Colorspace::sRGB);
         self.last_pixel = Pixel::new(0, 0, 0, 255);
         self.dec_buffer = [Pixel::default(); 64];
         self.buffer = [0; 4];
         self.num_pix = None;
         self.cur_pix = 0;
+        self.bytes_fed = 0;
+        self.unseen_slot = 0;
+    }
+
+    /// Returns the warning recorded since the last call, if any (see
+    /// [warn_unseen_index](crate::stream::StreamDecoder::warn_unseen_index)). Check it after each
+    /// feed: if several ops warn before it is taken, only the first is kept.
+    pub fn take_warning(&mut self) -> Option<ErrorMessage> {
+        if self.unseen_slot == 0 {
+            return None;
+        }
+        let slot = std::mem::take(&mut self.unseen_slot);
+        Some(err_msg!(
+            "OP_INDEX({}) at pixel {} reads a slot that was never written",
+            slot,
+            self.unseen_at
+        ))
+    }
+
+    /// The number of pixels that have been output so far.
+    pub fn cur_pix(&self) -> u64 {
+        self.cur_pix
+    }
+
+    /// The number of bytes of the image fed so far, through any of the feeding functions. Bytes
+    /// that were rejected with an error aren't counted, so after `Finished` this is the size of the
+    /// whole image, end marker included.
+    pub fn bytes_fed(&self) -> u64 {
+        self.bytes_fed
+    }
+
+    /// The number of pixels in the image, available once the width and height have been parsed.
+    pub fn num_pix(&self) -> Option<u64> {
+        self.num_pix
+    }
+
+    /// Whether the image has been finished, that is whether `Finished` has been returned by
+    /// [feed](crate::stream::StreamDecoder::feed) or reached by
+    /// [feed_all](crate::stream::StreamDecoder::feed_all). Nothing more can be fed until
+    /// [reset](crate::stream::StreamDecoder::reset()) is called.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, StreamDecoderState::Done)
+    }
+
+    /// Checks that a whole image has been fed, end marker included, and returns its header.
+    ///
+    /// The feeding functions simply wait for more bytes, so call this once the input runs out. An
+    /// image cut off before the end of its end marker is an `Error::Io` with
+    /// `ErrorKind::UnexpectedEof`.
+    pub fn finish(&self) -> Result<Header, Error> {
+        let msg = match self.state {
+            StreamDecoderState::Done => return Ok(self.header.clone()),
+            StreamDecoderState::ParsingTrailer(_) => "Input ended in the QOI end marker",
+            _ => "Input ended before the image was decoded",
+        };
+        Err(Error::Io(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, msg)))
+    }
+
+    /// Reads exactly one byte from `reader` and feeds it to the decoder.
+    ///
+    /// This uses `read_exact`, so short reads are retried rather than feeding garbage. Hitting the
+    /// end of the reader is returned as an `Error::Io` with `ErrorKind::UnexpectedEof`. Wrap
+    /// unbuffered readers (like a `File`) in a `BufReader`, as this reads a single byte at a time.
+    pub fn feed_read<R: Read>(&mut self, reader: &mut R) -> Result<StreamDecoderOutput, Error> {
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf)?;
+        self.feed(buf[0])
+    }
+
+    /// Feeds every byte of `data` to the decoder, passing each output to `on_output`.
+    ///
+    /// This stops early once `Finished` has been passed on, and returns the number of bytes of
+    /// `data` that were consumed. Anything after the end marker (the next image, say) is left for
+    /// the caller. Otherwise all of `data` is consumed, and more can be fed with another call.
+    ///
+    /// This is the same as calling [feed](crate::stream::StreamDecoder::feed()) in a loop, and
+    /// runs at about the same speed (see `benches/feed.rs`). It mostly saves writing the loop and
+    /// the early exit on `Finished`.
+    pub fn feed_slice(
+        &mut self,
+        data: &[u8],
+        mut on_output: impl FnMut(StreamDecoderOutput),
+    ) -> Result<usize, Error> {
+        for (i, &byte) in data.iter().enumerate() {
+            let out = self.feed(byte)?;
+            let finished = matches!(out, StreamDecoderOutput::Finished);
+            on_output(out);
+            if finished {
+                return Ok(i + 1);
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// Feeds every byte of `data` to the decoder, handing the header and pixels to `sink`.
+    ///
+    /// This consumes the same bytes and produces the same pixels as
+    /// [feed_slice](crate::stream::StreamDecoder::feed_slice), including stopping after the byte
+    /// that finishes the image and returning the number of bytes consumed. It is faster,
+    /// though: whenever the decoder is between two ops, every op that is entirely in `data` is
+    /// decoded in a tight loop over the slice, the same one used by
+    /// [decode_bufread](crate::dec::Decoder::decode_bufread). Only the header and an op cut off by
+    /// the end of `data` go through [feed](crate::stream::StreamDecoder::feed), so the decoder is
+    /// left in the same state either way and calls to `feed_all` and `feed` can be mixed freely.
+    ///
+    /// As the sink isn't told when the image finishes, check
+    /// [is_finished](crate::stream::StreamDecoder::is_finished) after each call.
+    pub fn feed_all(&mut self, data: &[u8], sink: &mut impl PixelSink) -> Result<usize, Error> {
+        let mut i = 0;
+        while i < data.len() {
+            if let (StreamDecoderState::ParsingOp(0, -1), Some(num_pix), false) =
+                (&self.state, self.num_pix, self.warn_unseen_index)
+            {
+                let mut state = self.last_pixel.to_bits();
+                let mut seen = self.dec_buffer.map(Pixel::to_bits);
+                let res = decode_slice_ops(
+                    data.get(i..).unwrap_or_default(),
+                    &mut state,
+                    &mut seen,
+                    Decoder::hash_pixel,
+                    num_pix.saturating_sub(self.cur_pix),
+                    |count, pix| sink.pixels(PixelsIter::new(count, pix)),
+                );
+                self.last_pixel = Pixel::from_bits(state);
+                self.dec_buffer = seen.map(Pixel::from_bits);
+                self.cur_pix += res.pixels;
+                self.bytes_fed += res.consumed as u64;
+                i += res.consumed;
+                if self.cur_pix == num_pix {
+                    self.state = StreamDecoderState::ParsingTrailer(0);
+                }
+            }
+
+            // The header, an op cut off by the end of `data`, or the end marker.
+            let Some(&byte) = data.get(i) else {
+                break;
+            };
+            i += 1;
+            match self.feed(byte)? {
+                StreamDecoderOutput::HeaderDone(header) => sink.header(&header),
+                StreamDecoderOutput::Pixels(it) => sink.pixels(it),
+                StreamDecoderOutput::Finished => return Ok(i),
+                _ => {}
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// Decodes a whole image from `iter` one byte at a time, returning its header and pixels.
+    ///
+    /// This is the loop over [feed](crate::stream::StreamDecoder::feed()) that an in-memory buffer
+    /// or `Read::bytes` otherwise needs, with the pixels collected into a `Vec`. The decoder is
+    /// reset first, and the 8 byte end marker is checked after the last pixel. Running out of bytes
+    /// before then is an `Error::Io` with `ErrorKind::UnexpectedEof` (see
+    /// [finish](crate::stream::StreamDecoder::finish)). Bytes after the end marker are left in the
+    /// iterator, so pass `iter.by_ref()` to get at them.
+    pub fn decode_from_iter(
+        &mut self,
+        iter: impl Iterator<Item = u8>,
+    ) -> Result<(Header, Vec<Pixel>), Error> {
+        self.reset();
+
+        let mut img = Vec::new();
+        for byte in iter {
+            match self.feed(byte)? {
+                StreamDecoderOutput::HeaderDone(header) => img.header(&header),
+                StreamDecoderOutput::Pixels(it) => it.extend_into(&mut img),
+                StreamDecoderOutput::Finished => break,
+                _ => {}
+            }
+        }
+
+        Ok((self.finish()?, img))
     }
 
     /// The main feeding function for decoding a QOI image as a stream of bytes.
     ///
     /// The user is expected to pass in the bytes of a QOI image sequentially, starting from the
-    /// first byte of the header and ending with the last byte of the image (we techically stop
-    /// before the ending sentinel).
+    /// first byte of the header and ending with the last byte of the 8 byte end marker.
     ///
     /// The function will return a `Result<StreamDecoderOutput, Error>`, where all errors are
     /// passed through the result and all decoded values are passed through the
@@ -139,12 +375,27 @@ impl StreamDecoder {
     /// done) until the image is finished (marked by `Finished`). `NeedsMore` can be ignored and
     /// is purely informational.
     ///
+    /// After the last pixel, the end marker is checked one byte at a time, and a wrong byte is an
+    /// `Error::DecodingError`. `Finished` is returned exactly once, for the last byte of the end
+    /// marker. Any byte fed after that returns
+    /// `Error::FeedAfterFinished` rather than being silently dropped, so the bytes of a following
+    /// image are never lost. Call [reset](crate::stream::StreamDecoder::reset()) before feeding the
+    /// next image.
+    ///
     /// See [Decoder](crate::dec::Decoder) for a chunked decoder that stores all data in memory.
     /// `Decoder` generally has a simpler interface and is faster than `StreamDecoder`.
     ///
     /// Internally, feed is a big state machine that takes in a single byte and uses it's internal
     /// state from the previous byte(s) to properly parse a QOI opcode. See the QOI spec
     /// [here](https://qoiformat.org) for more information.
+    ///
+    /// Be careful when filling a buffer with `Read::read` and feeding it byte by byte: `read` is
+    /// allowed to return fewer bytes than the buffer holds, and only the returned count of bytes
+    /// should be fed. Feeding the stale tail of the buffer silently corrupts the image. See
+    /// [feed_read](crate::stream::StreamDecoder::feed_read) for a helper that avoids this.
+    // Without this, calls from other crates can't inline feed, which costs a per-byte loop
+    // around 25% (see benches/feed.rs).
+    #[inline]
     pub fn feed(&mut self, byte: u8) -> Result<StreamDecoderOutput, Error> {
         use StreamDecoderOutput as Output;
         use StreamDecoderState as State;
@@ -159,22 +410,16 @@ impl StreamDecoder {
         // Very big state machine below.
         let out: Result<Output, Error> = match self.state {
             State::NotStarted => Err(Error::DecodingError(
-                "Not started should not be parsed!".to_string(),
+                err_msg!("Not started should not be parsed!"),
             )),
             State::ParsingHeader(c) => {
                 match c {
                     // If we're still parsing the first 4 bytes, check the magic bytes
                     0..=3 => {
-                        let res = match c {
-                            0 => byte == b'q',
-                            1 => byte == b'o',
-                            2 => byte == b'i',
-                            3 => byte == b'f',
-                            _ => false,
-                        };
+                        let res = QOI_MAGIC.get(c as usize) == Some(&byte);
 
                         if !res {
-                            return Err(Error::HeaderParseError(format!(
+                            return Err(Error::HeaderParseError(err_msg!(
                                 "Failed to parse header: idx={}",
                                 c
                             )));
@@ -201,14 +446,18 @@ impl StreamDecoder {
                             self.state = State::ParsingHeader(c + 1);
 
                             if c == 7 {
+                                self.header.width = v;
                                 self.num_pix = Some(v as u64);
                                 Ok(Output::ImageWidthParsed(v))
                             } else {
-                                self.num_pix = Some(self.num_pix.unwrap() * v as u64);
+                                self.header.height = v;
+                                self.num_pix = Some(self.header.width as u64 * v as u64);
                                 Ok(Output::ImageHeightParsed(v))
                             }
                         } else {
-                            self.buffer[(c % 4) as usize] = byte;
+                            if let Some(b) = self.buffer.get_mut((c % 4) as usize) {
+                                *b = byte;
+                            }
 
                             self.state = State::ParsingHeader(c + 1);
                             Ok(Output::NeedMore((11 - c) % 4))
@@ -217,19 +466,29 @@ impl StreamDecoder {
                     // TODO: Collapse 12 and 13 into one match statement.
                     12 => {
                         let ch = byte.try_into()?;
+                        self.header.channels = ch;
 
                         self.state = State::ParsingHeader(c + 1);
                         Ok(Output::ImageChannelParsed(ch))
                     }
                     13 => {
-                        let cs = byte.try_into()?;
+                        self.header.colorspace = byte.try_into()?;
+
+                        // An image without pixels is invalid, like in Header::from_bytes. This is
+                        // only reported here, so that the whole header has been checked first.
+                        if self.header.width == 0 || self.header.height == 0 {
+                            return Err(Error::InvalidDimensions {
+                                width: self.header.width,
+                                height: self.header.height,
+                            });
+                        }
 
                         // We finish the header after colorspace
                         self.state = State::ParsingOp(0, -1);
-                        Ok(Output::ImageColorspaceParsed(cs))
+                        Ok(Output::HeaderDone(self.header.clone()))
                     }
                     _ => Err(Error::HeaderParseError(
-                        "Invalid index into header.".to_string(),
+                        err_msg!("Invalid index into header."),
                     )),
                 }
             }
@@ -267,14 +526,14 @@ impl StreamDecoder {
                             2 => {
                                 self.last_pixel.b = byte;
                                 let hash = Decoder::hash_pixel(self.last_pixel);
-                                self.dec_buffer[(hash % 64) as usize] = self.last_pixel;
+                                *seen_slot(&mut self.dec_buffer, hash) = self.last_pixel;
 
                                 count = 1;
                                 self.state = State::ParsingOp(0, -1);
                                 Ok(Output::Pixels(PixelsIter::new(1, self.last_pixel)))
                             }
                             _ => Err(Error::DecodingError(
-                                "RGB parsed too many bytes".to_string(),
+                                err_msg!("RGB parsed too many bytes"),
                             )),
                         }
                     }
@@ -305,21 +564,30 @@ impl StreamDecoder {
                             3 => {
                                 self.last_pixel.a = byte;
                                 let hash = Decoder::hash_pixel(self.last_pixel);
-                                self.dec_buffer[(hash % 64) as usize] = self.last_pixel;
+                                *seen_slot(&mut self.dec_buffer, hash) = self.last_pixel;
 
                                 count = 1;
                                 self.state = State::ParsingOp(0, -1);
                                 Ok(Output::Pixels(PixelsIter::new(1, self.last_pixel)))
                             }
                             _ => Err(Error::DecodingError(
-                                "RGBA parsed too many bytes".to_string(),
+                                err_msg!("RGBA parsed too many bytes"),
                             )),
                         }
                     }
                     _ => match op & 0xc0 {
                         // Requires 1 bytes
                         ops::QOI_OP_INDEX => {
-                            self.last_pixel = self.dec_buffer[op as usize];
+                            self.last_pixel = *seen_slot(&mut self.dec_buffer, op);
+
+                            let index = op & 0x3f;
+                            if self.warn_unseen_index
+                                && self.unseen_slot == 0
+                                && Decoder::hash_pixel(self.last_pixel) & 0x3f != index
+                            {
+                                self.unseen_slot = index;
+                                self.unseen_at = self.cur_pix;
+                            }
 
                             count = 1;
                             self.state = State::ParsingOp(0, -1);
@@ -342,7 +610,7 @@ impl StreamDecoder {
                                 u8::wrapping_add(self.last_pixel.b, u8::wrapping_sub(db, 2));
 
                             let hash = Decoder::hash_pixel(self.last_pixel);
-                            self.dec_buffer[(hash % 64) as usize] = self.last_pixel;
+                            *seen_slot(&mut self.dec_buffer, hash) = self.last_pixel;
 
                             count = 1;
                             self.state = State::ParsingOp(0, -1);
@@ -374,37 +642,78 @@ impl StreamDecoder {
                                 );
 
                                 let hash = Decoder::hash_pixel(self.last_pixel);
-                                self.dec_buffer[(hash % 64) as usize] = self.last_pixel;
+                                *seen_slot(&mut self.dec_buffer, hash) = self.last_pixel;
 
                                 count = 1;
                                 self.state = State::ParsingOp(0, -1);
                                 Ok(Output::Pixels(PixelsIter::new(1, self.last_pixel)))
                             }
                             _ => Err(Error::DecodingError(
-                                "Luma parsed too many bytes".to_string(),
+                                err_msg!("Luma parsed too many bytes"),
                             )),
                         },
                         // Requires 1 byte
                         ops::QOI_OP_RUN => {
                             // Grab the number of pixels in the run.
                             // Run is biased by one, meaning we add one to the value.
-                            let run = (op & 0x3f) + 1;
+                            let mut run = (op & 0x3f) + 1;
+
+                            // A run may not extend past the end of the image. The chunked decoder
+                            // implicitly drops the extra pixels, so we do the same here. This keeps
+                            // cur_pix from overshooting num_pix and never reaching the end marker.
+                            if let Some(num_pix) = self.num_pix {
+                                let remaining = num_pix.saturating_sub(self.cur_pix);
+                                if self.strict && run as u64 > remaining {
+                                    return Err(Error::DecodingError(err_msg!(
+                                        "A run continues for {} pixels past the end of the image",
+                                        run as u64 - remaining
+                                    )));
+                                }
+                                run = u64::min(run as u64, remaining) as u8;
+                            }
+
+                            // The pixel is usually in the buffer already, except for a run at
+                            // the very start of the image.
+                            let hash = Decoder::hash_pixel(self.last_pixel);
+                            *seen_slot(&mut self.dec_buffer, hash) = self.last_pixel;
 
                             count = run;
                             self.state = State::ParsingOp(0, -1);
                             Ok(Output::Pixels(PixelsIter::new(run, self.last_pixel)))
                         }
-                        _ => Err(Error::DecodingError("Invalid op found".to_string())),
+                        _ => Err(Error::DecodingError(err_msg!("Invalid op found"))),
                     },
                 }
             }
-            State::Finished => Ok(Output::Finished),
+            State::ParsingTrailer(c) => {
+                let expected = ops::QOI_END_MARKER.get(c as usize).copied();
+                if expected != Some(byte) {
+                    return Err(Error::DecodingError(err_msg!(
+                        "Expected byte {} of the QOI end marker, found {}",
+                        c,
+                        byte
+                    )));
+                }
+                let left = ops::QOI_END_MARKER.len() as u8 - 1 - c;
+                if left == 0 {
+                    self.state = State::Done;
+                    Ok(Output::Finished)
+                } else {
+                    self.state = State::ParsingTrailer(c + 1);
+                    Ok(Output::NeedMore(left))
+                }
+            }
+            State::Done => Err(Error::FeedAfterFinished),
         };
 
         self.cur_pix += count as u64;
+        self.bytes_fed += out.is_ok() as u64;
         //println!("{}", self.cur_pix);
-        if self.num_pix.is_some() && self.cur_pix == self.num_pix.unwrap() {
-            self.state = State::Finished;
+        // Only checked once the header is done, as num_pix is known from the height on.
+        if let State::ParsingOp(..) = self.state {
+            if self.num_pix == Some(self.cur_pix) {
+                self.state = State::ParsingTrailer(0);
+            }
         }
 
         out
@@ -424,6 +733,13 @@ impl PixelsIter {
     fn new(count: u8, pixel: Pixel) -> Self {
         PixelsIter { count, pixel }
     }
+
+    /// Appends the remaining pixels to `vec`, reserving room for all of them first. This is the
+    /// same as `vec.extend(self)`, but fills the pixels in one go rather than one `next` at a
+    /// time, which helps when building one `Vec` out of the output of many `feed` calls.
+    pub fn extend_into(self, vec: &mut Vec<Pixel>) {
+        vec.resize(vec.len() + self.count as usize, self.pixel);
+    }
 }
 
 impl Iterator for PixelsIter {
@@ -444,101 +760,563 @@ impl Iterator for PixelsIter {
     }
 }
 
+impl ExactSizeIterator for PixelsIter {}
+
 #[cfg(test)]
+#[allow(clippy::indexing_slicing)]
 mod tests {
-    use crate::stream::dec::{Pixel, StreamDecoder, StreamDecoderOutput};
-    use image::io::Reader as ImageReader;
-    use std::fs::File;
-    use std::io::{BufReader, Read};
-    use std::path::PathBuf;
+    use crate::stream::dec::{Pixel, PixelsIter, StreamDecoder, StreamDecoderOutput};
+    use std::io::Read;
 
     #[test]
-    fn test_stream_decoder() {
+    fn test_run_overshoot() {
+        // A 1x1 image containing a run of 62. The run must be clamped to the image size, otherwise
+        // cur_pix skips past num_pix and the decoder never finishes.
+        let data = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
 
         let mut sdec = StreamDecoder::new();
+        let mut img: Vec<Pixel> = Vec::new();
+        let mut finished = false;
 
-        let qoi_file = BufReader::new(File::open(PathBuf::from("tests/dice.qoi")).unwrap());
+        for byte in data {
+            match sdec.feed(byte).unwrap() {
+                StreamDecoderOutput::Pixels(it) => img.extend(it),
+                StreamDecoderOutput::Finished => {
+                    finished = true;
+                    break;
+                }
+                _ => {}
+            }
+            assert!(sdec.cur_pix() <= sdec.num_pix().unwrap_or(0));
+        }
 
-        let mut iter = qoi_file.bytes();
+        assert!(finished);
+        assert_eq!(img.len(), 1);
 
-        let mut img_size: u64 = 0;
-        let mut img: Vec<Pixel> = Vec::new();
+        // Strictly, the run is an error instead.
+        let mut sdec = StreamDecoder::new().strict(true);
+        let data = std::fs::read("tests/corpus/lenient/run-overshoot.qoi").unwrap();
+        let res = data.into_iter().try_for_each(|byte| sdec.feed(byte).map(drop));
+        assert!(matches!(res, Err(crate::utils::Error::DecodingError(_))));
+    }
 
-        while let Some(b) = iter.next() {
-            match b {
-                Ok(byte) => {
-                    match sdec.feed(byte).unwrap() {
-                        // The StreamDecoder informs us if it needs more bytes after recieving one
-                        // byte. This allows us to work on just getting those bytes and checking
-                        // the state again later.
-                        StreamDecoderOutput::NeedMore(_) => {
-                            // println!("needs more");
-                        }
+    #[test]
+    fn test_header_done() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, _) = crate::dec::Decoder::new().decode_slice(&data).unwrap();
 
-                        // After recieving the image size, we can reserve space for the image
-                        // buffer.
-                        StreamDecoderOutput::ImageWidthParsed(w) => {
-                            println!("width: {}", w);
-                            img_size = w as u64;
-                        }
-                        StreamDecoderOutput::ImageHeightParsed(h) => {
-                            println!("height: {}", h);
-                            img_size *= h as u64;
-                            img.reserve_exact(img_size as usize);
-                        }
+        let mut sdec = StreamDecoder::new();
+        let mut done = Vec::new();
+        for (i, &byte) in data.iter().enumerate() {
+            match sdec.feed(byte).unwrap() {
+                StreamDecoderOutput::HeaderDone(h) => done.push((i, h)),
+                StreamDecoderOutput::Finished => break,
+                _ => {}
+            }
+        }
 
-                        // When pixels are ready to be produced, the StreamDecoder returns an
-                        // iterator that produces those pixels. This is a lightweight iterator,
-                        // with just a Pixel and u8 count attached (5 bytes in total).
-                        StreamDecoderOutput::Pixels(it) => {
-                            for pix in it {
-                                //if img.len() == (img_size as usize) {
-                                //    assert!(false)
-                                //}
-                                img.push(pix);
-                            }
-                        }
+        // Emitted once, for the colorspace byte, and never again while decoding pixels.
+        assert_eq!(done, vec![(13, header)]);
+    }
 
-                        StreamDecoderOutput::ImageChannelParsed(c) => {
-                            println!("channel: {}", c);
-                        }
-                        StreamDecoderOutput::ImageColorspaceParsed(c) => {
-                            println!("colorspace: {}", c);
-                        }
+    #[test]
+    fn test_feed_after_finished() {
+        use crate::utils::Error;
 
-                        // The StreamDecoder informs us when it has returned all pixels in the
-                        // image.
-                        StreamDecoderOutput::Finished => {
-                            println!("Finished");
-                            break;
-                        }
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut sdec = StreamDecoder::new();
+        let mut bytes = data.iter();
+        for &byte in bytes.by_ref() {
+            if let StreamDecoderOutput::Finished = sdec.feed(byte).unwrap() {
+                break;
+            }
+        }
+
+        // Finished comes with the last byte of the end marker. The start of another image is an
+        // error.
+        assert_eq!(bytes.next(), None);
+        assert!(matches!(sdec.feed(b'q'), Err(Error::FeedAfterFinished)));
+        assert!(matches!(sdec.feed(b'o'), Err(Error::FeedAfterFinished)));
+
+        // A reset makes the decoder usable again.
+        sdec.reset();
+        let mut finished = 0;
+        for &byte in &data {
+            if let StreamDecoderOutput::Finished = sdec.feed(byte).unwrap() {
+                finished += 1;
+                break;
+            }
+        }
+        assert_eq!(finished, 1);
+    }
+
+    #[test]
+    fn test_end_marker() {
+        use crate::dec::ops::QOI_END_MARKER;
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, _) = crate::dec::Decoder::new().decode_slice(&data).unwrap();
+        let marker_start = data.len() - QOI_END_MARKER.len();
+
+        // The end marker is checked byte by byte, asking for what is left of it.
+        let mut sdec = StreamDecoder::new();
+        sdec.feed_all(&data[..marker_start], &mut Vec::new()).unwrap();
+        assert!(matches!(sdec.finish(), Err(Error::Io(_))));
+        for (i, &byte) in data[marker_start..data.len() - 1].iter().enumerate() {
+            assert!(matches!(
+                sdec.feed(byte).unwrap(),
+                StreamDecoderOutput::NeedMore(left) if left as usize == 7 - i
+            ));
+            assert!(!sdec.is_finished());
+        }
+        assert!(matches!(
+            sdec.finish(),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(sdec.feed(1).unwrap(), StreamDecoderOutput::Finished));
+        assert_eq!(sdec.finish().unwrap(), header);
+
+        // A wrong byte anywhere in the end marker is an error.
+        for i in marker_start..data.len() {
+            let mut bad = data.clone();
+            bad[i] ^= 0x80;
+            let mut sdec = StreamDecoder::new();
+            let res = sdec.feed_all(&bad, &mut Vec::new());
+            assert!(matches!(res, Err(Error::DecodingError(_))), "byte {}", i);
+            assert!(!sdec.is_finished());
+        }
+    }
+
+    #[test]
+    fn test_zero_pixels() {
+        use crate::dec::{Channels, Colorspace, Header};
+        use crate::utils::Error;
+
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let header = Header::new(width, height, Channels::RGB, Colorspace::Linear);
+            let data = header.to_bytes();
+
+            // The whole header is parsed, channels and colorspace included, before the dimensions
+            // are rejected like in Header::from_bytes.
+            let mut sdec = StreamDecoder::new();
+            for &byte in &data[..13] {
+                sdec.feed(byte).unwrap();
+            }
+            assert!(matches!(
+                sdec.feed(data[13]),
+                Err(Error::InvalidDimensions { width: w, height: h }) if (w, h) == (width, height)
+            ));
+
+            let mut bad = data;
+            bad[13] = 7;
+            let mut sdec = StreamDecoder::new();
+            let res = bad.iter().try_for_each(|&byte| sdec.feed(byte).map(drop));
+            assert!(matches!(res, Err(Error::InvalidColorspace(7))));
+        }
+    }
+
+    #[test]
+    fn test_feed_slice() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = crate::dec::Decoder::new().decode_slice(&data).unwrap();
+
+        for size in [1, 7, 4096, data.len()] {
+            let mut sdec = StreamDecoder::new();
+            let mut img = Vec::new();
+            let mut finished = false;
+            let mut consumed = 0;
+
+            for chunk in data.chunks(size) {
+                consumed += sdec
+                    .feed_slice(chunk, |out| match out {
+                        StreamDecoderOutput::Pixels(it) => img.extend(it),
+                        StreamDecoderOutput::Finished => finished = true,
+                        _ => {}
+                    })
+                    .unwrap();
+                if finished {
+                    break;
+                }
+            }
+
+            assert!(img == expected);
+            // Finished comes with the last byte of the end marker.
+            assert_eq!(consumed, data.len());
+            assert_eq!(sdec.bytes_fed(), consumed as u64);
+        }
+    }
+
+    #[test]
+    fn test_bytes_fed() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut sdec = StreamDecoder::new();
+        for (i, &byte) in data[..20].iter().enumerate() {
+            sdec.feed(byte).unwrap();
+            assert_eq!(sdec.bytes_fed(), i as u64 + 1);
+        }
+
+        // feed_all counts the bytes of its fast path too, and stops at Finished.
+        let used = sdec.feed_all(&data[20..], &mut Vec::new()).unwrap();
+        assert_eq!(sdec.bytes_fed(), 20 + used as u64);
+        assert_eq!(sdec.bytes_fed(), data.len() as u64);
+
+        // Rejected bytes aren't counted.
+        assert!(sdec.feed(0).is_err());
+        assert_eq!(sdec.bytes_fed(), data.len() as u64);
+
+        sdec.reset();
+        assert_eq!(sdec.bytes_fed(), 0);
+    }
+
+    #[test]
+    fn test_feed_read() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = crate::dec::Decoder::new().decode_slice(&data).unwrap();
+
+        // A reader that never hands out more than one byte at a time, however much is asked for.
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = usize::min(1, buf.len()).min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let mut reader = Trickle(&data);
+        let mut sdec = StreamDecoder::new();
+        let mut img = Vec::new();
+        loop {
+            match sdec.feed_read(&mut reader).unwrap() {
+                StreamDecoderOutput::Pixels(it) => img.extend(it),
+                StreamDecoderOutput::Finished => break,
+                _ => {}
+            }
+        }
+        assert!(img == expected);
+
+        // Running out of bytes is an error rather than a hang or a panic.
+        let mut sdec = StreamDecoder::new();
+        let mut reader = &data[..5];
+        for _ in 0..5 {
+            assert!(sdec.feed_read(&mut reader).is_ok());
+        }
+        assert!(matches!(
+            sdec.feed_read(&mut reader),
+            Err(crate::utils::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    /// Decodes `data` with alternating `feed_all` calls and single-byte `feed` calls, switching
+    /// after every `splits[i]` bytes. Returns the pixels, the headers seen, and the bytes consumed.
+    fn feed_mixed(data: &[u8], splits: &[usize]) -> (Vec<Pixel>, usize, usize) {
+        use crate::dec::Header;
+        use crate::stream::{PixelSink, PixelsIter};
+
+        #[derive(Default)]
+        struct Sink {
+            img: Vec<Pixel>,
+            headers: usize,
+        }
+
+        impl PixelSink for Sink {
+            fn header(&mut self, _: &Header) {
+                self.headers += 1;
+            }
+
+            fn pixels(&mut self, pixels: PixelsIter) {
+                self.img.extend(pixels);
+            }
+        }
+
+        let mut sdec = StreamDecoder::new();
+        let mut sink = Sink::default();
+        let mut pos = 0;
+        for (i, &split) in splits.iter().cycle().enumerate() {
+            let chunk = &data[pos..usize::min(pos + split, data.len())];
+            if i % 2 == 0 {
+                let used = sdec.feed_all(chunk, &mut sink).unwrap();
+                pos += used;
+                if sdec.is_finished() || pos == data.len() {
+                    break;
+                }
+            } else {
+                for &byte in chunk {
+                    pos += 1;
+                    match sdec.feed(byte).unwrap() {
+                        StreamDecoderOutput::HeaderDone(h) => sink.header(&h),
+                        StreamDecoderOutput::Pixels(it) => sink.pixels(it),
+                        StreamDecoderOutput::Finished => return (sink.img, sink.headers, pos),
+                        _ => {}
                     }
                 }
-                // If we failed to pull a byte out of the file, then throw an error.
-                Err(e) => {
-                    println!("{}", e);
-                    assert!(false)
+            }
+        }
+        (sink.img, sink.headers, pos)
+    }
+
+    #[test]
+    fn test_feed_all() {
+        use crate::dec::Decoder;
+        use crate::testgen::{self, Pattern};
+
+        let mut images = vec![std::fs::read("tests/dice.qoi").unwrap()];
+        for pattern in [Pattern::Mixed, Pattern::Gradient, Pattern::Solid] {
+            let img = testgen::generate(pattern, 67, 31, 5);
+            images.push(testgen::encode(&img, 67, 31));
+        }
+
+        // Splits that land inside the header, inside ops of every length, and inside runs.
+        let splits: [&[usize]; 6] = [
+            &[usize::MAX],
+            &[1],
+            &[3, 1],
+            &[13, 2, 7, 4],
+            &[14, 5, 100, 3],
+            &[1000, 1, 1, 1, 1],
+        ];
+
+        for data in &images {
+            let (_, expected) = Decoder::new().decode_slice(data).unwrap();
+            for split in splits {
+                let (img, headers, consumed) = feed_mixed(data, split);
+                assert!(img == expected, "splits {:?}", split);
+                assert_eq!(headers, 1);
+                // Like feed_slice, this stops after the last byte of the end marker.
+                assert_eq!(consumed, data.len(), "splits {:?}", split);
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_updates_index() {
+        use crate::dec::{Channels, Colorspace, Decoder, Header};
+
+        // A run before any other op, then a QOI_OP_INDEX of the run's pixel. The index only holds
+        // the pixel if the run put it there.
+        let header = Header::new(2, 1, Channels::RGBA, Colorspace::sRGB);
+        let hash = Decoder::hash_pixel(Pixel::new(0, 0, 0, 255)) & 0x3f;
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(&[0xc0, hash]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+        assert_eq!(expected, [Pixel::new(0, 0, 0, 255); 2]);
+        // Through feed_all's loop, and through feed.
+        for split in [&[usize::MAX][..], &[14, 1]] {
+            assert!(feed_mixed(&data, split).0 == expected);
+        }
+    }
+
+    #[test]
+    fn test_warn_unseen_index() {
+        use crate::dec::{ops, Channels, Colorspace, Decoder, Header};
+
+        // A QOI_OP_INDEX of slot 5 before anything was written to it, then a correct one of the
+        // pixel the DIFF writes to its slot.
+        let header = Header::new(3, 1, Channels::RGBA, Colorspace::sRGB);
+        let diff = Pixel::new(1, 1, 1, 0);
+        let slot = Decoder::hash_pixel(diff) & 0x3f;
+        let mut data = header.to_bytes().to_vec();
+        data.extend([ops::QOI_OP_INDEX | 5, ops::QOI_OP_DIFF | 0x3f, ops::QOI_OP_INDEX | slot]);
+        data.extend(ops::QOI_END_MARKER);
+
+        // Warnings are taken after each byte, and the pixels come out as usual.
+        let decode = |mut sdec: StreamDecoder| {
+            let (mut img, mut warnings) = (Vec::new(), Vec::new());
+            for &byte in &data {
+                if let StreamDecoderOutput::Pixels(it) = sdec.feed(byte).unwrap() {
+                    img.extend(it);
                 }
+                warnings.extend(sdec.take_warning());
             }
+            (img, warnings)
+        };
+
+        let (img, warnings) = decode(StreamDecoder::new().warn_unseen_index(true));
+        assert_eq!(img, [Pixel::default(), diff, diff]);
+        #[cfg(feature = "fmt")]
+        assert_eq!(warnings, ["OP_INDEX(5) at pixel 0 reads a slot that was never written"]);
+        assert_eq!(warnings.len(), 1);
+
+        let (default_img, warnings) = decode(StreamDecoder::new());
+        assert_eq!(default_img, img);
+        assert!(warnings.is_empty());
+
+        // feed_all checks every op too, and only the first warning is kept until it is taken.
+        let header = Header::new(4, 1, Channels::RGBA, Colorspace::sRGB);
+        let mut twice = header.to_bytes().to_vec();
+        twice.push(ops::QOI_OP_INDEX | 6);
+        twice.extend(&data[14..]);
+        let mut sdec = StreamDecoder::new().warn_unseen_index(true);
+        let mut fed = Vec::new();
+        sdec.feed_all(&twice, &mut fed).unwrap();
+        assert_eq!(fed, [Pixel::default(), Pixel::default(), diff, diff]);
+        #[cfg(feature = "fmt")]
+        assert_eq!(
+            sdec.take_warning().unwrap(),
+            "OP_INDEX(6) at pixel 0 reads a slot that was never written"
+        );
+        #[cfg(not(feature = "fmt"))]
+        assert!(sdec.take_warning().is_some());
+        assert!(sdec.take_warning().is_none());
+
+        // A correct image has no warnings.
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut sdec = StreamDecoder::new().warn_unseen_index(true);
+        sdec.feed_all(&data, &mut Vec::new()).unwrap();
+        assert!(sdec.is_finished());
+        assert!(sdec.take_warning().is_none());
+    }
+
+    #[test]
+    fn test_extend_into() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (_, expected) = crate::dec::Decoder::new().decode_slice(&data).unwrap();
+
+        let mut sdec = StreamDecoder::new();
+        let mut img = Vec::new();
+        sdec.feed_slice(&data, |out| {
+            if let StreamDecoderOutput::Pixels(it) = out {
+                it.extend_into(&mut img);
+            }
+        })
+        .unwrap();
+        assert!(img == expected);
+
+        // Only the pixels that are left are appended.
+        let p = Pixel::new(1, 2, 3, 4);
+        let mut it = PixelsIter::new(5, p);
+        it.next();
+        let mut img = vec![Pixel::default()];
+        it.extend_into(&mut img);
+        assert_eq!(img, [Pixel::default(), p, p, p, p]);
+    }
+
+    #[test]
+    fn test_decode_from_iter() {
+        use crate::dec::Decoder;
+        use crate::utils::Error;
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let expected = Decoder::new().decode_slice(&data).unwrap();
+
+        let mut sdec = StreamDecoder::new();
+        let (header, img) = sdec.decode_from_iter(data.clone().into_iter()).unwrap();
+        assert_eq!(header, expected.0);
+        assert!(img == expected.1);
+
+        // The decoder is reset, so it can be reused. Anything after the end marker is left behind.
+        let mut longer = data.clone();
+        longer.extend([1, 2, 3]);
+        let mut iter = longer.into_iter();
+        assert!(sdec.decode_from_iter(iter.by_ref()).unwrap().1 == expected.1);
+        assert_eq!(iter.collect::<Vec<_>>(), [1, 2, 3]);
+
+        // Running out before the end of the end marker, or a wrong end marker.
+        for len in [0, 14, data.len() - 8, data.len() - 1] {
+            let res = sdec.decode_from_iter(data[..len].iter().copied());
+            assert!(
+                matches!(res, Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::UnexpectedEof),
+                "{} bytes: {:?}",
+                len,
+                res.map(|(h, _)| h)
+            );
         }
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 2;
+        let res = sdec.decode_from_iter(bad.into_iter());
+        assert!(matches!(res, Err(Error::DecodingError(_))));
+    }
+
+    mod props {
+        use crate::dec::Decoder;
+        use crate::stream::dec::{StreamDecoder, StreamDecoderOutput};
+        use crate::testgen::{self, Pattern};
+        use proptest::prelude::*;
+
+        /// An encoded test image along with its dimensions.
+        fn image() -> impl Strategy<Value = (Vec<u8>, u32, u32)> {
+            let pattern = proptest::sample::select(Pattern::ALL.to_vec());
+            (pattern, 1..=24u32, 1..=24u32, any::<u64>()).prop_map(
+                |(pattern, width, height, seed)| {
+                    let img = testgen::generate(pattern, width, height, seed);
+                    (testgen::encode(&img, width, height), width, height)
+                },
+            )
+        }
+
+        /// The sizes of the chunks the encoded bytes arrive in. The last chunk takes whatever is
+        /// left over.
+        fn chunking() -> impl Strategy<Value = Vec<usize>> {
+            proptest::collection::vec(1..=32usize, 0..16)
+        }
+
+        proptest! {
+            #[test]
+            fn prop_stream_matches_chunked(
+                (data, width, height) in image(),
+                chunks in chunking(),
+            ) {
+                let (_, expected) = Decoder::new().decode_slice(&data).unwrap();
+                let num_pix = width as u64 * height as u64;
 
-        // Using image's QOI reader as a known-good reader. We should parse to the same bytes.
-        let img_qoi_img = ImageReader::open("tests/dice.qoi")
-            .unwrap()
-            .decode()
-            .unwrap();
-        let img_qoi_img = img_qoi_img.into_bytes();
+                let mut sdec = StreamDecoder::new();
+                let mut img = Vec::new();
+                let mut finished = false;
 
-        let img: Vec<u8> = img.into_iter().flat_map(|a| a.to_bytes()).collect();
+                // Split the bytes up the way they would come in over a socket.
+                let mut rest = &data[..];
+                let mut chunks = chunks.into_iter();
+                while !finished && !rest.is_empty() {
+                    let len = chunks.next().unwrap_or(rest.len()).min(rest.len());
+                    let (chunk, tail) = rest.split_at(len);
+                    rest = tail;
 
-        assert_eq!(img.len(), img_qoi_img.len());
+                    for &byte in chunk {
+                        match sdec.feed(byte).unwrap() {
+                            StreamDecoderOutput::Pixels(it) => img.extend(it),
+                            StreamDecoderOutput::Finished => {
+                                finished = true;
+                                break;
+                            }
+                            _ => {}
+                        }
+                        prop_assert!(img.len() as u64 <= num_pix);
+                    }
+                }
+
+                prop_assert!(img == expected);
+            }
+
+            #[test]
+            fn prop_need_more_stays_in_bounds((data, width, height) in image()) {
+                let num_pix = width as u64 * height as u64;
+                // NeedMore may not reach into the end marker before the last pixel, and asks for
+                // exactly what is left of it after.
+                let end = data.len() - 8;
+
+                let mut sdec = StreamDecoder::new();
+                for (i, &byte) in data.iter().enumerate() {
+                    match sdec.feed(byte).unwrap() {
+                        StreamDecoderOutput::NeedMore(n) if sdec.cur_pix() == num_pix => {
+                            prop_assert_eq!(i + 1 + n as usize, data.len());
+                        }
+                        StreamDecoderOutput::NeedMore(n) => {
+                            prop_assert!((1..=4).contains(&n));
+                            prop_assert!(i + 1 + n as usize <= end);
+                        }
+                        StreamDecoderOutput::Finished => prop_assert_eq!(i + 1, data.len()),
+                        _ => {}
+                    }
+                    prop_assert!(sdec.cur_pix() <= num_pix);
+                }
 
-        // Not doing an assert_eq on qoi_img and img_qoi_img because it blows up the terminal log.
-        for (i, (p1, p2)) in img_qoi_img.iter().zip(img.iter()).enumerate() {
-            if p1 != p2 {
-                println!("{}", i);
+                prop_assert_eq!(sdec.cur_pix(), num_pix);
+                prop_assert!(sdec.is_finished());
             }
-            assert_eq!(p1, p2)
         }
     }
 }
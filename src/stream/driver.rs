@@ -0,0 +1,323 @@
+use crate::dec::{Header, Pixel};
+use crate::stream::dec::{PixelsIter, StreamDecoder};
+use crate::utils::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::mpsc;
+
+/// The chunk size used by [ChunkedDriver] unless another is set.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Receives the output of a [ChunkedDriver].
+pub trait PixelSink {
+    /// Called once with the header, before any pixels.
+    fn header(&mut self, _header: &Header) {}
+
+    /// Called with every run of decoded pixels, in order.
+    fn pixels(&mut self, pixels: PixelsIter);
+}
+
+/// Collects the pixels into the `Vec`, which is reserved from the header. Existing contents are
+/// kept, so clear it first when reusing it.
+impl PixelSink for Vec<Pixel> {
+    fn header(&mut self, header: &Header) {
+        let num_pixels = header.width as u64 * header.height as u64;
+        // Only a hint. If it can't be had, the pixels still arrive and the Vec grows as usual.
+        if let Ok(num_pixels) = usize::try_from(num_pixels) {
+            let _ = self.try_reserve_exact(num_pixels);
+        }
+    }
+
+    fn pixels(&mut self, pixels: PixelsIter) {
+        pixels.extend_into(self);
+    }
+}
+
+/// Feeds a [StreamDecoder] from any `Read`, handing the decoded pixels to a [PixelSink].
+///
+/// This is the loop every user of `StreamDecoder` otherwise writes by hand: reading a chunk,
+/// feeding it, handling the outputs, and checking the end marker once the last pixel is decoded.
+///
+/// The reader is read in chunks of [DEFAULT_CHUNK_SIZE] bytes (see
+/// [chunk_size](crate::stream::ChunkedDriver::chunk_size)), so it doesn't need to be buffered. This
+/// also means that anything following the image in the reader may be read and thrown away.
+pub struct ChunkedDriver<R: Read> {
+    reader: R,
+    chunk_size: usize,
+    dec: StreamDecoder,
+    bytes_read: u64,
+}
+
+impl<R: Read> ChunkedDriver<R> {
+    pub fn new(reader: R) -> Self {
+        ChunkedDriver {
+            reader,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            dec: StreamDecoder::new(),
+            bytes_read: 0,
+        }
+    }
+
+    /// Sets the number of bytes read from the reader at a time. A size of 0 is treated as 1.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = usize::max(chunk_size, 1);
+        self
+    }
+
+    /// The number of bytes of the last image run through the driver: its header, ops, and end
+    /// marker. Bytes read past the end marker aren't counted.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Decodes an image from the reader into `sink`, returning its header.
+    ///
+    /// The 8 byte end marker is read and checked after the last pixel. Running out of data before
+    /// then is an `Error::Io` with `ErrorKind::UnexpectedEof`.
+    pub fn run(&mut self, sink: &mut impl PixelSink) -> Result<Header, Error> {
+        self.dec.reset();
+        self.bytes_read = 0;
+
+        let mut buf = vec![0u8; self.chunk_size];
+        while !self.dec.is_finished() {
+            let len = read_chunk(&mut self.reader, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            self.bytes_read += self.dec.feed_all(&buf[..len], sink)? as u64;
+        }
+        self.dec.finish()
+    }
+}
+
+/// The number of buffers [decode_pipelined] splits its channel capacity into.
+const PIPELINE_BUFFERS: usize = 4;
+
+/// Reads the chunks sent by the reader thread of [decode_pipelined], one after the other.
+struct ChannelReader {
+    rx: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    // How much of chunk has been read.
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // The sender is dropped at the end of the file.
+                Err(mpsc::RecvError) => return Ok(0),
+            }
+        }
+
+        let len = usize::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Decodes the QOI image at `path` with two threads: a spawned thread reads the file while the
+/// calling thread decodes what has been read so far.
+///
+/// On spinning disks and network filesystems, this keeps the decoder busy while the next read is
+/// waiting on IO, instead of the two taking turns. `channel_capacity_bytes` bounds how far ahead
+/// of the decoder the reader can get, split into a few buffers that a [ChunkedDriver] decodes as
+/// they arrive. The end marker is checked, and anything after it in the file is ignored.
+///
+/// IO errors are returned as [Error::FileIo], with the path. An error on either side stops both,
+/// and the reader thread has always exited by the time this returns.
+pub fn decode_pipelined(
+    path: &Path,
+    channel_capacity_bytes: usize,
+) -> Result<(Header, Vec<Pixel>), Error> {
+    let chunk_size = usize::max(channel_capacity_bytes / PIPELINE_BUFFERS, 1);
+    let with_path = |source| Error::FileIo {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut file = File::open(path).map_err(with_path)?;
+
+    std::thread::scope(|scope| {
+        // The channel holds at most PIPELINE_BUFFERS - 1 chunks, while the reader fills one more.
+        let (tx, rx) = mpsc::sync_channel(PIPELINE_BUFFERS - 1);
+        scope.spawn(move || loop {
+            let mut buf = vec![0u8; chunk_size];
+            let msg = match file.read(&mut buf) {
+                // The end of the file. Dropping the sender tells the decoder.
+                Ok(0) => return,
+                Ok(len) => {
+                    buf.truncate(len);
+                    Ok(buf)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = msg.is_err();
+            // Sending only fails once the decoder has stopped, so there is nobody to tell.
+            if tx.send(msg).is_err() || failed {
+                return;
+            }
+        });
+
+        // Returning drops the receiver, which stops the reader at its next send. The scope then
+        // waits for it to exit.
+        let reader = ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        let mut img = Vec::new();
+        match ChunkedDriver::new(reader).chunk_size(chunk_size).run(&mut img) {
+            Ok(header) => Ok((header, img)),
+            // Both read errors and the file ending early.
+            Err(Error::Io(e)) => Err(with_path(e)),
+            Err(e) => Err(e),
+        }
+    })
+}
+
+/// Reads the next chunk into `buf`, returning how many bytes were read, or 0 once the reader has
+/// run out.
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, Error> {
+    loop {
+        match reader.read(buf) {
+            Ok(len) => return Ok(len),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{Channels, Colorspace, Decoder, Header};
+    use crate::stream::{ChunkedDriver, PixelSink, PixelsIter};
+    use crate::utils::Error;
+
+    #[test]
+    fn test_chunked_driver() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, expected) = Decoder::new().decode_slice(&data).unwrap();
+
+        // Chunk sizes that split the header, the end marker, and everything in between.
+        for size in [1, 3, 7, 13, 4096, 1 << 16] {
+            let mut driver = ChunkedDriver::new(&data[..]).chunk_size(size);
+            let mut img = Vec::new();
+            assert_eq!(driver.run(&mut img).unwrap(), header);
+            assert!(img == expected, "chunk size {}", size);
+            assert_eq!(driver.bytes_read(), data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_chunked_driver_errors() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+
+        // Cut short, in the ops and in the end marker.
+        for len in [1000, data.len() - 3] {
+            let err = ChunkedDriver::new(&data[..len]).run(&mut Vec::new());
+            assert!(matches!(
+                err,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+            ));
+        }
+
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 2;
+        let err = ChunkedDriver::new(&bad[..]).run(&mut Vec::new());
+        assert!(matches!(err, Err(Error::DecodingError(_))));
+    }
+
+    #[test]
+    fn test_decode_pipelined() {
+        use crate::stream::decode_pipelined;
+        use std::path::Path;
+
+        let path = Path::new("tests/dice.qoi");
+        let data = std::fs::read(path).unwrap();
+        let expected = Decoder::new().decode_slice(&data).unwrap();
+
+        // Capacities that split the header, the ops, and the end marker, and one that holds the
+        // whole file.
+        for capacity in [30, 4096, 1 << 20] {
+            let res = decode_pipelined(path, capacity).unwrap();
+            assert!(res == expected, "capacity {}", capacity);
+        }
+
+        // Cut short, in the ops and in the end marker.
+        let short = std::env::temp_dir().join("qoi-parser-pipelined-short.qoi");
+        for len in [1000, data.len() - 3] {
+            std::fs::write(&short, &data[..len]).unwrap();
+            match decode_pipelined(&short, 4096) {
+                Err(Error::FileIo { path, source }) => {
+                    assert_eq!(path, short);
+                    assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+                }
+                res => panic!("expected Error::FileIo, got {:?}", res.map(|(h, _)| h)),
+            }
+        }
+
+        // A bad end marker, and an error from the decoder side while the reader is still going.
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 2;
+        std::fs::write(&short, &bad).unwrap();
+        assert!(matches!(decode_pipelined(&short, 64), Err(Error::DecodingError(_))));
+        bad[0] = b'x';
+        std::fs::write(&short, &bad).unwrap();
+        assert!(decode_pipelined(&short, 64).is_err());
+        std::fs::remove_file(&short).unwrap();
+
+        let missing = Path::new("tests/missing.qoi");
+        assert!(matches!(
+            decode_pipelined(missing, 4096),
+            Err(Error::FileIo { source, .. }) if source.kind() == std::io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_pixel_sink() {
+        /// Counts pixels without storing them.
+        #[derive(Default)]
+        struct Count {
+            headers: usize,
+            pixels: usize,
+        }
+
+        impl PixelSink for Count {
+            fn header(&mut self, _: &Header) {
+                self.headers += 1;
+            }
+
+            fn pixels(&mut self, pixels: PixelsIter) {
+                self.pixels += pixels.len();
+            }
+        }
+
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let mut count = Count::default();
+        let header = ChunkedDriver::new(&data[..]).run(&mut count).unwrap();
+        assert_eq!(count.headers, 1);
+        assert_eq!(count.pixels, (header.width * header.height) as usize);
+
+        // An image without pixels is invalid, and nothing reaches the sink.
+        let empty = Header::new(0, 3, Channels::RGBA, Colorspace::sRGB);
+        let mut data = empty.to_bytes().to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        let mut count = Count::default();
+        let res = ChunkedDriver::new(&data[..]).run(&mut count);
+        assert!(matches!(res, Err(Error::InvalidDimensions { width: 0, height: 3 })));
+        assert_eq!((count.headers, count.pixels), (0, 0));
+    }
+}
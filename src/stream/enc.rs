@@ -0,0 +1,204 @@
+use crate::dec::{ops, Channels, Colorspace, Header, Pixel};
+use crate::enc::{EncoderState, MAX_ENCODED_PIXEL};
+use std::io::{ErrorKind, Read};
+use std::iter::Fuse;
+
+/// What the EncoderReader will produce next.
+#[derive(Debug, PartialEq, Eq)]
+enum EncoderReaderState {
+    Header,
+    Pixels,
+    Finished,
+}
+
+/// A streaming encoder that produces a QOI image through [Read].
+///
+/// Pixels are pulled from the inner iterator and encoded lazily, only as the caller asks for more
+/// bytes. This allows an encoded image to be piped directly into a file, socket, or compressor
+/// with `std::io::copy` without ever holding the encoded image (or the source pixels) in memory.
+///
+/// Exactly `width * height` pixels are taken from the iterator. Any pixels after that are left in
+/// the iterator, and running out of pixels early is reported as an `UnexpectedEof` error.
+pub struct EncoderReader<I: Iterator<Item = Pixel>> {
+    pixels: Fuse<I>,
+    header: Header,
+    state: EncoderReaderState,
+    enc: EncoderState,
+    remaining: u64,
+    // Bytes that have been encoded but did not fit in the caller's buffer. This needs to hold the
+    // header (14 bytes), which is larger than any op or the end marker.
+    spill: [u8; 14],
+    spill_pos: usize,
+    spill_len: usize,
+}
+
+impl<I: Iterator<Item = Pixel>> EncoderReader<I> {
+    /// Creates an EncoderReader that encodes the pixels from `pixels` as an image with the given
+    /// header fields. Nothing is encoded until the first call to `read`.
+    pub fn new(
+        pixels: I,
+        width: u32,
+        height: u32,
+        channels: Channels,
+        colorspace: Colorspace,
+    ) -> Self {
+        EncoderReader {
+            pixels: pixels.fuse(),
+            header: Header::new(width, height, channels, colorspace),
+            state: EncoderReaderState::Header,
+            enc: EncoderState::new(),
+            remaining: width as u64 * height as u64,
+            spill: [0; 14],
+            spill_pos: 0,
+            spill_len: 0,
+        }
+    }
+
+    /// Fills the spill buffer with the next chunk of encoded bytes. This may produce no bytes when
+    /// a pixel extends a run.
+    fn refill(&mut self) -> std::io::Result<()> {
+        self.spill_pos = 0;
+        self.spill_len = 0;
+
+        match self.state {
+            EncoderReaderState::Header => {
+                self.spill = self.header.to_bytes();
+                self.spill_len = 14;
+                self.state = EncoderReaderState::Pixels;
+            }
+            EncoderReaderState::Pixels if self.remaining == 0 => {
+                // Any run still being built must be written before the end marker.
+                let len = self.enc.flush(&mut self.spill);
+                self.spill[len..len + 8].copy_from_slice(&ops::QOI_END_MARKER);
+                self.spill_len = len + 8;
+                self.state = EncoderReaderState::Finished;
+            }
+            EncoderReaderState::Pixels => {
+                let px = self.pixels.next().ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Pixel iterator ended before width * height pixels",
+                    )
+                })?;
+                self.remaining -= 1;
+
+                let mut out = [0; MAX_ENCODED_PIXEL];
+                let len = self.enc.encode(px, &mut out);
+                self.spill[..len].copy_from_slice(&out[..len]);
+                self.spill_len = len;
+            }
+            EncoderReaderState::Finished => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = Pixel>> Read for EncoderReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            // Hand out anything left over from the previous op first.
+            if self.spill_pos < self.spill_len {
+                let len = usize::min(self.spill_len - self.spill_pos, buf.len() - written);
+                buf[written..written + len]
+                    .copy_from_slice(&self.spill[self.spill_pos..self.spill_pos + len]);
+                self.spill_pos += len;
+                written += len;
+                continue;
+            }
+
+            if self.state == EncoderReaderState::Finished {
+                break;
+            }
+
+            // Errors are only reported once the bytes before them have been handed out.
+            if let Err(e) = self.refill() {
+                if written > 0 {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::{Channels, Colorspace, Decoder, Pixel};
+    use crate::stream::enc::EncoderReader;
+    use std::io::Read;
+
+    #[test]
+    fn test_encoder_reader() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+
+        let mut reader = EncoderReader::new(
+            img.iter().copied(),
+            header.width,
+            header.height,
+            header.channels,
+            header.colorspace,
+        );
+
+        let mut encoded = Vec::new();
+        std::io::copy(&mut reader, &mut encoded).unwrap();
+
+        // Using image's QOI reader as a known-good reader for our output.
+        let img_qoi_img = image::load_from_memory_with_format(&encoded, image::ImageFormat::Qoi)
+            .unwrap()
+            .into_rgba8()
+            .into_raw();
+        let img = crate::dec::pixels_into_bytes(img);
+
+        // Not doing an assert_eq on the images because it blows up the terminal log.
+        assert!(img == img_qoi_img);
+        assert!(encoded == data);
+    }
+
+    #[test]
+    fn test_encoder_reader_small_reads() {
+        let img: Vec<Pixel> = (0..=255)
+            .map(|i| Pixel::new(i, i / 2, 255 - i, if i % 7 == 0 { 128 } else { 255 }))
+            .chain(std::iter::repeat_n(Pixel::new(1, 2, 3, 4), 100))
+            .collect();
+
+        // Feeding one byte at a time should be no different from one big read.
+        let mut reader = EncoderReader::new(
+            img.iter().copied(),
+            356,
+            1,
+            Channels::RGBA,
+            Colorspace::sRGB,
+        );
+        let mut encoded = Vec::new();
+        let mut buf = [0u8; 1];
+        while reader.read(&mut buf).unwrap() == 1 {
+            encoded.push(buf[0]);
+        }
+
+        let mut all = Vec::new();
+        EncoderReader::new(img.iter().copied(), 356, 1, Channels::RGBA, Colorspace::sRGB)
+            .read_to_end(&mut all)
+            .unwrap();
+        assert_eq!(encoded, all);
+
+        let (_, decoded) = Decoder::new().decode_slice(&encoded).unwrap();
+        assert_eq!(decoded, img);
+    }
+
+    #[test]
+    fn test_encoder_reader_too_few_pixels() {
+        let img = vec![Pixel::new(0, 0, 0, 255); 3];
+        let mut reader =
+            EncoderReader::new(img.into_iter(), 2, 2, Channels::RGBA, Colorspace::sRGB);
+
+        let mut encoded = Vec::new();
+        let err = reader.read_to_end(&mut encoded).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
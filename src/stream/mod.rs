@@ -1,3 +1,9 @@
 pub mod dec;
+pub mod driver;
+pub mod enc;
+pub mod reader;
 
 pub use dec::*;
+pub use driver::*;
+pub use enc::*;
+pub use reader::*;
@@ -0,0 +1,207 @@
+use crate::dec::{Header, Pixel};
+use crate::stream::dec::{StreamDecoder, StreamDecoderOutput};
+use crate::utils::{err_msg, Error};
+use std::io::Read;
+
+/// A reader that decodes a QOI image on the fly, producing the raw RGBA bytes of its pixels.
+///
+/// This allows a QOI image to be handed to anything that consumes a `Read` of raw pixels (another
+/// encoder, a socket, a hasher) without decoding the whole image first. Only as many ops are
+/// decoded as are needed to fill each `read`. The header is parsed when the reader is created.
+///
+/// Internally this drives a [StreamDecoder](crate::stream::StreamDecoder) one byte at a time, so
+/// the inner reader should be buffered.
+pub struct QoiReader<R: Read> {
+    inner: R,
+    header: Header,
+    sdec: StreamDecoder,
+    // The pixel currently being handed out, how many more copies of it are left, and how many of
+    // its bytes have already been handed out.
+    pixel: Pixel,
+    remaining: u8,
+    offset: usize,
+    validate_trailer: bool,
+    finished: bool,
+}
+
+impl<R: Read> QoiReader<R> {
+    /// Reads and parses the header from `inner`. No pixels are decoded until the first `read`.
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut buf = [0u8; 14];
+        inner.read_exact(&mut buf)?;
+
+        let header = Header::from_bytes(&buf).map_err(|e| {
+            e.downcast::<Error>()
+                .unwrap_or_else(|e| Error::HeaderParseError(err_msg!("{}", e)))
+        })?;
+
+        let mut sdec = StreamDecoder::new();
+        for byte in buf {
+            sdec.feed(byte)?;
+        }
+
+        Ok(QoiReader {
+            inner,
+            header,
+            sdec,
+            pixel: Pixel::default(),
+            remaining: 0,
+            offset: 0,
+            validate_trailer: false,
+            finished: false,
+        })
+    }
+
+    /// When enabled, the 8 byte end marker is read and checked after the last pixel, and a missing
+    /// or wrong end marker is reported as an `InvalidData` error. Disabled by default, which
+    /// leaves the end marker unread in the inner reader.
+    pub fn validate_trailer(mut self, validate: bool) -> Self {
+        self.validate_trailer = validate;
+        self
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the inner reader. Unless the trailer was validated, it is positioned at the end
+    /// marker once all pixels have been read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Decodes ops until at least one pixel is ready or the image is finished.
+    fn next_pixels(&mut self) -> std::io::Result<()> {
+        while self.remaining == 0 && !self.finished {
+            if Some(self.sdec.cur_pix()) == self.sdec.num_pix() {
+                self.finish()?;
+                break;
+            }
+
+            if let StreamDecoderOutput::Pixels(mut it) = self.sdec.feed_read(&mut self.inner)? {
+                let count = it.len() as u8;
+                if let Some(pix) = it.next() {
+                    self.pixel = pix;
+                    self.remaining = count;
+                    self.offset = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.finished = true;
+
+        if self.validate_trailer {
+            while !self.sdec.is_finished() {
+                self.sdec.feed_read(&mut self.inner)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for QoiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            if self.remaining == 0 {
+                // Hand out what we have before surfacing an error.
+                if let Err(e) = self.next_pixels() {
+                    if written > 0 {
+                        break;
+                    }
+                    return Err(e);
+                }
+                if self.remaining == 0 {
+                    break;
+                }
+            }
+
+            let bytes = self.pixel.to_bytes();
+            let len = usize::min(4 - self.offset, buf.len() - written);
+            buf[written..written + len].copy_from_slice(&bytes[self.offset..self.offset + len]);
+            written += len;
+            self.offset += len;
+
+            if self.offset == 4 {
+                self.offset = 0;
+                self.remaining -= 1;
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::Decoder;
+    use crate::stream::reader::QoiReader;
+    use crate::testgen::{self, Pattern};
+    use std::io::{BufReader, Read};
+
+    /// Copies `reader` into a Vec using reads of at most `size` bytes.
+    fn read_in_chunks(mut reader: impl Read, size: usize) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; size];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(out),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_qoi_reader() {
+        let data = std::fs::read("tests/dice.qoi").unwrap();
+        let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+        let expected = crate::dec::pixels_into_bytes(img);
+
+        let mut reader = QoiReader::new(BufReader::new(&data[..])).unwrap();
+        assert_eq!(reader.header(), &header);
+
+        let mut out = Vec::new();
+        std::io::copy(&mut reader, &mut out).unwrap();
+        assert!(out == expected);
+    }
+
+    #[test]
+    fn test_qoi_reader_small_reads() {
+        for pattern in Pattern::ALL {
+            let img = testgen::generate(pattern, 33, 17, 3);
+            let data = testgen::encode(&img, 33, 17);
+            let expected = crate::dec::pixels_into_bytes(img);
+
+            // Reads that split pixels (and runs) at every possible boundary.
+            for size in [1, 3, 5, 4096] {
+                let reader = QoiReader::new(&data[..]).unwrap().validate_trailer(true);
+                assert_eq!(read_in_chunks(reader, size).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_qoi_reader_trailer() {
+        let img = testgen::generate(Pattern::Mixed, 8, 8, 0);
+        let mut data = testgen::encode(&img, 8, 8);
+
+        // Without validation the end marker is left alone.
+        let mut reader = QoiReader::new(&data[..]).unwrap();
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(reader.into_inner().len(), 8);
+
+        let len = data.len();
+        data[len - 1] = 0;
+        assert!(QoiReader::new(&data[..]).unwrap().read_to_end(&mut Vec::new()).is_ok());
+        let err = QoiReader::new(&data[..])
+            .unwrap()
+            .validate_trailer(true)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,171 @@
+//! Generators for synthetic images, used by the tests and benchmarks.
+//!
+//! Each pattern leans on a different set of ops when encoded, which makes them useful for
+//! exercising (and timing) specific parts of the decoders. All generators are deterministic, so
+//! the same parameters always produce the same image.
+
+use crate::dec::{Channels, Colorspace, Pixel};
+use crate::stream::EncoderReader;
+use std::io::Read;
+
+/// The kinds of synthetic images that can be generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// A single color. Encodes almost entirely as `QOI_OP_RUN`.
+    Solid,
+    /// Smooth ramps in each channel. Encodes mostly as `QOI_OP_DIFF` and `QOI_OP_LUMA`.
+    Gradient,
+    /// Random opaque colors. Encodes mostly as `QOI_OP_RGB`.
+    Noise,
+    /// A random mix of runs, small differences, repeated colors, and alpha changes, so that every
+    /// op shows up.
+    Mixed,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 4] = [
+        Pattern::Solid,
+        Pattern::Gradient,
+        Pattern::Noise,
+        Pattern::Mixed,
+    ];
+}
+
+/// A small xorshift generator. Good enough for test images and keeps us free of dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at 0, so make sure we never start there.
+        XorShift(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn next_u8(&mut self) -> u8 {
+        (self.next() >> 32) as u8
+    }
+}
+
+/// Generates a `width * height` image of the given pattern. The seed is ignored by patterns that
+/// aren't random.
+pub fn generate(pattern: Pattern, width: u32, height: u32, seed: u64) -> Vec<Pixel> {
+    match pattern {
+        Pattern::Solid => solid(width, height, Pixel::new(200, 40, 90, 255)),
+        Pattern::Gradient => gradient(width, height),
+        Pattern::Noise => noise(width, height, seed),
+        Pattern::Mixed => mixed(width, height, seed),
+    }
+}
+
+pub fn solid(width: u32, height: u32, pixel: Pixel) -> Vec<Pixel> {
+    vec![pixel; width as usize * height as usize]
+}
+
+pub fn gradient(width: u32, height: u32) -> Vec<Pixel> {
+    let mut img = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            img.push(Pixel::new(x as u8, y as u8, (x.wrapping_add(y) / 2) as u8, 255));
+        }
+    }
+    img
+}
+
+pub fn noise(width: u32, height: u32, seed: u64) -> Vec<Pixel> {
+    let mut rng = XorShift::new(seed);
+    (0..width as usize * height as usize)
+        .map(|_| Pixel::new(rng.next_u8(), rng.next_u8(), rng.next_u8(), 255))
+        .collect()
+}
+
+pub fn mixed(width: u32, height: u32, seed: u64) -> Vec<Pixel> {
+    let mut rng = XorShift::new(seed);
+    let num_pixels = width as usize * height as usize;
+
+    let mut img: Vec<Pixel> = Vec::with_capacity(num_pixels);
+    let mut prev = Pixel::new(0, 0, 0, 255);
+
+    while img.len() < num_pixels {
+        let pix = match rng.next() % 6 {
+            // Runs of various lengths, including ones longer than a single QOI_OP_RUN can hold.
+            0 => {
+                let len = (rng.next() % 80) as usize + 1;
+                let len = usize::min(len, num_pixels - img.len());
+                img.extend(std::iter::repeat_n(prev, len));
+                continue;
+            }
+            // Small differences (QOI_OP_DIFF).
+            1 => Pixel::new(
+                prev.r.wrapping_add(rng.next_u8() % 4).wrapping_sub(2),
+                prev.g.wrapping_add(rng.next_u8() % 4).wrapping_sub(2),
+                prev.b.wrapping_add(rng.next_u8() % 4).wrapping_sub(2),
+                prev.a,
+            ),
+            // Luma differences (QOI_OP_LUMA).
+            2 => {
+                let dg = (rng.next_u8() % 64).wrapping_sub(32);
+                Pixel::new(
+                    prev.r.wrapping_add(dg).wrapping_add(rng.next_u8() % 16).wrapping_sub(8),
+                    prev.g.wrapping_add(dg),
+                    prev.b.wrapping_add(dg).wrapping_add(rng.next_u8() % 16).wrapping_sub(8),
+                    prev.a,
+                )
+            }
+            // A color seen earlier (QOI_OP_INDEX, when it hasn't been evicted).
+            3 if !img.is_empty() => {
+                let back = usize::min(img.len(), 64);
+                img[img.len() - 1 - (rng.next() as usize % back)]
+            }
+            // A new alpha (QOI_OP_RGBA).
+            4 => Pixel::new(rng.next_u8(), rng.next_u8(), rng.next_u8(), rng.next_u8()),
+            // Anything else (QOI_OP_RGB).
+            _ => Pixel::new(rng.next_u8(), rng.next_u8(), rng.next_u8(), prev.a),
+        };
+
+        img.push(pix);
+        prev = pix;
+    }
+
+    img
+}
+
+/// Encodes an image as an RGBA, sRGB QOI image.
+///
+/// Panics if `pixels` does not hold exactly `width * height` pixels.
+pub fn encode(pixels: &[Pixel], width: u32, height: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    EncoderReader::new(
+        pixels.iter().copied(),
+        width,
+        height,
+        Channels::RGBA,
+        Colorspace::sRGB,
+    )
+    .read_to_end(&mut data)
+    .expect("Encoding from memory can't fail when the pixel count matches");
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::Decoder;
+    use crate::testgen::{encode, generate, Pattern};
+
+    #[test]
+    fn test_generate() {
+        for pattern in Pattern::ALL {
+            let img = generate(pattern, 37, 11, 1);
+            assert_eq!(img.len(), 37 * 11);
+            assert_eq!(img, generate(pattern, 37, 11, 1));
+
+            let (_, decoded) = Decoder::new().decode_slice(&encode(&img, 37, 11)).unwrap();
+            assert_eq!(decoded, img);
+        }
+    }
+}
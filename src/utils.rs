@@ -1,18 +1,135 @@
-use clap::{Parser, arg};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, thiserror::Error)]
+/// The message attached to some errors. Without the `fmt` feature no messages are built, so this
+/// is an empty struct and errors only carry their variant and numeric fields.
+#[cfg(feature = "fmt")]
+pub type ErrorMessage = String;
+#[cfg(not(feature = "fmt"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorMessage;
+
+/// Builds an [ErrorMessage] with `format!` syntax. Without the `fmt` feature the arguments are
+/// dropped and nothing is formatted.
+#[cfg(feature = "fmt")]
+macro_rules! err_msg {
+    ($($arg:tt)*) => {
+        format!($($arg)*)
+    };
+}
+#[cfg(not(feature = "fmt"))]
+macro_rules! err_msg {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        $(let _ = &$arg;)*
+        $crate::utils::ErrorMessage
+    }};
+}
+pub(crate) use err_msg;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "fmt", derive(thiserror::Error))]
 pub enum Error {
-    #[error("Failed to parse header: {0}")]
-    HeaderParseError(String),
-    #[error("Failed to decode: {0}")]
-    DecodingError(String),
+    #[cfg_attr(feature = "fmt", error("Failed to parse header: {0}"))]
+    HeaderParseError(ErrorMessage),
+    #[cfg_attr(feature = "fmt", error("Failed to decode: {0}"))]
+    DecodingError(ErrorMessage),
+    #[cfg_attr(feature = "fmt", error("Invalid image dimensions: {width}x{height}"))]
+    InvalidDimensions { width: u32, height: u32 },
+    /// The channels byte of the header, which must be 3 or 4.
+    #[cfg_attr(feature = "fmt", error("Invalid number of channels in the header: {0}"))]
+    InvalidChannels(u8),
+    /// The colorspace byte of the header, which must be 0 or 1.
+    #[cfg_attr(feature = "fmt", error("Invalid colorspace in the header: {0}"))]
+    InvalidColorspace(u8),
+    #[cfg_attr(
+        feature = "fmt",
+        error(
+            "Found data after the end marker{}",
+            .bytes_remaining.map(|n| format!(" ({} bytes)", n)).unwrap_or_default()
+        )
+    )]
+    TrailingData { bytes_remaining: Option<u64> },
+    #[cfg_attr(
+        feature = "fmt",
+        error("Fed more bytes after the image finished. Call reset() before the next image")
+    )]
+    FeedAfterFinished,
+    #[cfg_attr(feature = "fmt", error(transparent))]
+    Io(#[cfg_attr(feature = "fmt", from)] std::io::Error),
+    /// An IO error on a file opened by the crate, along with its path.
+    #[cfg_attr(feature = "fmt", error("{}: {source}", path.display()))]
+    FileIo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Without the `fmt` feature, errors display as a fixed description of their variant.
+#[cfg(not(feature = "fmt"))]
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::HeaderParseError(_) => "Failed to parse header",
+            Error::DecodingError(_) => "Failed to decode",
+            Error::InvalidDimensions { .. } => "Invalid image dimensions",
+            Error::InvalidChannels(_) => "Invalid number of channels in the header",
+            Error::InvalidColorspace(_) => "Invalid colorspace in the header",
+            Error::TrailingData { .. } => "Found data after the end marker",
+            Error::FeedAfterFinished => "Fed more bytes after the image finished",
+            Error::Io(_) | Error::FileIo { .. } => "IO error",
+        })
+    }
+}
+
+#[cfg(not(feature = "fmt"))]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::FileIo { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "fmt"))]
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+/// Errors are passed through `Read` impls (like [QoiReader](crate::stream::QoiReader)) as
+/// `InvalidData`, unless they were IO errors to begin with.
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            Error::FileIo { ref source, .. } => std::io::Error::new(source.kind(), value),
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
 }
 
-#[derive(Debug, Parser)]
-pub struct Args {
-    #[arg(short, long)]
-    pub file: PathBuf,
-    #[arg(short, long)]
-    pub stream: bool
+/// A reader that counts the bytes read through it.
+pub(crate) struct CountingReader<R: std::io::Read> {
+    inner: R,
+    count: u64,
 }
+
+impl<R: std::io::Read> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        CountingReader { inner, count: 0 }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
@@ -0,0 +1,118 @@
+//! Helpers for showing decoded images on screen: converting pixels to the formats windowing
+//! libraries expect, and making transparency visible.
+//!
+//! These are used by the `--window` option of the `qoi-parser` binary (behind the `viewer`
+//! feature), but don't depend on any windowing library themselves.
+
+use crate::dec::Pixel;
+
+/// The light and dark squares of the background drawn by [checkerboard].
+const CHECKER_LIGHT: u8 = 0xcc;
+const CHECKER_DARK: u8 = 0x88;
+
+/// Converts pixels to `0RGB` words: the upper 8 bits are zero, followed by red, green, and blue.
+/// This is the buffer format used by `minifb`. Alpha is dropped, see [checkerboard] to show it.
+pub fn to_0rgb(pixels: &[Pixel]) -> Vec<u32> {
+    pixels
+        .iter()
+        .map(|p| (p.r as u32) << 16 | (p.g as u32) << 8 | p.b as u32)
+        .collect()
+}
+
+/// Converts pixels to `BGRA` bytes, the byte order most window surfaces use on little endian
+/// machines.
+pub fn to_bgra(pixels: &[Pixel]) -> Vec<u8> {
+    pixels.iter().flat_map(|p| [p.b, p.g, p.r, p.a]).collect()
+}
+
+/// Composites `pixels` over a grey checkerboard with squares of `cell` pixels, returning opaque
+/// pixels. This is how image editors show transparency.
+///
+/// `width` is the width of the image, which the pixels are in row-major order for.
+pub fn checkerboard(pixels: &[Pixel], width: u32, cell: u32) -> Vec<Pixel> {
+    let width = usize::max(width as usize, 1);
+    let cell = usize::max(cell as usize, 1);
+
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            let (x, y) = (i % width, i / width);
+            let bg = match (x / cell + y / cell) % 2 {
+                0 => CHECKER_LIGHT,
+                _ => CHECKER_DARK,
+            };
+            Pixel::new(blend(p.r, bg, p.a), blend(p.g, bg, p.a), blend(p.b, bg, p.a), 255)
+        })
+        .collect()
+}
+
+/// Blends `fg` over `bg` with an alpha of `a`, rounding to the nearest value.
+fn blend(fg: u8, bg: u8, a: u8) -> u8 {
+    let (fg, bg, a) = (fg as u32, bg as u32, a as u32);
+    ((fg * a + bg * (255 - a) + 127) / 255) as u8
+}
+
+/// The largest size with the same aspect ratio as `width`x`height` that fits within
+/// `max_width`x`max_height`. Images that already fit are left at their size, so this only ever
+/// scales down. Neither dimension is scaled below 1.
+pub fn fit_within(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+
+    // Compare width / max_width against height / max_height without floating point.
+    let (w, h) = (width as u64, height as u64);
+    let (mw, mh) = (max_width as u64, max_height as u64);
+    let (w, h) = if w * mh > h * mw {
+        (mw, h * mw / w)
+    } else {
+        (w * mh / h, mh)
+    };
+
+    (u64::max(w, 1) as u32, u64::max(h, 1) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dec::Pixel;
+    use crate::view::{checkerboard, fit_within, to_0rgb, to_bgra};
+
+    #[test]
+    fn test_pixel_formats() {
+        let pixels = [Pixel::new(0x12, 0x34, 0x56, 0x78), Pixel::new(255, 0, 1, 255)];
+
+        assert_eq!(to_0rgb(&pixels), vec![0x00123456, 0x00ff0001]);
+        assert_eq!(to_bgra(&pixels), vec![0x56, 0x34, 0x12, 0x78, 1, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_checkerboard() {
+        let clear = Pixel::new(10, 20, 30, 0);
+        let opaque = Pixel::new(10, 20, 30, 255);
+
+        // Fully transparent pixels show the checkerboard, in 2x2 squares.
+        let out = checkerboard(&[clear; 16], 4, 2);
+        let light = Pixel::new(0xcc, 0xcc, 0xcc, 255);
+        let dark = Pixel::new(0x88, 0x88, 0x88, 255);
+        assert_eq!(out[0], light);
+        assert_eq!(out[5], light);
+        assert_eq!(out[2], dark);
+        assert_eq!(out[8], dark);
+        assert_eq!(out[10], light);
+
+        // Opaque pixels are unchanged, and half transparent ones land in between.
+        assert_eq!(checkerboard(&[opaque], 1, 8), vec![opaque]);
+        let half = checkerboard(&[Pixel::new(0, 255, 0xcc, 128)], 1, 8)[0];
+        assert_eq!(half, Pixel::new(102, 230, 0xcc, 255));
+    }
+
+    #[test]
+    fn test_fit_within() {
+        assert_eq!(fit_within(800, 600, 1600, 900), (800, 600));
+        assert_eq!(fit_within(3200, 1800, 1600, 900), (1600, 900));
+        assert_eq!(fit_within(4000, 1000, 1600, 900), (1600, 400));
+        assert_eq!(fit_within(1000, 4000, 1600, 900), (225, 900));
+        assert_eq!(fit_within(100_000, 1, 1600, 900), (1600, 1));
+    }
+}
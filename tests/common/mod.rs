@@ -0,0 +1,186 @@
+//! Helpers shared by the integration tests. Not every test uses every helper.
+#![allow(dead_code)]
+
+use qoiparser::stream::ChunkedDriver;
+use qoiparser::{Channels, Colorspace, Decoder, Encoder, Error, Header, Pixel};
+use std::path::Path;
+
+/// The first index at which `a` and `b` hold different pixels, along with the pixel from each.
+/// Only the indices both slices have are compared, so check the lengths separately.
+///
+/// For reporting where two images differ without an `assert_eq!` that prints both of them.
+pub fn first_mismatch(a: &[Pixel], b: &[Pixel]) -> Option<(usize, Pixel, Pixel)> {
+    a.iter()
+        .zip(b)
+        .position(|(pa, pb)| pa != pb)
+        .map(|i| (i, a[i], b[i]))
+}
+
+/// Encodes `pixels` with [Encoder], decodes the result with [Decoder], and asserts that the header
+/// and every pixel come back exactly as they went in.
+pub fn assert_roundtrip(
+    pixels: &[Pixel],
+    width: u32,
+    height: u32,
+    channels: Channels,
+    colorspace: Colorspace,
+) {
+    let header = Header::new(width, height, channels, colorspace);
+    let encoded = Encoder::new()
+        .encode_to_vec(&header, pixels)
+        .expect("encoding failed");
+
+    let (decoded_header, decoded) = Decoder::new()
+        .decode_slice(&encoded)
+        .expect("decoding the encoder's output failed");
+    assert_eq!(decoded_header, header);
+    assert_eq!(decoded.len(), pixels.len());
+
+    if let Some((i, expected, got)) = first_mismatch(pixels, &decoded) {
+        panic!(
+            "pixel {} (x={}, y={}) changed: expected {:?}, got {:?}",
+            i,
+            i as u32 % width,
+            i as u32 / width,
+            expected,
+            got
+        );
+    }
+}
+
+/// Decodes `data` with a [ChunkedDriver], end marker included. The chunks are small and of an odd
+/// size, so that they split the header, ops, and end marker.
+pub fn decode_stream(data: &[u8]) -> Result<Vec<Pixel>, Error> {
+    let mut img = Vec::new();
+    ChunkedDriver::new(data).chunk_size(7).run(&mut img)?;
+    Ok(img)
+}
+
+/// The kind of an error from the chunked decoder, in the form used by the `.expect` files in
+/// `tests/corpus/invalid`: the [Error] variant name, or `Io(<ErrorKind>)` for IO errors.
+pub fn error_kind(e: &anyhow::Error) -> String {
+    if let Some(e) = e.downcast_ref::<Error>() {
+        return match e {
+            Error::HeaderParseError(_) => "HeaderParseError".to_string(),
+            Error::DecodingError(_) => "DecodingError".to_string(),
+            Error::InvalidDimensions { .. } => "InvalidDimensions".to_string(),
+            Error::InvalidChannels(_) => "InvalidChannels".to_string(),
+            Error::InvalidColorspace(_) => "InvalidColorspace".to_string(),
+            Error::TrailingData { .. } => "TrailingData".to_string(),
+            Error::FeedAfterFinished => "FeedAfterFinished".to_string(),
+            Error::Io(e) | Error::FileIo { source: e, .. } => format!("Io({:?})", e.kind()),
+        };
+    }
+    match e.downcast_ref::<std::io::Error>() {
+        Some(e) => format!("Io({:?})", e.kind()),
+        None => format!("Unknown({})", e),
+    }
+}
+
+/// Checks an image that both decoders must accept: the chunked decoder (in strict mode) and the
+/// streaming decoder must both decode it, to the same pixels.
+pub fn check_valid(path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read: {}", e))?;
+
+    let (header, img) = Decoder::new()
+        .strict(true)
+        .decode_slice(&data)
+        .map_err(|e| format!("chunked decoder failed: {}", e))?;
+    if img.len() as u64 != header.width as u64 * header.height as u64 {
+        return Err(format!(
+            "chunked decoder returned {} pixels for a {}x{} image",
+            img.len(),
+            header.width,
+            header.height
+        ));
+    }
+
+    match decode_stream(&data) {
+        Ok(stream_img) => disagreement(&img, &stream_img).map_or(Ok(()), Err),
+        Err(e) => Err(format!("stream decoder failed: {}", e)),
+    }
+}
+
+/// Checks an image that is off-spec but decodable. Both decoders must accept it and agree, like
+/// [check_valid], except that the chunked decoder is not strict. In strict mode, it must fail with
+/// the error kind written in the `.expect` file next to the image.
+pub fn check_lenient(path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read: {}", e))?;
+
+    let (_, img) = Decoder::new()
+        .decode_slice(&data)
+        .map_err(|e| format!("chunked decoder failed: {}", e))?;
+
+    match decode_stream(&data) {
+        Ok(stream_img) => {
+            if let Some(e) = disagreement(&img, &stream_img) {
+                return Err(e);
+            }
+        }
+        Err(e) => return Err(format!("stream decoder failed: {}", e)),
+    }
+
+    let expected = read_expect(path)?;
+    match Decoder::new().strict(true).decode_slice(&data) {
+        Ok(_) => Err("strict chunked decoder accepted the image".to_string()),
+        Err(e) if error_kind(&e) != expected => Err(format!(
+            "expected {}, strict chunked decoder failed with {}: {}",
+            expected,
+            error_kind(&e),
+            e
+        )),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Describes how the chunked decoder's pixels differ from the streaming decoder's, if they do.
+fn disagreement(chunked: &[Pixel], stream: &[Pixel]) -> Option<String> {
+    if chunked.len() != stream.len() {
+        return Some(format!(
+            "stream and chunked decoders disagree: {} pixels from the chunked decoder, {} from \
+             the stream decoder",
+            chunked.len(),
+            stream.len()
+        ));
+    }
+    first_mismatch(chunked, stream).map(|(i, c, s)| {
+        format!(
+            "stream and chunked decoders disagree at pixel {}: chunked {:?}, stream {:?}",
+            i, c, s
+        )
+    })
+}
+
+/// Reads the error kind from the `.expect` file next to `path`.
+fn read_expect(path: &Path) -> Result<String, String> {
+    let expect = path.with_extension("expect");
+    let expected = std::fs::read_to_string(&expect)
+        .map_err(|e| format!("failed to read {}: {}", expect.display(), e))?;
+    Ok(expected.trim().to_string())
+}
+
+/// Checks an image that must be rejected. The chunked decoder must fail with the error kind
+/// written in the `.expect` file next to the image, and the streaming decoder must fail too.
+pub fn check_invalid(path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read: {}", e))?;
+
+    let expected = read_expect(path)?;
+
+    match Decoder::new().decode_slice(&data) {
+        Ok(_) => return Err("chunked decoder accepted the image".to_string()),
+        Err(e) if error_kind(&e) != expected => {
+            return Err(format!(
+                "expected {}, chunked decoder failed with {}: {}",
+                expected,
+                error_kind(&e),
+                e
+            ))
+        }
+        Err(_) => {}
+    }
+
+    match decode_stream(&data) {
+        Ok(_) => Err("stream decoder accepted the image".to_string()),
+        Err(_) => Ok(()),
+    }
+}
@@ -0,0 +1,99 @@
+//! Checks that `Decoder` and `StreamDecoder` (driven by `ChunkedDriver`) produce exactly the same
+//! pixels for the same input, as do `Decoder::decode_bufread` and `StreamDecoder::feed_all`.
+//!
+//! Both decoders are compared against the `image` crate in `reference.rs`, but that doesn't catch
+//! a bug they share. Here they are compared against each other, for every QOI file under `tests/`
+//! and for generated images that lean on `QOI_OP_INDEX` and `QOI_OP_LUMA`.
+
+mod common;
+
+use common::{decode_stream, first_mismatch};
+use qoiparser::stream::StreamDecoder;
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{Decoder, Pixel};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Every `.qoi` file under `dir`, in a stable order.
+fn qoi_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(qoi_files(&path));
+        } else if path.extension() == Some("qoi".as_ref()) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Fails with the first pixel that differs, rather than printing both images.
+fn assert_same(name: &str, decoder: &[Pixel], stream: &[Pixel]) {
+    assert_eq!(
+        decoder.len(),
+        stream.len(),
+        "{}: the decoders returned different numbers of pixels",
+        name
+    );
+    if let Some((i, d, s)) = first_mismatch(decoder, stream) {
+        panic!(
+            "{}: pixel {} differs: Decoder {:?}, StreamDecoder {:?}",
+            name, i, d, s
+        );
+    }
+}
+
+/// Decodes `data` with both decoders and checks that they agree. Returns false if the chunked
+/// decoder rejects the data, in which case there is nothing to compare.
+fn check(name: &str, data: &[u8]) -> bool {
+    let Ok((_, img)) = Decoder::new().decode_slice(data) else {
+        return false;
+    };
+
+    let buffered = Decoder::new()
+        .decode_bufread(&mut BufReader::with_capacity(7, data))
+        .unwrap_or_else(|e| panic!("{}: decode_bufread failed: {:?}", name, e));
+    assert_same(name, &img, &buffered.1);
+
+    match decode_stream(data) {
+        Ok(stream_img) => assert_same(name, &img, &stream_img),
+        Err(e) => panic!("{}: ChunkedDriver failed: {:?}", name, e),
+    }
+
+    let mut sdec = StreamDecoder::new();
+    let mut fed = Vec::new();
+    sdec.feed_all(data, &mut fed)
+        .unwrap_or_else(|e| panic!("{}: feed_all failed: {:?}", name, e));
+    assert!(sdec.is_finished(), "{}: feed_all never finished", name);
+    assert_same(name, &img, &fed);
+    true
+}
+
+#[test]
+fn decoders_agree_on_files() {
+    let files = qoi_files(Path::new("tests"));
+    assert!(!files.is_empty(), "no files found");
+
+    let checked = files
+        .iter()
+        .filter(|path| check(&path.display().to_string(), &std::fs::read(path).unwrap()))
+        .count();
+    // tests/dice.qoi and the valid corpus, at least.
+    assert!(checked >= 2, "only {} files decoded", checked);
+}
+
+#[test]
+fn decoders_agree_on_generated() {
+    for pattern in [Pattern::Mixed, Pattern::Gradient] {
+        for (width, height, seed) in [(64, 64, 1), (257, 3, 2), (1, 500, 3)] {
+            let img = testgen::generate(pattern, width, height, seed);
+            let data = testgen::encode(&img, width, height);
+
+            let name = format!("{:?} {}x{}", pattern, width, height);
+            assert!(check(&name, &data), "{}: Decoder failed", name);
+            assert_same(&name, &img, &decode_stream(&data).unwrap());
+        }
+    }
+}
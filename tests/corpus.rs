@@ -0,0 +1,53 @@
+//! Runs every image in `tests/corpus` through both decoders. See the README for how to add images.
+
+mod common;
+
+use common::{check_invalid, check_lenient, check_valid};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// The `.qoi` files in a folder of the corpus, in a stable order.
+fn corpus_files(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new("tests/corpus").join(dir);
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "qoi"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Runs `check` on every file, collecting failures (panics included) so that a single run reports
+/// every broken file.
+fn run_all(files: &[PathBuf], check: fn(&Path) -> Result<(), String>) {
+    assert!(!files.is_empty(), "no files found");
+
+    let failures: Vec<String> = files
+        .iter()
+        .filter_map(|path| {
+            match panic::catch_unwind(AssertUnwindSafe(|| check(path))) {
+                Ok(Ok(())) => None,
+                Ok(Err(msg)) => Some(format!("{}: {}", path.display(), msg)),
+                Err(_) => Some(format!("{}: panicked", path.display())),
+            }
+        })
+        .collect();
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn corpus_valid() {
+    run_all(&corpus_files("valid"), check_valid);
+}
+
+#[test]
+fn corpus_invalid() {
+    run_all(&corpus_files("invalid"), check_invalid);
+}
+
+#[test]
+fn corpus_lenient() {
+    run_all(&corpus_files("lenient"), check_lenient);
+}
@@ -0,0 +1,151 @@
+/*
+ * Feeds two copies of a QOI file back to back to the streaming C interface, in 1500-byte chunks
+ * after a short first one that splits the header, and rebuilds both images. Each is compared
+ * against raw RGBA pixels. Build and run from the repository root with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ *     cc -Iinclude tests/ffi/stream.c target/release/libqoiparser.a -lpthread -ldl -lm -o stream
+ *     ./stream tests/dice.qoi
+ *
+ * Without an RGBA file to compare against, the test checks that every pixel was written once.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "qoip.h"
+
+#define CHUNK 1500
+#define FRAMES 2
+
+typedef struct Image {
+    QoipStream *stream;
+    QoipPixel *pixels;
+    uint64_t len;
+    uint64_t written;
+} Image;
+
+static void on_pixels(void *user, const QoipPixel *run_pixel, uint32_t run_len,
+                      uint64_t start_index) {
+    Image *img = user;
+    /* The header is always complete before the first pixel, so allocate the image here. */
+    if (!img->pixels) {
+        QoipHeader header;
+        if (qoip_stream_header(img->stream, &header) != QOIP_OK) {
+            fprintf(stderr, "pixels before the header\n");
+            exit(1);
+        }
+        img->len = (uint64_t)header.width * header.height;
+        img->pixels = calloc(img->len, sizeof(QoipPixel));
+        printf("%ux%u, %u channels\n", header.width, header.height, header.channels);
+    }
+    if (start_index + run_len > img->len) {
+        fprintf(stderr, "run past the end of the image\n");
+        exit(1);
+    }
+    for (uint32_t i = 0; i < run_len; i++)
+        img->pixels[start_index + i] = *run_pixel;
+    img->written += run_len;
+}
+
+/* Reads the whole of `path`, setting `len`. Returns NULL on failure. */
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *f = fopen(path, "rb");
+    if (!f) {
+        perror(path);
+        return NULL;
+    }
+    fseek(f, 0, SEEK_END);
+    *len = (size_t)ftell(f);
+    fseek(f, 0, SEEK_SET);
+    uint8_t *data = malloc(*len ? *len : 1);
+    if (fread(data, 1, *len, f) != *len) {
+        perror(path);
+        free(data);
+        data = NULL;
+    }
+    fclose(f);
+    return data;
+}
+
+/* Checks a finished image, then clears it for the next one. Returns 0 if it is correct. */
+static int check_image(Image *img, const uint8_t *expected, size_t expected_len) {
+    int res = 0;
+    if (img->written != img->len) {
+        fprintf(stderr, "wrote %llu of %llu pixels\n", (unsigned long long)img->written,
+                (unsigned long long)img->len);
+        res = 1;
+    } else if (expected && (expected_len != img->len * sizeof(QoipPixel) ||
+                            memcmp(expected, img->pixels, expected_len) != 0)) {
+        fprintf(stderr, "pixels differ from the expected RGBA\n");
+        res = 1;
+    }
+    free(img->pixels);
+    img->pixels = NULL;
+    img->len = 0;
+    img->written = 0;
+    return res;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 2) {
+        fprintf(stderr, "usage: %s <file.qoi> [expected.rgba]\n", argv[0]);
+        return 2;
+    }
+
+    size_t file_len, expected_len = 0;
+    uint8_t *file = read_file(argv[1], &file_len);
+    if (!file)
+        return 2;
+    uint8_t *expected = NULL;
+    if (argc > 2 && !(expected = read_file(argv[2], &expected_len)))
+        return 2;
+
+    size_t total = file_len * FRAMES;
+    uint8_t *data = malloc(total);
+    for (int i = 0; i < FRAMES; i++)
+        memcpy(data + i * file_len, file, file_len);
+
+    QoipStream *stream = qoip_stream_new();
+    Image img = {stream, NULL, 0, 0};
+    int frames = 0;
+    /* A short first chunk, so the header arrives in two pieces. */
+    size_t chunk = 9;
+
+    for (size_t pos = 0; pos < total;) {
+        size_t n = total - pos < chunk ? total - pos : chunk;
+        const uint8_t *p = data + pos;
+        pos += n;
+        chunk = CHUNK;
+
+        /* A chunk can hold the end of one image and the start of the next. */
+        while (n > 0) {
+            int res = qoip_stream_feed(stream, p, n, on_pixels, &img);
+            if (res == QOIP_OK)
+                break;
+            if (res != QOIP_DONE) {
+                fprintf(stderr, "decoding failed: %d\n", res);
+                return 1;
+            }
+
+            size_t used = qoip_stream_consumed(stream);
+            p += used;
+            n -= used;
+            if (qoip_stream_finish(stream) != QOIP_OK || check_image(&img, expected, expected_len))
+                return 1;
+            frames++;
+        }
+    }
+
+    if (frames != FRAMES || qoip_stream_finish(stream) != QOIP_ERR_INCOMPLETE) {
+        fprintf(stderr, "decoded %d of %d images\n", frames, FRAMES);
+        return 1;
+    }
+    qoip_stream_free(stream);
+
+    printf("ok, %d images\n", frames);
+    free(data);
+    free(file);
+    free(expected);
+    return 0;
+}
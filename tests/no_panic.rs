@@ -0,0 +1,165 @@
+//! Throws random and mutated images at both decoders and checks that none of them panic.
+//!
+//! Integration tests build with `debug_assertions`, so arithmetic overflow panics here as well.
+//! Every input is generated from a fixed seed, and a failure prints the input that caused it.
+
+mod common;
+
+use common::decode_stream;
+use qoiparser::stream::StreamDecoder;
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{Channels, Colorspace, DecodeLimits, Decoder, Header};
+use std::io::BufReader;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Small enough that a mutated header can't make the chunked decoder allocate much.
+const MAX_PIXELS: u64 = 1 << 16;
+
+/// The same xorshift generator as `testgen`, which keeps its own private.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`. `n` must not be 0.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn byte(&mut self) -> u8 {
+        (self.next() >> 32) as u8
+    }
+}
+
+/// Runs `data` through every decoder and decode function that takes arbitrary input.
+fn decode_all(data: &[u8]) {
+    let limits = DecodeLimits {
+        max_pixels: MAX_PIXELS,
+    };
+
+    let _ = Decoder::with_limits(limits).decode_slice(data);
+    let _ = Decoder::with_limits(limits).strict(true).decode_slice(data);
+    let _ = Decoder::with_limits(limits).decode_counting(&mut &data[..]);
+    let _ = Decoder::with_limits(limits).decode_debug(&mut &data[..], &mut std::io::sink());
+    // A tiny buffer, so that ops straddle the end of it.
+    let _ = Decoder::with_limits(limits).decode_bufread(&mut BufReader::with_capacity(3, data));
+    let _ = qoiparser::decode_frames(data, |_, _| std::ops::ControlFlow::Continue(()));
+
+    // The stream decoder doesn't allocate, and the driver's Vec only reserves what it can get, so
+    // they can run without limits.
+    let _ = decode_stream(data);
+    let _ = StreamDecoder::new().feed_slice(data, |_| {});
+    let _ = StreamDecoder::new().feed_all(data, &mut Vec::new());
+}
+
+/// Panics with the hex of `data` if decoding it panics.
+fn check(data: &[u8]) {
+    if panic::catch_unwind(AssertUnwindSafe(|| decode_all(data))).is_err() {
+        let hex: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+        panic!(
+            "decoding panicked on {} bytes: {}",
+            data.len(),
+            hex.join(" ")
+        );
+    }
+}
+
+/// Applies one random change to `data`: flipping a bit, replacing, inserting, or removing a byte,
+/// truncating, or rewriting the dimensions in the header.
+fn mutate(data: &mut Vec<u8>, rng: &mut XorShift) {
+    if data.is_empty() {
+        data.push(rng.byte());
+        return;
+    }
+
+    let i = rng.below(data.len());
+    match rng.below(6) {
+        0 => data[i] ^= 1 << rng.below(8),
+        1 => data[i] = rng.byte(),
+        2 => data.insert(i, rng.byte()),
+        3 => {
+            data.remove(i);
+        }
+        4 => data.truncate(i),
+        _ if data.len() >= 12 => {
+            let (w, h) = (rng.below(300) as u32, rng.below(300) as u32);
+            data[4..8].copy_from_slice(&w.to_be_bytes());
+            data[8..12].copy_from_slice(&h.to_be_bytes());
+        }
+        _ => data[i] = rng.byte(),
+    }
+}
+
+/// Small valid images that use every op between them.
+fn seeds() -> Vec<Vec<u8>> {
+    let mut seeds = Vec::new();
+    for (width, height) in [(16, 16), (1, 40), (37, 3)] {
+        for pattern in Pattern::ALL {
+            let img = testgen::generate(pattern, width, height, 3);
+            seeds.push(testgen::encode(&img, width, height));
+        }
+    }
+    seeds
+}
+
+#[test]
+fn no_panic_mutated() {
+    let mut rng = XorShift(0x5eed_9a1c);
+
+    for seed in seeds() {
+        check(&seed);
+        for _ in 0..200 {
+            let mut data = seed.clone();
+            for _ in 0..1 + rng.below(4) {
+                mutate(&mut data, &mut rng);
+            }
+            check(&data);
+        }
+    }
+}
+
+#[test]
+fn no_panic_random() {
+    let mut rng = XorShift(0xdec0_de01);
+    let header = Header::new(17, 9, Channels::RGBA, Colorspace::sRGB);
+
+    for i in 0..2000 {
+        let len = rng.below(400);
+        let mut data: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+        // Most random bytes fail on the magic, so give half of them a valid header.
+        if i % 2 == 0 {
+            data.splice(0..0, header.to_bytes());
+        }
+        check(&data);
+    }
+}
+
+#[test]
+fn no_panic_corpus() {
+    let mut rng = XorShift(0xc04f_4905);
+
+    for dir in ["valid", "invalid", "lenient"] {
+        for entry in std::fs::read_dir(format!("tests/corpus/{}", dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("qoi".as_ref()) {
+                continue;
+            }
+
+            let data = std::fs::read(&path).unwrap();
+            check(&data);
+
+            // Large images are slow to decode in debug builds, so only mutate their start.
+            let data = &data[..usize::min(data.len(), 4096)];
+            for _ in 0..20 {
+                let mut data = data.to_vec();
+                mutate(&mut data, &mut rng);
+                check(&data);
+            }
+        }
+    }
+}
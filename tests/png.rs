@@ -0,0 +1,78 @@
+#![cfg(feature = "png")]
+
+use image::codecs::png::PngEncoder;
+use image::ImageEncoder;
+
+use qoiparser::interop::decode_to_png;
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{
+    pixels_to_bytes, pixels_to_rgb_bytes, Channels, Colorspace, Decoder, Encoder, Header,
+};
+
+fn color_type(channels: Channels) -> image::ColorType {
+    match channels {
+        Channels::RGB => image::ColorType::Rgb8,
+        Channels::RGBA => image::ColorType::Rgba8,
+    }
+}
+
+/// Converts `qoi` to a PNG the buffered way: decoding the whole image, then encoding it in one go.
+fn buffered_png(qoi: &[u8]) -> Vec<u8> {
+    let (header, img) = Decoder::new().decode_slice(qoi).unwrap();
+    let buf = match header.channels {
+        Channels::RGB => pixels_to_rgb_bytes(&img),
+        Channels::RGBA => pixels_to_bytes(&img),
+    };
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(
+            &buf,
+            header.width,
+            header.height,
+            color_type(header.channels),
+        )
+        .unwrap();
+    png
+}
+
+/// Checks that streaming `qoi` to a PNG gives the same image as the buffered conversion.
+fn assert_same_png(qoi: &[u8]) {
+    let mut png = Vec::new();
+    let header = decode_to_png(qoi, &mut png).unwrap();
+
+    let streamed = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap();
+    let buffered =
+        image::load_from_memory_with_format(&buffered_png(qoi), image::ImageFormat::Png).unwrap();
+
+    assert_eq!(streamed.color(), color_type(header.channels));
+    assert_eq!(streamed.color(), buffered.color());
+    assert_eq!(streamed.as_bytes(), buffered.as_bytes());
+}
+
+#[test]
+fn png_dice() {
+    assert_same_png(&std::fs::read("tests/dice.qoi").unwrap());
+}
+
+#[test]
+fn png_generated() {
+    for (pattern, channels) in [
+        (Pattern::Gradient, Channels::RGB),
+        (Pattern::Noise, Channels::RGB),
+        (Pattern::Mixed, Channels::RGBA),
+    ] {
+        let img = testgen::generate(pattern, 123, 45, 3);
+        let header = Header::new(123, 45, channels, Colorspace::sRGB);
+        let mut qoi = Vec::new();
+        Encoder::new().encode(&header, &img, &mut qoi).unwrap();
+        assert_same_png(&qoi);
+    }
+}
+
+#[test]
+fn png_truncated() {
+    let qoi = std::fs::read("tests/dice.qoi").unwrap();
+    let mut png = Vec::new();
+    assert!(decode_to_png(&qoi[..qoi.len() - 4], &mut png).is_err());
+}
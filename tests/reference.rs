@@ -0,0 +1,47 @@
+//! Checks both decoders against the `image` crate's QOI decoder, as a known-good reader.
+
+mod common;
+
+use common::{decode_stream, first_mismatch};
+use qoiparser::{Decoder, Pixel};
+use std::fs::File;
+
+/// `tests/dice.qoi` as decoded by the `image` crate.
+fn reference_dice() -> Vec<Pixel> {
+    let img = image::io::Reader::open("tests/dice.qoi")
+        .unwrap()
+        .decode()
+        .unwrap()
+        .into_rgba8();
+    img.pixels()
+        .map(|&image::Rgba([r, g, b, a])| Pixel::new(r, g, b, a))
+        .collect()
+}
+
+/// Fails with the first pixel that differs, rather than printing both images.
+fn assert_same(name: &str, expected: &[Pixel], got: &[Pixel]) {
+    assert_eq!(
+        expected.len(),
+        got.len(),
+        "{}: wrong number of pixels",
+        name
+    );
+    if let Some((i, e, g)) = first_mismatch(expected, got) {
+        panic!("{}: pixel {} differs: image {:?}, {:?}", name, i, e, g);
+    }
+}
+
+#[test]
+fn decoder_matches_image() {
+    let (_, img) = Decoder::new()
+        .decode(&mut File::open("tests/dice.qoi").unwrap())
+        .unwrap();
+    assert_same("Decoder", &reference_dice(), &img);
+}
+
+#[test]
+fn stream_decoder_matches_image() {
+    let data = std::fs::read("tests/dice.qoi").unwrap();
+    let img = decode_stream(&data).unwrap();
+    assert_same("StreamDecoder", &reference_dice(), &img);
+}
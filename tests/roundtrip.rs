@@ -0,0 +1,41 @@
+mod common;
+
+use common::assert_roundtrip;
+use qoiparser::testgen::{self, Pattern};
+use qoiparser::{Channels, Colorspace, Decoder, Pixel};
+
+#[test]
+fn roundtrip_gradient() {
+    let img = testgen::gradient(300, 200);
+    assert_roundtrip(&img, 300, 200, Channels::RGB, Colorspace::sRGB);
+}
+
+#[test]
+fn roundtrip_solid() {
+    // 1000 pixels is many maximum length runs back to back.
+    let img = testgen::solid(40, 25, Pixel::new(12, 34, 56, 255));
+    assert_roundtrip(&img, 40, 25, Channels::RGBA, Colorspace::Linear);
+}
+
+#[test]
+fn roundtrip_noise() {
+    let img = testgen::noise(64, 64, 7);
+    assert_roundtrip(&img, 64, 64, Channels::RGB, Colorspace::sRGB);
+
+    // Noise is opaque, so mix in the alpha changes as well.
+    let img = testgen::generate(Pattern::Mixed, 64, 64, 7);
+    assert_roundtrip(&img, 64, 64, Channels::RGBA, Colorspace::sRGB);
+}
+
+#[test]
+fn roundtrip_dice() {
+    let data = std::fs::read("tests/dice.qoi").unwrap();
+    let (header, img) = Decoder::new().decode_slice(&data).unwrap();
+    assert_roundtrip(
+        &img,
+        header.width,
+        header.height,
+        header.channels,
+        header.colorspace,
+    );
+}